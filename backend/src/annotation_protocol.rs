@@ -0,0 +1,755 @@
+use crate::config::Config;
+use crate::models::{Comment, User};
+use crate::services::annotations::{
+    annotation_iri, annotation_json_ld, container_iri, parse_annotation, AnnotationInput, AnnotationTarget, ANNOTATION_CONTEXT,
+};
+use crate::services::api_keys::{SCOPE_COMMENTS_READ, SCOPE_COMMENTS_WRITE};
+use crate::services::comments::{CommentError, CommentService, NewComment};
+use crate::services::url_aliases::UrlAliasService;
+use crate::utils::normalize_url;
+use actix_web::error::{ErrorBadRequest, InternalError};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// W3C Web Annotation Protocol (https://www.w3.org/TR/annotation-protocol/).
+// The comments on each page form an LDP basic container at
+// /annotations/pages/{url_hash}/, listed in pages of PAGE_SIZE; each comment
+// is an annotation at /annotations/{id}. Requests authenticate with the same
+// bearer tokens and API key scopes as the GraphQL API.
+
+const ANNOTATION_MEDIA_TYPE: &str = r#"application/ld+json; profile="http://www.w3.org/ns/anno.jsonld""#;
+const LDP_CONTEXT: &str = "http://www.w3.org/ns/ldp.jsonld";
+const PREFER_MINIMAL_CONTAINER: &str = "http://www.w3.org/ns/ldp#PreferMinimalContainer";
+const PREFER_CONTAINED_IRIS: &str = "http://www.w3.org/ns/oa#PreferContainedIRIs";
+const CONTAINER_LINKS: &str = concat!(
+    r#"<http://www.w3.org/ns/ldp#BasicContainer>; rel="type", "#,
+    r#"<http://www.w3.org/TR/annotation-protocol/>; rel="http://www.w3.org/ns/ldp#constrainedBy""#,
+);
+const ANNOTATION_LINKS: &str = r#"<http://www.w3.org/ns/ldp#Resource>; rel="type""#;
+const CONTAINER_ALLOW: &str = "GET, HEAD, OPTIONS, POST";
+const ANNOTATION_ALLOW: &str = "GET, HEAD, OPTIONS, PUT, DELETE";
+
+/// Annotations per container page
+const PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct ContainerLookupQuery {
+    url: String,
+}
+
+#[derive(Deserialize)]
+pub struct ContainerPageQuery {
+    page: Option<i64>,
+    /// 1 to list annotation IRIs instead of full annotations
+    iris: Option<u8>,
+}
+
+/// Redirects to the container for a page's URL, so clients don't have to
+/// compute url_hash
+pub async fn find_container(query: web::Query<ContainerLookupQuery>, config: web::Data<Config>) -> HttpResponse {
+    match normalize_url(&query.url) {
+        Ok((_, url_hash)) => HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, container_iri(&config.public_base_url, &url_hash)))
+            .finish(),
+        Err(e) => HttpResponse::BadRequest().body(format!("Invalid URL: {}", e)),
+    }
+}
+
+/// The container for a page, or one page of it with `?page=`. The
+/// container's first page is embedded unless the client prefers a minimal
+/// container, and lists IRIs instead of annotations if it prefers those.
+/// Containers of URLs merged into another page's thread redirect to that
+/// page's container.
+pub async fn get_container(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<ContainerPageQuery>,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let url_hash = path.into_inner();
+    if !is_url_hash(&url_hash) {
+        return HttpResponse::NotFound().finish();
+    }
+    let viewer_id = match viewer(&req, &config, &pool, SCOPE_COMMENTS_READ).await {
+        Ok(viewer_id) => viewer_id,
+        Err(response) => return response,
+    };
+
+    let aliases = UrlAliasService::new(pool.get_ref().clone());
+    match aliases.resolve(&url_hash).await {
+        Ok(canonical_hash) if canonical_hash != url_hash => {
+            let mut location = container_iri(&config.public_base_url, &canonical_hash);
+            if !req.query_string().is_empty() {
+                location = format!("{}?{}", location, req.query_string());
+            }
+            // Temporary, as a moderator may split the alias off again
+            return HttpResponse::TemporaryRedirect().insert_header((header::LOCATION, location)).finish();
+        }
+        Ok(_) => {}
+        Err(e) => return internal_error("URL alias lookup", e),
+    }
+
+    let container = Container {
+        iri: container_iri(&config.public_base_url, &url_hash),
+        url_hashes: match aliases.thread_hashes(&url_hash).await {
+            Ok(url_hashes) => url_hashes,
+            Err(e) => return internal_error("URL alias lookup", e),
+        },
+        viewer_id,
+    };
+    let total = match container.total(&pool).await {
+        Ok(total) => total,
+        Err(e) => return internal_error("Annotation count", e),
+    };
+    let last_page = (total - 1).max(0) / PAGE_SIZE;
+
+    if let Some(page) = query.page {
+        if !(0..=last_page).contains(&page) {
+            return HttpResponse::NotFound().finish();
+        }
+        return match container.page(&pool, &config, page, query.iris == Some(1), total).await {
+            Ok(body) => json_ld(&req, HttpResponse::Ok(), &body),
+            Err(e) => internal_error("Annotation page", e),
+        };
+    }
+
+    let iris = prefers(&req, PREFER_CONTAINED_IRIS);
+    let mut body = json!({
+        "@context": [ANNOTATION_CONTEXT, LDP_CONTEXT],
+        "id": container.iri,
+        "type": ["BasicContainer", "AnnotationCollection"],
+        "total": total,
+    });
+    if total > 0 {
+        body["first"] = if prefers(&req, PREFER_MINIMAL_CONTAINER) {
+            json!(container.page_iri(0, iris))
+        } else {
+            match container.page(&pool, &config, 0, iris, total).await {
+                Ok(mut page) => {
+                    page.as_object_mut().map(|page| page.remove("@context"));
+                    page
+                }
+                Err(e) => return internal_error("Annotation page", e),
+            }
+        };
+        body["last"] = json!(container.page_iri(last_page, iris));
+    }
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((header::LINK, CONTAINER_LINKS))
+        .insert_header((header::ALLOW, CONTAINER_ALLOW))
+        .insert_header(("Accept-Post", ANNOTATION_MEDIA_TYPE));
+    if req.headers().contains_key("prefer") {
+        response.insert_header(("Preference-Applied", "return=representation"));
+    }
+    json_ld(&req, response, &body)
+}
+
+/// Creates a comment from an annotation on the container's page, or a reply
+/// to an annotation in the container
+pub async fn create_annotation(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let url_hash = path.into_inner();
+    if !is_url_hash(&url_hash) {
+        return HttpResponse::NotFound().finish();
+    }
+    let user_id = match signed_in(&req, &config, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let annotation = match annotation_body(&req, &body, &config) {
+        Ok(annotation) => annotation,
+        Err(e) => return e.error_response(),
+    };
+    let url_hashes = match UrlAliasService::new(pool.get_ref().clone()).thread_hashes(&url_hash).await {
+        Ok(url_hashes) => url_hashes,
+        Err(e) => return internal_error("URL alias lookup", e),
+    };
+
+    let (url, parent_id, anchor) = match annotation.target {
+        AnnotationTarget::Page { source, anchor } => {
+            let on_page = normalize_url(&source).is_ok_and(|(_, source_hash)| url_hashes.contains(&source_hash));
+            if !on_page {
+                return HttpResponse::BadRequest().body("The annotation's target is not this container's page");
+            }
+            (source, None, anchor)
+        }
+        AnnotationTarget::Reply(parent_id) => {
+            let parent_url = sqlx::query_scalar::<_, String>("SELECT url FROM comments WHERE id = $1 AND url_hash = ANY($2)")
+                .bind(parent_id)
+                .bind(&url_hashes)
+                .fetch_optional(pool.get_ref())
+                .await;
+            match parent_url {
+                Ok(Some(parent_url)) => (parent_url, Some(parent_id), None),
+                Ok(None) => return HttpResponse::BadRequest().body("The annotation replies to one that is not in this container"),
+                Err(e) => return internal_error("Parent lookup", e),
+            }
+        }
+    };
+
+    let new_comment = NewComment {
+        content: annotation.content,
+        url,
+        parent_id,
+        language: annotation.language,
+        anchor,
+        page: None,
+    };
+    let comment = match CommentService::new(pool.get_ref().clone()).create(user_id, new_comment).await {
+        Ok(comment) => comment,
+        Err(e) => return comment_error(e),
+    };
+
+    info!("Annotation {} created by user {} in container {}", comment.id, user_id, url_hash);
+
+    match render(&pool, &config, &comment).await {
+        Ok(body) => {
+            let mut response = HttpResponse::Created();
+            response
+                .insert_header((header::LOCATION, annotation_iri(&config.public_base_url, comment.id)))
+                .insert_header((header::LINK, ANNOTATION_LINKS))
+                .insert_header((header::ALLOW, ANNOTATION_ALLOW));
+            json_ld(&req, response, &body)
+        }
+        Err(e) => internal_error("Annotation rendering", e),
+    }
+}
+
+pub async fn get_annotation(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let viewer_id = match viewer(&req, &config, &pool, SCOPE_COMMENTS_READ).await {
+        Ok(viewer_id) => viewer_id,
+        Err(response) => return response,
+    };
+    let body = match find(&pool, path.into_inner(), viewer_id).await {
+        Ok(Some(comment)) => render(&pool, &config, &comment).await,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => return internal_error("Annotation lookup", e),
+    };
+
+    match body {
+        Ok(body) => {
+            let mut response = HttpResponse::Ok();
+            response
+                .insert_header((header::LINK, ANNOTATION_LINKS))
+                .insert_header((header::ALLOW, ANNOTATION_ALLOW));
+            json_ld(&req, response, &body)
+        }
+        Err(e) => internal_error("Annotation rendering", e),
+    }
+}
+
+/// Replaces an annotation's body text. Requires If-Match, so edits based on
+/// a stale copy are refused; the target can't be changed.
+pub async fn update_annotation(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = match signed_in(&req, &config, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let comment = match own_annotation(&req, &pool, &config, path.into_inner(), user_id, true).await {
+        Ok(comment) => comment,
+        Err(response) => return response,
+    };
+    let annotation = match annotation_body(&req, &body, &config) {
+        Ok(annotation) => annotation,
+        Err(e) => return e.error_response(),
+    };
+
+    let same_target = match annotation.target {
+        AnnotationTarget::Page { source, anchor } => {
+            comment.parent_id.is_none()
+                && normalize_url(&source).is_ok_and(|(_, url_hash)| url_hash == comment.url_hash)
+                && anchor == comment.anchor.as_deref().cloned()
+        }
+        AnnotationTarget::Reply(parent_id) => comment.parent_id == Some(parent_id),
+    };
+    if !same_target {
+        return HttpResponse::BadRequest().body("Only the body of an annotation can be changed");
+    }
+
+    // Only apply the edit to the version If-Match was checked against
+    let updated = CommentService::new(pool.get_ref().clone())
+        .update(user_id, comment.id, &annotation.content, Some(comment.updated_at))
+        .await;
+    let updated = match updated {
+        Ok(updated) => updated,
+        Err(e) => return comment_error(e),
+    };
+
+    info!("Annotation {} updated by user {}", updated.id, user_id);
+
+    match render(&pool, &config, &updated).await {
+        Ok(body) => {
+            let mut response = HttpResponse::Ok();
+            response
+                .insert_header((header::LINK, ANNOTATION_LINKS))
+                .insert_header((header::ALLOW, ANNOTATION_ALLOW));
+            json_ld(&req, response, &body)
+        }
+        Err(e) => internal_error("Annotation rendering", e),
+    }
+}
+
+/// Deletes an annotation, and with it any replies. If-Match is honoured but
+/// not required.
+pub async fn delete_annotation(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = match signed_in(&req, &config, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    let comment = match own_annotation(&req, &pool, &config, path.into_inner(), user_id, false).await {
+        Ok(comment) => comment,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = CommentService::new(pool.get_ref().clone()).delete(user_id, comment.id).await {
+        return comment_error(e);
+    }
+
+    info!("Annotation {} deleted by user {}", comment.id, user_id);
+
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn container_options() -> HttpResponse {
+    HttpResponse::NoContent()
+        .insert_header((header::LINK, CONTAINER_LINKS))
+        .insert_header((header::ALLOW, CONTAINER_ALLOW))
+        .insert_header(("Accept-Post", ANNOTATION_MEDIA_TYPE))
+        .finish()
+}
+
+pub async fn annotation_options() -> HttpResponse {
+    HttpResponse::NoContent()
+        .insert_header((header::LINK, ANNOTATION_LINKS))
+        .insert_header((header::ALLOW, ANNOTATION_ALLOW))
+        .finish()
+}
+
+/// The comments on a page, including URLs merged into its thread, as the
+/// viewer may see them
+struct Container {
+    iri: String,
+    url_hashes: Vec<String>,
+    viewer_id: Option<Uuid>,
+}
+
+impl Container {
+    fn page_iri(&self, page: i64, iris: bool) -> String {
+        if iris {
+            format!("{}?iris=1&page={}", self.iri, page)
+        } else {
+            format!("{}?page={}", self.iri, page)
+        }
+    }
+
+    async fn total(&self, pool: &PgPool) -> sqlx::Result<i64> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM comments c WHERE c.url_hash = ANY($1) AND NOT is_blocked_between($2, c.user_id)"
+        )
+        .bind(&self.url_hashes)
+        .bind(self.viewer_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// An AnnotationPage of the container, oldest annotations first
+    async fn page(&self, pool: &PgPool, config: &Config, page: i64, iris: bool, total: i64) -> anyhow::Result<Value> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+            SELECT c.* FROM comments c
+            WHERE c.url_hash = ANY($1) AND NOT is_blocked_between($2, c.user_id)
+            ORDER BY c.created_at ASC, c.id ASC
+            LIMIT $3 OFFSET $4
+            "#
+        )
+        .bind(&self.url_hashes)
+        .bind(self.viewer_id)
+        .bind(PAGE_SIZE)
+        .bind(page * PAGE_SIZE)
+        .fetch_all(pool)
+        .await?;
+
+        let items: Vec<Value> = if iris {
+            comments.iter().map(|comment| json!(annotation_iri(&config.public_base_url, comment.id))).collect()
+        } else {
+            let authors = authors(pool, &comments).await?;
+            comments
+                .iter()
+                .map(|comment| annotation_json_ld(comment, authors.get(&comment.user_id), &config.public_base_url))
+                .collect()
+        };
+
+        let mut body = json!({
+            "@context": ANNOTATION_CONTEXT,
+            "id": self.page_iri(page, iris),
+            "type": "AnnotationPage",
+            "partOf": { "id": self.iri, "total": total },
+            "startIndex": page * PAGE_SIZE,
+            "items": items,
+        });
+        if page > 0 {
+            body["prev"] = json!(self.page_iri(page - 1, iris));
+        }
+        if (page + 1) * PAGE_SIZE < total {
+            body["next"] = json!(self.page_iri(page + 1, iris));
+        }
+
+        Ok(body)
+    }
+}
+
+async fn authors(pool: &PgPool, comments: &[Comment]) -> sqlx::Result<HashMap<Uuid, User>> {
+    let ids: Vec<Uuid> = comments.iter().map(|comment| comment.user_id).collect();
+    let users = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ANY($1)")
+        .bind(&ids)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(users.into_iter().map(|user| (user.id, user)).collect())
+}
+
+/// A comment the viewer may see
+async fn find(pool: &PgPool, id: Uuid, viewer_id: Option<Uuid>) -> sqlx::Result<Option<Comment>> {
+    sqlx::query_as::<_, Comment>("SELECT * FROM comments WHERE id = $1 AND NOT is_blocked_between($2, user_id)")
+        .bind(id)
+        .bind(viewer_id)
+        .fetch_optional(pool)
+        .await
+}
+
+async fn render(pool: &PgPool, config: &Config, comment: &Comment) -> sqlx::Result<Value> {
+    let authors = authors(pool, std::slice::from_ref(comment)).await?;
+    Ok(annotation_json_ld(comment, authors.get(&comment.user_id), &config.public_base_url))
+}
+
+/// One of the user's own comments, checked against the request's If-Match
+/// header, which may be required
+async fn own_annotation(
+    req: &HttpRequest,
+    pool: &PgPool,
+    config: &Config,
+    id: Uuid,
+    user_id: Uuid,
+    require_if_match: bool,
+) -> Result<Comment, HttpResponse> {
+    let comment = match find(pool, id, Some(user_id)).await {
+        Ok(Some(comment)) => comment,
+        Ok(None) => return Err(HttpResponse::NotFound().finish()),
+        Err(e) => return Err(internal_error("Annotation lookup", e)),
+    };
+    if comment.user_id != user_id {
+        return Err(HttpResponse::Forbidden().body("You can only change your own annotations"));
+    }
+
+    let Some(if_match) = req.headers().get(header::IF_MATCH).and_then(|value| value.to_str().ok()) else {
+        return if require_if_match {
+            Err(HttpResponse::build(actix_web::http::StatusCode::PRECONDITION_REQUIRED).body("If-Match is required"))
+        } else {
+            Ok(comment)
+        };
+    };
+    let current = match render(pool, config, &comment).await {
+        Ok(body) => etag(&body.to_string()),
+        Err(e) => return Err(internal_error("Annotation rendering", e)),
+    };
+    if !etag_matches(if_match, &current, false) {
+        return Err(HttpResponse::PreconditionFailed().body("The annotation has changed"));
+    }
+
+    Ok(comment)
+}
+
+/// Parses the annotation in a POST or PUT body
+fn annotation_body(req: &HttpRequest, body: &[u8], config: &Config) -> actix_web::Result<AnnotationInput> {
+    let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if !content_type.starts_with("application/ld+json") && !content_type.starts_with("application/json") {
+        let response = HttpResponse::UnsupportedMediaType()
+            .insert_header(("Accept-Post", ANNOTATION_MEDIA_TYPE))
+            .body("Annotations must be sent as JSON-LD");
+        return Err(InternalError::from_response("unsupported media type", response).into());
+    }
+
+    let annotation = serde_json::from_slice::<Value>(body)
+        .map_err(|e| ErrorBadRequest(format!("Invalid JSON: {}", e)))?;
+    parse_annotation(&annotation, &config.public_base_url)
+        .map_err(|e| ErrorBadRequest(format!("Invalid annotation: {:#}", e)))
+}
+
+/// The signed-in user, if any, after checking an API key carries `scope`
+async fn viewer(req: &HttpRequest, config: &Config, pool: &PgPool, scope: &str) -> Result<Option<Uuid>, HttpResponse> {
+    match crate::authenticate(req, config, pool).await {
+        Some((_, Some(api_key))) if !api_key.has_scope(scope) => {
+            Err(HttpResponse::Forbidden().body(format!("API key is missing the required scope: {}", scope)))
+        }
+        Some((user_id, _)) => Ok(Some(user_id)),
+        None => Ok(None),
+    }
+}
+
+/// The signed-in user allowed to write comments
+async fn signed_in(req: &HttpRequest, config: &Config, pool: &PgPool) -> Result<Uuid, HttpResponse> {
+    viewer(req, config, pool, SCOPE_COMMENTS_WRITE).await?.ok_or_else(|| {
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .body("Authentication required")
+    })
+}
+
+/// Sends a JSON-LD body with an ETag, or 304 if the client's copy is current
+fn json_ld(req: &HttpRequest, mut response: actix_web::HttpResponseBuilder, body: &Value) -> HttpResponse {
+    let body = body.to_string();
+    let etag = etag(&body);
+    let vary = (header::VARY, "Prefer, Authorization");
+
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|if_none_match| etag_matches(if_none_match, &etag, true)) {
+        return HttpResponse::NotModified().insert_header((header::ETAG, etag)).insert_header(vary).finish();
+    }
+
+    response
+        .content_type(ANNOTATION_MEDIA_TYPE)
+        .insert_header((header::ETAG, etag))
+        .insert_header(vary)
+        .body(body)
+}
+
+fn etag(body: &str) -> String {
+    format!("\"{}\"", &hex::encode(Sha256::digest(body.as_bytes()))[..32])
+}
+
+/// Whether an If-Match or If-None-Match header names `etag`. If-None-Match
+/// uses weak comparison; If-Match uses strong comparison, where weak
+/// validators never match (RFC 9110, section 13.1.1).
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.trim() == "*"
+        || header.split(',').map(str::trim).any(|candidate| {
+            let candidate = if weak { candidate.strip_prefix("W/").unwrap_or(candidate) } else { candidate };
+            candidate == etag
+        })
+}
+
+/// Whether the client's Prefer header asks to include `iri`
+fn prefers(req: &HttpRequest, iri: &str) -> bool {
+    req.headers()
+        .get_all("prefer")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|param| param.trim().strip_prefix("include="))
+        .any(|values| values.trim_matches('"').split_whitespace().any(|value| value == iri))
+}
+
+fn is_url_hash(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+fn comment_error(e: CommentError) -> HttpResponse {
+    match e {
+        CommentError::Invalid(message) => HttpResponse::BadRequest().body(message),
+        CommentError::Forbidden(message) => HttpResponse::Forbidden().body(message),
+        CommentError::NotFound(message) => HttpResponse::NotFound().body(message),
+        CommentError::RateLimited => HttpResponse::TooManyRequests().body(e.to_string()),
+        CommentError::Stale => HttpResponse::PreconditionFailed().body("The annotation has changed"),
+        CommentError::Internal(e) => internal_error("Comment", e),
+    }
+}
+
+fn internal_error(what: &str, e: impl std::fmt::Display) -> HttpResponse {
+    warn!("{} failed: {}", what, e);
+    HttpResponse::InternalServerError().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::services::auth::AuthService;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = r#""abc""#;
+        for weak in [true, false] {
+            assert!(etag_matches(r#""abc""#, etag, weak));
+            assert!(etag_matches(r#""xyz", "abc""#, etag, weak));
+            assert!(etag_matches("*", etag, weak));
+            assert!(!etag_matches(r#""abcd""#, etag, weak));
+            assert!(!etag_matches("abc", etag, weak));
+        }
+        assert!(etag_matches(r#""xyz", W/"abc""#, etag, true));
+        assert!(!etag_matches(r#""xyz", W/"abc""#, etag, false));
+    }
+
+    #[test]
+    fn test_prefers() {
+        let req = actix_web::test::TestRequest::default()
+            .insert_header((
+                "Prefer",
+                format!(r#"return=representation;include="{} {}""#, PREFER_MINIMAL_CONTAINER, PREFER_CONTAINED_IRIS),
+            ))
+            .to_http_request();
+        assert!(prefers(&req, PREFER_MINIMAL_CONTAINER));
+        assert!(prefers(&req, PREFER_CONTAINED_IRIS));
+        assert!(!prefers(&actix_web::test::TestRequest::default().to_http_request(), PREFER_CONTAINED_IRIS));
+    }
+
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.service(
+            web::resource("/annotations/pages/{url_hash}/")
+                .route(web::get().to(get_container))
+                .route(web::post().to(create_annotation)),
+        )
+        .service(
+            web::resource("/annotations/{id}")
+                .route(web::get().to(get_annotation))
+                .route(web::put().to(update_annotation)),
+        );
+    }
+
+    async fn bearer(pool: &PgPool, config: &Config, user_id: Uuid) -> String {
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        format!("Bearer {}", AuthService::new(config.jwt_secret.clone()).generate_jwt_token(&user).unwrap())
+    }
+
+    fn annotation(content: &str, source: &str) -> String {
+        json!({ "@context": ANNOTATION_CONTEXT, "type": "Annotation", "bodyValue": content, "target": source }).to_string()
+    }
+
+    #[actix_web::test]
+    async fn test_edits_need_the_current_etag() {
+        let Some(pool) = testing::pool().await else { return };
+        let config = Config::from_env().unwrap();
+        let user_id = testing::create_user(&pool).await;
+        let url = testing::unique_url();
+        let id = testing::create_comment(&pool, user_id, &url).await;
+        let auth = bearer(&pool, &config, user_id).await;
+        let app = init_service(
+            App::new().app_data(web::Data::new(config.clone())).app_data(web::Data::new(pool.clone())).configure(routes),
+        )
+        .await;
+        let iri = format!("/annotations/{}", id);
+        let put = |if_match: Option<&str>, content: &str| {
+            let mut req = TestRequest::put()
+                .uri(&iri)
+                .insert_header((header::AUTHORIZATION, auth.as_str()))
+                .insert_header((header::CONTENT_TYPE, "application/ld+json"))
+                .set_payload(annotation(content, &url));
+            if let Some(if_match) = if_match {
+                req = req.insert_header((header::IF_MATCH, if_match));
+            }
+            req.to_request()
+        };
+
+        let response = call_service(&app, TestRequest::get().uri(&iri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        let response = call_service(&app, TestRequest::get().uri(&iri).insert_header((header::IF_NONE_MATCH, etag.as_str())).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        assert_eq!(call_service(&app, put(None, "Edited")).await.status(), StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(call_service(&app, put(Some(r#""stale""#), "Edited")).await.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(call_service(&app, put(Some(&format!("W/{}", etag)), "Edited")).await.status(), StatusCode::PRECONDITION_FAILED);
+        let response = call_service(&app, put(Some(&etag), "Edited")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+        assert_eq!(call_service(&app, put(Some(&etag), "Edited again")).await.status(), StatusCode::PRECONDITION_FAILED);
+
+        // An edit that lands between the If-Match check and the update
+        let checked = find(&pool, id, Some(user_id)).await.unwrap().unwrap().updated_at;
+        let service = CommentService::new(pool.clone());
+        service.update(user_id, id, "Concurrent edit", None).await.unwrap();
+        assert!(matches!(service.update(user_id, id, "Edited again", Some(checked)).await, Err(CommentError::Stale)));
+        assert_eq!(find(&pool, id, None).await.unwrap().unwrap().content, "Concurrent edit");
+    }
+
+    #[actix_web::test]
+    async fn test_annotations_stay_in_their_container() {
+        let Some(pool) = testing::pool().await else { return };
+        let config = Config::from_env().unwrap();
+        let user_id = testing::create_user(&pool).await;
+        let auth = bearer(&pool, &config, user_id).await;
+        let (url, other_url) = (testing::unique_url(), testing::unique_url());
+        let (_, url_hash) = normalize_url(&url).unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(config.clone())).app_data(web::Data::new(pool.clone())).configure(routes),
+        )
+        .await;
+        let post = |source: &str| {
+            TestRequest::post()
+                .uri(&format!("/annotations/pages/{}/", url_hash))
+                .insert_header((header::AUTHORIZATION, auth.as_str()))
+                .insert_header((header::CONTENT_TYPE, "application/ld+json"))
+                .set_payload(annotation("On this page", source))
+                .to_request()
+        };
+
+        let response = call_service(&app, post(&other_url)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(read_body(response).await, "The annotation's target is not this container's page");
+        assert_eq!(call_service(&app, post(&url)).await.status(), StatusCode::CREATED);
+    }
+
+    #[actix_web::test]
+    async fn test_alias_containers_redirect_to_the_canonical_one() {
+        let Some(pool) = testing::pool().await else { return };
+        let config = Config::from_env().unwrap();
+        let moderator_id = testing::create_user(&pool).await;
+        let page = testing::unique_url();
+        let canonical = normalize_url(&page).unwrap();
+        let amp = normalize_url(&format!("{}/amp", page)).unwrap();
+        UrlAliasService::new(pool.clone())
+            .merge(moderator_id, (&amp.0, &amp.1), (&canonical.0, &canonical.1))
+            .await
+            .unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(config.clone())).app_data(web::Data::new(pool.clone())).configure(routes),
+        )
+        .await;
+
+        let request = TestRequest::get().uri(&format!("/annotations/pages/{}/?page=0", amp.1)).to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap().to_str().unwrap(),
+            format!("{}?page=0", container_iri(&config.public_base_url, &canonical.1)),
+        );
+        let request = TestRequest::get().uri(&format!("/annotations/pages/{}/", canonical.1)).to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+    }
+}
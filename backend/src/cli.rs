@@ -0,0 +1,110 @@
+use crate::config::Config;
+use crate::services::data_export::DataExportService;
+use crate::services::renormalize::{RenormalizeBatch, RenormalizeService};
+use crate::services::reputation::ReputationService;
+use crate::url_rules::{self, UrlRules};
+use crate::utils::create_url_hash;
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Comments renormalized per transaction
+const RENORMALIZE_BATCH_SIZE: i64 = 1000;
+
+const USAGE: &str = "\
+Usage: votp-backend [COMMAND]
+
+Runs the API server when no command is given.
+
+Admin commands:
+  explain-url <url> [rules.toml]       Show how a URL is canonicalized and which rules fired
+  export-user <user-id> [output.zip]   Write a user's data export archive
+  recompute-reputation                 Recompute every user's reputation and trust level
+  renormalize                          Recompute stored URL hashes with the current URL rules
+  set-moderator <user-id> <on|off>     Grant or revoke moderator access
+";
+
+/// Runs an admin command from the command line
+pub async fn run(args: &[String], pool: &PgPool, config: &Config) -> Result<()> {
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["explain-url", url, rest @ ..] if rest.len() <= 1 => {
+            let rules = match rest.first() {
+                Some(path) => std::sync::Arc::new(UrlRules::load(path.as_ref())?),
+                None => url_rules::current(),
+            };
+            let (canonical, trace) = rules.explain(url)?;
+
+            println!("{}", canonical);
+            println!("hash {}", create_url_hash(canonical.as_str()));
+            for line in trace {
+                println!("  {}", line);
+            }
+            Ok(())
+        }
+        ["export-user", user_id, rest @ ..] if rest.len() <= 1 => {
+            let user_id = Uuid::parse_str(user_id)
+                .map_err(|e| anyhow::anyhow!("Invalid user id: {}", e))?;
+            let output = rest.first().map(|path| path.to_string())
+                .unwrap_or_else(|| format!("votp-export-{}.zip", user_id));
+
+            let export_service = DataExportService::new(pool.clone(), config.export_dir.clone().into(), config.jwt_secret.clone());
+            let archive = export_service.build_archive(user_id).await?;
+            tokio::fs::write(&output, archive).await?;
+
+            println!("Wrote data export for {} to {}", user_id, output);
+            Ok(())
+        }
+        ["recompute-reputation"] => {
+            let updated = ReputationService::new(pool.clone()).recompute_all().await?;
+            println!("Updated reputation for {} users", updated);
+            Ok(())
+        }
+        ["renormalize"] => {
+            let rules = url_rules::current();
+            let service = RenormalizeService::new(pool.clone());
+            for (version, count) in service.status().await? {
+                println!("{:>10} comments at {}", count, version.as_deref().unwrap_or("no version"));
+            }
+            println!("Renormalizing to {}", rules.version());
+
+            let mut total = RenormalizeBatch::default();
+            loop {
+                let batch = service.comments_batch(&rules, total.last_id, RENORMALIZE_BATCH_SIZE).await?;
+                let Some(last_id) = batch.last_id else { break };
+                total.checked += batch.checked;
+                total.rehashed += batch.rehashed;
+                total.failed += batch.failed;
+                total.last_id = Some(last_id);
+                println!("  {} comments checked, {} rehashed", total.checked, total.rehashed);
+            }
+            let (kept, removed) = service.aliases(&rules).await?;
+
+            println!(
+                "Renormalized {} comments: {} rehashed, {} with URLs that no longer parse. {} URL aliases kept, {} removed.",
+                total.checked, total.rehashed, total.failed, kept, removed
+            );
+            Ok(())
+        }
+        ["set-moderator", user_id, setting @ ("on" | "off")] => {
+            let user_id = Uuid::parse_str(user_id)
+                .map_err(|e| anyhow::anyhow!("Invalid user id: {}", e))?;
+
+            let updated = sqlx::query("UPDATE users SET is_moderator = $1 WHERE id = $2")
+                .bind(*setting == "on")
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+            if updated.rows_affected() == 0 {
+                return Err(anyhow::anyhow!("User {} not found", user_id));
+            }
+
+            println!("Moderator access for {} turned {}", user_id, setting);
+            Ok(())
+        }
+        ["help"] | ["--help"] | ["-h"] => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(anyhow::anyhow!("Unknown command\n\n{}", USAGE)),
+    }
+}
@@ -8,6 +8,21 @@ pub struct Config {
     pub host: String,
     pub port: u16,
     pub smtp: SmtpConfig,
+    pub totp_issuer: String,
+    pub verification_code_expiry_minutes: i64,
+    pub magic_link_base_url: String,
+    pub oidc: OidcConfig,
+    pub account_deletion_grace_days: i64,
+    pub account_deletion_cancel_url: String,
+    pub public_base_url: String,
+    pub export_dir: String,
+    pub export_link_ttl_hours: i64,
+    pub handle_rename_cooldown_days: i64,
+    pub blob_store: BlobStoreConfig,
+    pub avatar_max_bytes: usize,
+    /// URL rules file to use instead of the bundled rules
+    pub url_rules_file: Option<String>,
+    pub url_rules_reload_secs: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +35,105 @@ pub struct SmtpConfig {
     pub use_ssl: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobStoreConfig {
+    /// "filesystem" (default) or "s3"
+    pub kind: String,
+    pub dir: String,
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Base URL blobs are publicly served from, e.g. a CDN in front of the bucket
+    pub public_url: Option<String>,
+}
+
+impl BlobStoreConfig {
+    fn from_env() -> Self {
+        let s3 = env::var("S3_BUCKET").ok().map(|bucket| S3Config {
+            endpoint: env::var("S3_ENDPOINT").unwrap_or_else(|_| "https://s3.amazonaws.com".to_string()),
+            bucket,
+            region: env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            access_key_id: env::var("S3_ACCESS_KEY_ID").unwrap_or_default(),
+            secret_access_key: env::var("S3_SECRET_ACCESS_KEY").unwrap_or_default(),
+            public_url: env::var("S3_PUBLIC_URL").ok().filter(|url| !url.is_empty()),
+        });
+
+        BlobStoreConfig {
+            kind: env::var("BLOB_STORE").unwrap_or_else(|_| "filesystem".to_string()),
+            dir: env::var("BLOB_DIR").unwrap_or_else(|_| "./blobs".to_string()),
+            s3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    pub redirect_uri: String,
+    pub providers: Vec<OidcProviderConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    /// "oidc" for standards-compliant issuers, "github" for GitHub's OAuth2 API
+    pub kind: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: String,
+}
+
+impl OidcConfig {
+    pub fn provider(&self, name: &str) -> Option<&OidcProviderConfig> {
+        self.providers.iter().find(|provider| provider.name == name)
+    }
+
+    fn from_env() -> Self {
+        let providers = env::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let var = |key: &str| env::var(format!("OIDC_{}_{}", name.to_uppercase(), key));
+                let kind = var("KIND").unwrap_or_else(|_| {
+                    if name == "github" { "github" } else { "oidc" }.to_string()
+                });
+                let issuer = var("ISSUER").unwrap_or_else(|_| match name.as_str() {
+                    "google" => "https://accounts.google.com".to_string(),
+                    "github" => "https://github.com".to_string(),
+                    _ => String::new(),
+                });
+                let scopes = var("SCOPES").unwrap_or_else(|_| {
+                    if kind == "github" { "read:user user:email" } else { "openid email profile" }.to_string()
+                });
+
+                OidcProviderConfig {
+                    client_id: var("CLIENT_ID").unwrap_or_default(),
+                    client_secret: var("CLIENT_SECRET").unwrap_or_default(),
+                    name,
+                    kind,
+                    issuer,
+                    scopes,
+                }
+            })
+            .collect();
+
+        OidcConfig {
+            redirect_uri: env::var("OIDC_REDIRECT_URI")
+                .unwrap_or_else(|_| "http://localhost:8000/auth/oidc/callback".to_string()),
+            providers,
+        }
+    }
+}
+
 impl Config {
     pub fn from_env() -> Result<Self, env::VarError> {
         Ok(Config {
@@ -46,6 +160,41 @@ impl Config {
                     .parse()
                     .unwrap_or(true),
             },
+            totp_issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| "VOTP".to_string()),
+            verification_code_expiry_minutes: env::var("VERIFICATION_CODE_EXPIRY_MINUTES")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()
+                .unwrap_or(10),
+            magic_link_base_url: env::var("MAGIC_LINK_BASE_URL")
+                .unwrap_or_else(|_| "http://localhost:8000/auth/magic-link".to_string()),
+            oidc: OidcConfig::from_env(),
+            account_deletion_grace_days: env::var("ACCOUNT_DELETION_GRACE_DAYS")
+                .unwrap_or_else(|_| "14".to_string())
+                .parse()
+                .unwrap_or(14),
+            account_deletion_cancel_url: env::var("ACCOUNT_DELETION_CANCEL_URL")
+                .unwrap_or_else(|_| "http://localhost:8000/account/cancel-deletion".to_string()),
+            public_base_url: env::var("PUBLIC_BASE_URL")
+                .unwrap_or_else(|_| "http://localhost:8000".to_string()),
+            export_dir: env::var("EXPORT_DIR").unwrap_or_else(|_| "./exports".to_string()),
+            export_link_ttl_hours: env::var("EXPORT_LINK_TTL_HOURS")
+                .unwrap_or_else(|_| "24".to_string())
+                .parse()
+                .unwrap_or(24),
+            handle_rename_cooldown_days: env::var("HANDLE_RENAME_COOLDOWN_DAYS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
+            blob_store: BlobStoreConfig::from_env(),
+            avatar_max_bytes: env::var("AVATAR_MAX_BYTES")
+                .unwrap_or_else(|_| "5242880".to_string())
+                .parse()
+                .unwrap_or(5 * 1024 * 1024),
+            url_rules_file: env::var("URL_RULES_FILE").ok().filter(|path| !path.is_empty()),
+            url_rules_reload_secs: env::var("URL_RULES_RELOAD_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .unwrap_or(30),
         })
     }
 }
\ No newline at end of file
@@ -1,3 +1,7 @@
+use crate::services::comment_counts::CommentCountService;
+use crate::services::domains::DomainService;
+use crate::services::handles::HandleService;
+use crate::services::search::search_config_function_sql;
 use sqlx::PgPool;
 use tracing::info;
 
@@ -15,7 +19,7 @@ pub async fn create_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
     Ok(pool)
 }
 
-pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
+pub async fn run_migrations(pool: &PgPool) -> anyhow::Result<()> {
     info!("Running database migrations...");
     
     // Create users table
@@ -58,6 +62,532 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
     .execute(pool)
     .await?;
 
+    // Passwordless-only accounts have no password hash
+    sqlx::query("ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL")
+        .execute(pool)
+        .await?;
+
+    // Emailed verification codes for signup and passwordless login
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS verification_codes (
+            email VARCHAR(255) PRIMARY KEY,
+            code VARCHAR(6) NOT NULL,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE verification_codes ADD COLUMN IF NOT EXISTS purpose VARCHAR(20) NOT NULL DEFAULT 'signup'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE verification_codes ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE verification_codes ADD COLUMN IF NOT EXISTS link_token_hash VARCHAR(64)")
+        .execute(pool)
+        .await?;
+
+    // Two-factor authentication (TOTP) columns
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_used_step BIGINT")
+        .execute(pool)
+        .await?;
+
+    // Single-use recovery codes, stored hashed
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            code_hash VARCHAR(64) NOT NULL,
+            used_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Pending second-factor challenges issued by login for TOTP-enrolled users
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS mfa_challenges (
+            token_hash VARCHAR(64) PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // External identities (OAuth2/OIDC) linked to local accounts
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS linked_identities (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            provider VARCHAR(50) NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            email VARCHAR(255),
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            UNIQUE (provider, subject),
+            UNIQUE (user_id, provider)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // In-flight authorization requests, keyed by the OAuth2 state parameter
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS oidc_auth_requests (
+            state VARCHAR(64) PRIMARY KEY,
+            provider VARCHAR(50) NOT NULL,
+            code_verifier VARCHAR(128) NOT NULL,
+            nonce VARCHAR(64) NOT NULL,
+            link_user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+            expires_at TIMESTAMPTZ NOT NULL,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Personal API keys for scripted access, stored hashed
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name VARCHAR(100) NOT NULL,
+            prefix VARCHAR(16) NOT NULL,
+            key_hash VARCHAR(64) UNIQUE NOT NULL,
+            scopes TEXT[] NOT NULL,
+            expires_at TIMESTAMPTZ,
+            last_used_at TIMESTAMPTZ,
+            revoked_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Account deletion requests and their grace period
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_requested_at TIMESTAMPTZ")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_scheduled_for TIMESTAMPTZ")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_delete_comments BOOLEAN NOT NULL DEFAULT FALSE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_cancel_token_hash VARCHAR(64)")
+        .execute(pool)
+        .await?;
+
+    // Audit trail for account lifecycle events; kept after the account is gone
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS account_audit_log (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL,
+            event VARCHAR(50) NOT NULL,
+            details JSONB NOT NULL DEFAULT '{}',
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Subject-access data exports
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS data_exports (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            status VARCHAR(20) NOT NULL DEFAULT 'pending',
+            created_at TIMESTAMPTZ DEFAULT NOW(),
+            completed_at TIMESTAMPTZ
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Email change awaiting confirmation of the new address
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS pending_email VARCHAR(255)")
+        .execute(pool)
+        .await?;
+
+    // Tokens issued before this moment are rejected (used to sign out other sessions)
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_valid_after TIMESTAMPTZ")
+        .execute(pool)
+        .await?;
+
+    // Unique public handles; the skeleton folds case and confusable characters
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS handle VARCHAR(30)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS handle_skeleton VARCHAR(120)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS handle_changed_at TIMESTAMPTZ")
+        .execute(pool)
+        .await?;
+
+    // Previous handles, kept so old links and mentions still resolve
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS handle_redirects (
+            handle VARCHAR(30) PRIMARY KEY,
+            skeleton VARCHAR(120) NOT NULL,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Blob store key prefix of the current avatar's variants
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_key VARCHAR(255)")
+        .execute(pool)
+        .await?;
+
+    // Who follows whom, for the cross-site feed
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS follows (
+            follower_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            followee_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (follower_id, followee_id),
+            CHECK (follower_id <> followee_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Blocks hide both users from each other; mutes collapse the muted author's comments
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_blocks (
+            blocker_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            blocked_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (blocker_id, blocked_id),
+            CHECK (blocker_id <> blocked_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS user_mutes (
+            muter_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            muted_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (muter_id, muted_id),
+            CHECK (muter_id <> muted_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Used by every comment query to drop authors the viewer blocked or was blocked by;
+    // false when there is no viewer
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION is_blocked_between(viewer_id UUID, author_id UUID)
+        RETURNS BOOLEAN AS $$
+            SELECT EXISTS(
+                SELECT 1 FROM user_blocks
+                WHERE (blocker_id = viewer_id AND blocked_id = author_id)
+                   OR (blocker_id = author_id AND blocked_id = viewer_id)
+            )
+        $$ LANGUAGE sql STABLE
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Privacy settings
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS show_in_feeds BOOLEAN NOT NULL DEFAULT TRUE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS show_connections BOOLEAN NOT NULL DEFAULT TRUE")
+        .execute(pool)
+        .await?;
+
+    // Reputation and the trust level derived from it, recomputed by ReputationService
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS reputation INTEGER NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS trust_level SMALLINT NOT NULL DEFAULT 0")
+        .execute(pool)
+        .await?;
+
+    // Set when a user's inputs changed outside their own recompute. Existing
+    // rows start stale so the first batch run applies the current rules.
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS reputation_stale BOOLEAN NOT NULL DEFAULT TRUE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ALTER COLUMN reputation_stale SET DEFAULT FALSE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS is_moderator BOOLEAN NOT NULL DEFAULT FALSE")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS comment_votes (
+            comment_id UUID NOT NULL REFERENCES comments(id) ON DELETE CASCADE,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            value SMALLINT NOT NULL CHECK (value IN (-1, 1)),
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (comment_id, user_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // author_id is copied from the comment so accepted reports still count
+    // against the author if the comment is later deleted
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS comment_reports (
+            id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+            comment_id UUID REFERENCES comments(id) ON DELETE SET NULL,
+            reporter_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            author_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            reason TEXT NOT NULL,
+            weight INTEGER NOT NULL,
+            status VARCHAR(16) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'accepted', 'rejected')),
+            resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            resolved_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            UNIQUE (comment_id, reporter_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Full-text search: each comment is indexed with the stemming configuration
+    // for its language, if known, plus `simple` so unstemmed queries still match
+    sqlx::query("ALTER TABLE comments ADD COLUMN IF NOT EXISTS language VARCHAR(8)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(&search_config_function_sql())
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        ALTER TABLE comments ADD COLUMN IF NOT EXISTS search_vector tsvector
+            GENERATED ALWAYS AS (to_tsvector(search_config(language), content) || to_tsvector('simple', content)) STORED
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Lowercased host of a URL, for filtering comments by site
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION url_host(url TEXT) RETURNS TEXT AS $$
+            SELECT lower(substring(url FROM '^[A-Za-z][A-Za-z0-9+.-]*://(?:[^@/?#]*@)?([^:/?#]+)'))
+        $$ LANGUAGE sql IMMUTABLE
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Registrable domain of each comment's page, for site-wide discussion
+    sqlx::query("ALTER TABLE comments ADD COLUMN IF NOT EXISTS domain TEXT")
+        .execute(pool)
+        .await?;
+
+    // Which normalizer produced each comment's normalized_url and url_hash
+    // (see url_rules::UrlRules::version); NULL for comments stored before
+    // versioning. The renormalize command brings rows up to date.
+    sqlx::query("ALTER TABLE comments ADD COLUMN IF NOT EXISTS normalizer_version TEXT")
+        .execute(pool)
+        .await?;
+
+    // URLs are only ever normalized by the backend; an SQL copy of the rules
+    // drifts out of sync and produces hashes that match nothing
+    sqlx::query("DROP FUNCTION IF EXISTS normalize_and_hash_url(TEXT)")
+        .execute(pool)
+        .await?;
+
+    // Text on the page a comment is about, as a W3C Web Annotation selector
+    // list (see services::annotations::TextAnchor); NULL for the whole page
+    sqlx::query("ALTER TABLE comments ADD COLUMN IF NOT EXISTS anchor JSONB CHECK (jsonb_typeof(anchor) = 'array')")
+        .execute(pool)
+        .await?;
+
+    // Pages reachable under several URLs (AMP versions, rel=canonical targets)
+    // share one thread. Aliases are one hop: a canonical hash is never itself
+    // an alias. Comments keep their own url_hash, so removing an alias splits
+    // the threads again.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS url_aliases (
+            alias_hash VARCHAR(64) PRIMARY KEY,
+            alias_url TEXT NOT NULL,
+            canonical_hash VARCHAR(64) NOT NULL,
+            canonical_url TEXT NOT NULL,
+            source VARCHAR(16) NOT NULL CHECK (source IN ('reported', 'moderator')),
+            created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            CHECK (alias_hash <> canonical_hash)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Canonical URLs reported by clients, one per account and page, until
+    // enough reporters agree for UrlAliasService to merge the threads
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS url_alias_reports (
+            alias_hash VARCHAR(64) NOT NULL,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            alias_url TEXT NOT NULL,
+            canonical_hash VARCHAR(64) NOT NULL,
+            canonical_url TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            PRIMARY KEY (alias_hash, user_id)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Comment counts per URL hash for badges and link decorations, kept up
+    // to date by a trigger so count lookups never scan comments. Rehashing
+    // a comment (see the renormalize command) moves it between counts.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS url_comment_counts (
+            url_hash VARCHAR(64) PRIMARY KEY,
+            comment_count BIGINT NOT NULL,
+            last_activity_at TIMESTAMPTZ NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE OR REPLACE FUNCTION update_url_comment_counts()
+        RETURNS TRIGGER AS $$
+        DECLARE
+            remaining BIGINT;
+        BEGIN
+            IF TG_OP = 'UPDATE' AND OLD.url_hash = NEW.url_hash THEN
+                RETURN NULL;
+            END IF;
+            IF TG_OP IN ('UPDATE', 'DELETE') THEN
+                UPDATE url_comment_counts SET comment_count = comment_count - 1
+                WHERE url_hash = OLD.url_hash
+                RETURNING comment_count INTO remaining;
+                IF remaining <= 0 THEN
+                    DELETE FROM url_comment_counts WHERE url_hash = OLD.url_hash;
+                ELSIF remaining IS NOT NULL THEN
+                    UPDATE url_comment_counts
+                    SET last_activity_at = COALESCE((SELECT MAX(created_at) FROM comments WHERE url_hash = OLD.url_hash), last_activity_at)
+                    WHERE url_hash = OLD.url_hash AND last_activity_at <= OLD.created_at;
+                END IF;
+            END IF;
+            IF TG_OP IN ('UPDATE', 'INSERT') THEN
+                INSERT INTO url_comment_counts (url_hash, comment_count, last_activity_at)
+                VALUES (NEW.url_hash, 1, COALESCE(NEW.created_at, NOW()))
+                ON CONFLICT (url_hash) DO UPDATE
+                SET comment_count = url_comment_counts.comment_count + 1,
+                    last_activity_at = GREATEST(url_comment_counts.last_activity_at, EXCLUDED.last_activity_at);
+            END IF;
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS update_url_comment_counts ON comments")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TRIGGER update_url_comment_counts AFTER INSERT OR DELETE OR UPDATE OF url_hash ON comments
+            FOR EACH ROW EXECUTE FUNCTION update_url_comment_counts()
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    CommentCountService::new(pool.clone()).backfill().await?;
+
+    // What a commented page is, as its commenters' clients saw it; refreshed
+    // by every comment that brings metadata (see services::pages)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS pages (
+            url_hash VARCHAR(64) PRIMARY KEY,
+            title TEXT,
+            description TEXT,
+            site_name TEXT,
+            language VARCHAR(35),
+            favicon_url TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
     // Create indexes for performance
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_comments_url_hash ON comments(url_hash)")
         .execute(pool)
@@ -75,12 +605,108 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
         .execute(pool)
         .await?;
 
-    // Create function to automatically update updated_at timestamp
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_users_deletion_scheduled_for ON users(deletion_scheduled_for) WHERE deletion_scheduled_for IS NOT NULL")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_account_audit_log_user_id ON account_audit_log(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_data_exports_user_id ON data_exports(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_verification_codes_expires_at ON verification_codes(expires_at)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_handle_lower ON users(LOWER(handle))")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_handle_skeleton ON users(handle_skeleton)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_handle_redirects_skeleton ON handle_redirects(skeleton)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_follows_followee_id ON follows(followee_id, created_at)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_blocks_blocked_id ON user_blocks(blocked_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comments_user_id_created_at ON comments(user_id, created_at DESC, id DESC)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comment_votes_user_id ON comment_votes(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comment_reports_author_id ON comment_reports(author_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comment_reports_reporter_id ON comment_reports(reporter_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comment_reports_open ON comment_reports(comment_id) WHERE status = 'open'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comments_search_vector ON comments USING GIN (search_vector)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_comments_domain_created_at ON comments(domain, created_at DESC, id DESC)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_url_aliases_canonical_hash ON url_aliases(canonical_hash)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_url_alias_reports_user_id ON url_alias_reports(user_id)")
+        .execute(pool)
+        .await?;
+
+    // Give accounts created before handles existed a generated one
+    HandleService::new(pool.clone(), 0).backfill().await?;
+
+    sqlx::query("ALTER TABLE users ALTER COLUMN handle SET NOT NULL")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE users ALTER COLUMN handle_skeleton SET NOT NULL")
+        .execute(pool)
+        .await?;
+
+    // Create function to automatically update updated_at timestamp.
+    // Maintenance jobs that only rewrite derived columns set
+    // votp.keep_updated_at for their transaction so rows don't look edited.
     sqlx::query(
         r#"
         CREATE OR REPLACE FUNCTION update_updated_at_column()
         RETURNS TRIGGER AS $$
         BEGIN
+            IF current_setting('votp.keep_updated_at', true) = 'on' THEN
+                RETURN NEW;
+            END IF;
             NEW.updated_at = NOW();
             RETURN NEW;
         END;
@@ -107,6 +733,9 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
     sqlx::query("DROP TRIGGER IF EXISTS update_comments_updated_at ON comments")
         .execute(pool)
         .await?;
+
+    // Backfilled while the trigger is dropped so old comments don't look edited
+    DomainService::new(pool.clone()).backfill().await?;
     
     sqlx::query(
         r#"
@@ -119,4 +748,90 @@ pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
 
     info!("Database migrations completed successfully");
     Ok(())
-}
\ No newline at end of file
+}
+/// Helpers for tests that need Postgres. They run against the database named
+/// by TEST_DATABASE_URL and are skipped when it isn't set.
+#[cfg(test)]
+pub mod testing {
+    use crate::graphql::loaders::{PageLoader, UserLoader};
+    use crate::graphql::{mutation::Mutation, query::Query, VotpSchema};
+    use crate::utils::{normalize_url_versioned, url_domain};
+    use async_graphql::dataloader::DataLoader;
+    use async_graphql::EmptySubscription;
+    use sqlx::PgPool;
+    use tokio::sync::OnceCell;
+    use uuid::Uuid;
+
+    static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+    /// A pool on the migrated test database, or None to skip the test
+    pub async fn pool() -> Option<PgPool> {
+        let Ok(database_url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping database test");
+            return None;
+        };
+
+        // Tests each run on their own runtime, so migrate on a pool of its own
+        MIGRATED
+            .get_or_init(|| async {
+                let pool = PgPool::connect(&database_url).await.expect("Failed to connect to the test database");
+                super::run_migrations(&pool).await.expect("Failed to migrate the test database");
+                pool.close().await;
+            })
+            .await;
+
+        Some(PgPool::connect(&database_url).await.expect("Failed to connect to the test database"))
+    }
+
+    /// The GraphQL schema with the shared data resolvers look up
+    pub fn schema(pool: &PgPool) -> VotpSchema {
+        VotpSchema::build(Query, Mutation, EmptySubscription)
+            .data(pool.clone())
+            .data(DataLoader::new(UserLoader::new(pool.clone()), tokio::spawn))
+            .data(DataLoader::new(PageLoader::new(pool.clone()), tokio::spawn))
+            .finish()
+    }
+
+    pub async fn create_user(pool: &PgPool) -> Uuid {
+        let id = Uuid::new_v4();
+        let handle = format!("test_{}", &id.simple().to_string()[..12]);
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, name, email, email_verified, handle, handle_skeleton)
+            VALUES ($1, 'Test User', $2, TRUE, $3, $3)
+            "#
+        )
+        .bind(id)
+        .bind(format!("{}@votp.test", handle))
+        .bind(&handle)
+        .execute(pool)
+        .await
+        .expect("Failed to create test user");
+        id
+    }
+
+    pub async fn create_comment(pool: &PgPool, user_id: Uuid, url: &str) -> Uuid {
+        let (normalized_url, url_hash, normalizer_version) = normalize_url_versioned(url).expect("Invalid test URL");
+        sqlx::query_scalar::<_, Uuid>(
+            r#"
+            INSERT INTO comments (content, url, normalized_url, url_hash, normalizer_version, user_id, domain)
+            VALUES ('Test comment', $1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#
+        )
+        .bind(url)
+        .bind(&normalized_url)
+        .bind(&url_hash)
+        .bind(&normalizer_version)
+        .bind(user_id)
+        .bind(url_domain(&normalized_url))
+        .fetch_one(pool)
+        .await
+        .expect("Failed to create test comment")
+    }
+
+    /// A page URL no other test uses
+    pub fn unique_url() -> String {
+        format!("https://test.votp.example/{}", Uuid::new_v4().simple())
+    }
+}
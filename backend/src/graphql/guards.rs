@@ -0,0 +1,65 @@
+use crate::services::api_keys::{ApiKeyAuth, SCOPE_MODERATION};
+use async_graphql::{Context, Guard, Result};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Requires an API key to carry the given scope. Requests made with a
+/// session token are not scope-limited and always pass.
+pub struct ScopeGuard {
+    scope: &'static str,
+}
+
+impl ScopeGuard {
+    pub fn new(scope: &'static str) -> Self {
+        Self { scope }
+    }
+}
+
+impl Guard for ScopeGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        match ctx.data_opt::<ApiKeyAuth>() {
+            Some(api_key) if !api_key.has_scope(self.scope) => Err(async_graphql::Error::new(format!(
+                "API key is missing the required scope: {}",
+                self.scope
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Restricts account management to interactive sessions, so an API key
+/// can never be used to mint further credentials or change the account
+pub struct SessionGuard;
+
+impl Guard for SessionGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        if ctx.data_opt::<ApiKeyAuth>().is_some() {
+            return Err(async_graphql::Error::new("This operation is not available to API keys"));
+        }
+        Ok(())
+    }
+}
+
+/// Requires the signed-in user to be a moderator, and API keys acting for
+/// them to carry the moderation scope
+pub struct ModeratorGuard;
+
+impl Guard for ModeratorGuard {
+    async fn check(&self, ctx: &Context<'_>) -> Result<()> {
+        ScopeGuard::new(SCOPE_MODERATION).check(ctx).await?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+        let pool = ctx.data::<PgPool>()?;
+        let is_moderator = sqlx::query_scalar::<_, bool>("SELECT is_moderator FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(false);
+
+        if !is_moderator {
+            return Err(async_graphql::Error::new("Moderator access required"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use crate::models::{Page, User};
+use async_graphql::dataloader::Loader;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Users by id. Fields resolved once per comment in a list (author,
+/// annotation) go through this so a page of comments costs one query.
+pub struct UserLoader {
+    pool: PgPool,
+}
+
+impl UserLoader {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl Loader<Uuid> for UserLoader {
+    type Value = User;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, User>, Self::Error> {
+        let users = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ANY($1)")
+            .bind(keys)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(users.into_iter().map(|user| (user.id, user)).collect())
+    }
+}
+
+/// Page metadata by url_hash, for the page of each comment in a list
+pub struct PageLoader {
+    pool: PgPool,
+}
+
+impl PageLoader {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl Loader<String> for PageLoader {
+    type Value = Page;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, keys: &[String]) -> Result<HashMap<String, Page>, Self::Error> {
+        let pages = sqlx::query_as::<_, Page>("SELECT * FROM pages WHERE url_hash = ANY($1)")
+            .bind(keys)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(pages.into_iter().map(|page| (page.url_hash.clone(), page)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::testing;
+    use crate::services::pages::{PageMetadata, PageService};
+    use crate::utils::normalize_url;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_authors_and_pages_load_for_each_comment() {
+        let Some(pool) = testing::pool().await else { return };
+        let url = testing::unique_url();
+        let authors = [testing::create_user(&pool).await, testing::create_user(&pool).await];
+        for author in authors.iter().chain(&authors) {
+            testing::create_comment(&pool, *author, &url).await;
+        }
+        let metadata = PageMetadata { title: Some("A page".to_string()), ..Default::default() };
+        PageService::new(pool.clone()).record(&normalize_url(&url).unwrap().1, &metadata, false).await.unwrap();
+
+        let query = format!(r#"{{ commentsForUrl(url: "{}") {{ userId author {{ id }} page {{ title }} }} }}"#, url);
+        let response = testing::schema(&pool).execute(query.as_str()).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        let comments = data["commentsForUrl"].as_array().unwrap();
+        assert_eq!(comments.len(), 4);
+        for comment in comments {
+            assert_eq!(comment["author"]["id"], comment["userId"]);
+            assert_eq!(comment["page"]["title"], "A page");
+            assert!(authors.contains(&comment["userId"].as_str().unwrap().parse::<Uuid>().unwrap()));
+        }
+    }
+}
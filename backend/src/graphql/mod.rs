@@ -1,4 +1,7 @@
+pub mod guards;
+pub mod loaders;
 pub mod mutation;
+pub mod pagination;
 pub mod query;
 
 use async_graphql::Schema;
@@ -1,13 +1,51 @@
 use crate::config::Config;
-use crate::models::{AuthPayload, Comment, UpdateProfileInput, User};
-use crate::services::{auth::AuthService, email::EmailService};
-use crate::utils::{generate_verification_code, normalize_url};
-use async_graphql::{Context, Object, Result};
-use chrono::Utc;
+use crate::models::{
+    ApiKey, AuthPayload, Comment, CommentReport, CreatedApiKey, DataExport, LinkedIdentity, LoginPayload, OidcAuthRequest, OidcAuthorization, TotpEnrollment,
+    PageMetadataInput, UpdatePrivacySettingsInput, UpdateProfileInput, UrlAlias, User,
+};
+use crate::graphql::guards::{ModeratorGuard, ScopeGuard, SessionGuard};
+use crate::services::account_deletion::DELETED_USER_ID;
+use crate::services::annotations::TextAnchor;
+use crate::services::api_keys::{ApiKeyAuth, ApiKeyService, SCOPE_COMMENTS_WRITE};
+use crate::services::audit;
+use crate::services::auth::AuthService;
+use crate::services::avatars::{process_avatar, AvatarService};
+use crate::services::blob_store::BlobStore;
+use crate::services::blocks::BlockService;
+use crate::services::comments::{CommentError, CommentService, NewComment};
+use crate::services::data_export::DataExportService;
+use crate::services::email::EmailService;
+use crate::services::follows::FollowService;
+use crate::services::handles::HandleService;
+use crate::services::oidc::{ExternalIdentity, OidcService};
+use crate::services::reports::ReportService;
+use crate::services::reputation::ReputationService;
+use crate::services::totp::TotpService;
+use crate::services::url_aliases::UrlAliasService;
+use crate::services::verification::{VerificationPurpose, VerificationService};
+use crate::services::votes::VoteService;
+use crate::utils::{generate_secure_token, hash_token, normalize_url};
+use crate::validation::{
+    handle_skeleton, validate_bio, validate_email, validate_handle, validate_name, validate_password,
+    validate_phone_number, FieldError, Validator,
+};
+use async_graphql::{Context, Object, Result, Upload};
+use chrono::{DateTime, Utc};
+use serde_json::json;
 use sqlx::PgPool;
+use std::io::Read;
+use std::sync::Arc;
 use tracing::{info, warn};
+use url::Url;
 use uuid::Uuid;
 
+/// How long a login MFA challenge stays valid
+const MFA_CHALLENGE_TTL_MINUTES: i64 = 5;
+/// Maximum number of codes that may be tried against one MFA challenge
+const MFA_CHALLENGE_MAX_ATTEMPTS: i32 = 5;
+/// How long the user has to finish signing in at an external provider
+const OIDC_REQUEST_TTL_MINUTES: i64 = 10;
+
 #[derive(Default)]
 pub struct Mutation;
 
@@ -20,7 +58,7 @@ impl Mutation {
         let user_exists = sqlx::query_scalar::<_, bool>(
             "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)"
         )
-        .bind(&email.to_lowercase())
+        .bind(email.to_lowercase())
         .fetch_one(pool)
         .await?;
 
@@ -46,29 +84,25 @@ impl Mutation {
             return Err(async_graphql::Error::new("User with this email already exists"));
         }
 
-        // Generate verification code
-        let code = generate_verification_code();
-        let _expires_at = Utc::now() + chrono::Duration::minutes(10);
-
-        // Store verification code (in production, use Redis or similar)
-        // For now, we'll store it in a temporary table or in-memory store
+        // Generate and store verification code
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let code = verification_service.issue(&email, VerificationPurpose::SignUp).await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?
+            .code;
         
         // Send email
         let email_service = EmailService::new(config.smtp.clone())
             .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
         
-        email_service.send_verification_code(&email, &code).await
+        email_service.send_verification_code(&email, &code, config.verification_code_expiry_minutes).await
             .map_err(|e| async_graphql::Error::new(format!("Failed to send email: {}", e)))?;
 
-        // Store the verification code temporarily (you might want to use Redis in production)
-        // For now, we'll create a simple in-memory store or use the database
-        
         info!("Verification code sent to {}", email);
         Ok(true)
     }
 
     /// User login
-    async fn login(&self, ctx: &Context<'_>, email: String, password: String) -> Result<AuthPayload> {
+    async fn login(&self, ctx: &Context<'_>, email: String, password: String) -> Result<LoginPayload> {
         let pool = ctx.data::<PgPool>()?;
         let config = ctx.data::<Config>()?;
         
@@ -84,9 +118,12 @@ impl Mutation {
 
         let user = user.ok_or_else(|| async_graphql::Error::new("Invalid email or password"))?;
 
-        // Verify password
+        // Verify password (passwordless-only accounts cannot log in this way)
+        let password_hash = user.password_hash.as_deref()
+            .ok_or_else(|| async_graphql::Error::new("Invalid email or password"))?;
+
         let auth_service = AuthService::new(config.jwt_secret.clone());
-        let password_valid = auth_service.verify_password(&password, &user.password_hash)
+        let password_valid = auth_service.verify_password(&password, password_hash)
             .map_err(|e| async_graphql::Error::new(format!("Authentication error: {}", e)))?;
 
         if !password_valid {
@@ -97,253 +134,344 @@ impl Mutation {
             return Err(async_graphql::Error::new("Email not verified. Please verify your email first."));
         }
 
-        // Generate JWT token
-        let token = auth_service.generate_jwt_token(&user)
-            .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
-
-        info!("User {} logged in successfully", user.email);
-
-        Ok(AuthPayload { token, user })
+        start_session(pool, config, user).await
     }
 
-    /// User signup with verification code
-    async fn sign_up(
-        &self,
-        ctx: &Context<'_>,
-        email: String,
-        password: String,
-        verification_code: String,
-        name: String,
-    ) -> Result<AuthPayload> {
+    /// Email a one-time login code and magic link to an existing account
+    async fn request_login_code(&self, ctx: &Context<'_>, email: String) -> Result<bool> {
         let pool = ctx.data::<PgPool>()?;
         let config = ctx.data::<Config>()?;
-        
+
         let email = email.to_lowercase();
 
-        // Check if user already exists
         let user_exists = sqlx::query_scalar::<_, bool>(
-            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)"
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1 AND email_verified = TRUE)"
         )
         .bind(&email)
         .fetch_one(pool)
         .await?;
 
-        if user_exists {
-            return Err(async_graphql::Error::new("User with this email already exists"));
+        if !user_exists {
+            // Respond the same way so the mutation can't be used to probe for accounts
+            info!("Login code requested for unknown email {}", email);
+            return Ok(true);
         }
 
-        // In a real implementation, you'd verify the code from your verification store
-        // For now, we'll assume it's valid if it's 6 digits
-        if verification_code.len() != 6 || !verification_code.chars().all(|c| c.is_ascii_digit()) {
-            return Err(async_graphql::Error::new("Invalid verification code"));
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let issued = verification_service.issue(&email, VerificationPurpose::Login).await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        let mut magic_link = Url::parse(&config.magic_link_base_url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid magic link URL: {}", e)))?;
+        magic_link.query_pairs_mut()
+            .append_pair("email", &email)
+            .append_pair("token", &issued.link_token);
+
+        let email_service = EmailService::new(config.smtp.clone())
+            .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
+
+        email_service.send_login_code(&email, &issued.code, magic_link.as_str(), config.verification_code_expiry_minutes).await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to send email: {}", e)))?;
+
+        info!("Login code sent to {}", email);
+        Ok(true)
+    }
+
+    /// Log in with a code sent by requestLoginCode
+    async fn login_with_code(&self, ctx: &Context<'_>, email: String, code: String) -> Result<LoginPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let email = email.to_lowercase();
+
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let valid = verification_service.verify_code(&email, VerificationPurpose::Login, &code).await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        if !valid {
+            return Err(async_graphql::Error::new("Invalid or expired login code"));
         }
 
-        // Hash password
-        let auth_service = AuthService::new(config.jwt_secret.clone());
-        let password_hash = auth_service.hash_password(&password)
-            .map_err(|e| async_graphql::Error::new(format!("Password hashing error: {}", e)))?;
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_one(pool)
+            .await?;
 
-        // Create user
-        let user = sqlx::query_as::<_, User>(
+        start_session(pool, config, user).await
+    }
+
+    /// Log in with the token from a magic link sent by requestLoginCode
+    async fn login_with_magic_link(&self, ctx: &Context<'_>, email: String, token: String) -> Result<LoginPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let email = email.to_lowercase();
+
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let valid = verification_service.verify_link_token(&email, VerificationPurpose::Login, &token).await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        if !valid {
+            return Err(async_graphql::Error::new("Invalid or expired login link"));
+        }
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_one(pool)
+            .await?;
+
+        start_session(pool, config, user).await
+    }
+
+    /// Complete a login for a TOTP-enrolled user using an authenticator or recovery code
+    async fn complete_login(&self, ctx: &Context<'_>, challenge: String, code: String) -> Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let challenge_hash = hash_token(&challenge);
+
+        // Count the attempt up front so concurrent guesses share the same budget
+        let user_id = sqlx::query_scalar::<_, Uuid>(
             r#"
-            INSERT INTO users (name, email, password_hash, email_verified, created_at, updated_at)
-            VALUES ($1, $2, $3, true, NOW(), NOW())
-            RETURNING *
+            UPDATE mfa_challenges
+            SET attempts = attempts + 1
+            WHERE token_hash = $1 AND expires_at > NOW() AND attempts < $2
+            RETURNING user_id
             "#
         )
-        .bind(&name)
-        .bind(&email)
-        .bind(&password_hash)
-        .fetch_one(pool)
-        .await?;
+        .bind(&challenge_hash)
+        .bind(MFA_CHALLENGE_MAX_ATTEMPTS)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| async_graphql::Error::new("Login challenge is invalid or has expired"))?;
 
-        // Generate JWT token
-        let token = auth_service.generate_jwt_token(&user)
-            .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
 
-        // Send welcome email
-        let email_service = EmailService::new(config.smtp.clone())
-            .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
-        
-        if let Err(e) = email_service.send_welcome_email(&email, &name).await {
-            warn!("Failed to send welcome email to {}: {}", email, e);
+        let secret = user.totp_secret.as_deref()
+            .filter(|_| user.totp_enabled)
+            .ok_or_else(|| async_graphql::Error::new("Two-factor authentication is not enabled"))?;
+
+        let totp_service = TotpService::new(config.totp_issuer.clone());
+        let matched_step = totp_service
+            .verify_code(secret, &code, Utc::now().timestamp(), user.totp_last_used_step)
+            .map_err(|e| async_graphql::Error::new(format!("Authentication error: {}", e)))?;
+
+        let verified = if let Some(step) = matched_step {
+            // Only advance forward so a code cannot be replayed within its window
+            let result = sqlx::query(
+                r#"
+                UPDATE users SET totp_last_used_step = $1
+                WHERE id = $2 AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)
+                "#
+            )
+            .bind(step)
+            .bind(user.id)
+            .execute(pool)
+            .await?;
+
+            result.rows_affected() == 1
+        } else {
+            let result = sqlx::query(
+                r#"
+                UPDATE recovery_codes SET used_at = NOW()
+                WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+                "#
+            )
+            .bind(user.id)
+            .bind(hash_token(&TotpService::normalize_recovery_code(&code)))
+            .execute(pool)
+            .await?;
+
+            if result.rows_affected() == 1 {
+                info!("User {} signed in with a recovery code", user.email);
+            }
+            result.rows_affected() == 1
+        };
+
+        if !verified {
+            return Err(async_graphql::Error::new("Invalid authentication code"));
         }
 
-        info!("User {} signed up successfully", user.email);
+        sqlx::query("DELETE FROM mfa_challenges WHERE token_hash = $1")
+            .bind(&challenge_hash)
+            .execute(pool)
+            .await?;
+
+        let auth_service = AuthService::new(config.jwt_secret.clone());
+        let token = auth_service.generate_jwt_token(&user)
+            .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
+
+        info!("User {} logged in successfully", user.email);
 
-        Ok(AuthPayload { token, user })
+        Ok(AuthPayload { token, user: user.for_session() })
     }
 
-    /// Update user profile
-    async fn update_profile(
-        &self,
-        ctx: &Context<'_>,
-        input: UpdateProfileInput,
-    ) -> Result<User> {
+    /// Start signing in with an external OAuth2/OIDC provider
+    async fn begin_oidc_login(&self, ctx: &Context<'_>, provider: String) -> Result<OidcAuthorization> {
         let pool = ctx.data::<PgPool>()?;
-        
-        // Get user ID from context (set by auth middleware)
-        let user_id = ctx.data::<Uuid>()
-            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+        let config = ctx.data::<Config>()?;
 
-        let mut query = "UPDATE users SET updated_at = NOW()".to_string();
-        let mut params: Vec<String> = vec![];
-        let mut param_count = 1;
+        begin_oidc(pool, config, &provider, None).await
+    }
 
-        if let Some(name) = input.name {
-            query.push_str(&format!(", name = ${}", param_count));
-            params.push(name);
-            param_count += 1;
-        }
+    /// Finish an external sign-in started by beginOidcLogin.
+    /// Accounts are matched by linked identity first, then by verified email.
+    /// An account whose email was never verified is reset before it is linked.
+    async fn complete_oidc_login(&self, ctx: &Context<'_>, state: String, code: String) -> Result<LoginPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
 
-        if let Some(phone_number) = input.phone_number {
-            query.push_str(&format!(", phone_number = ${}", param_count));
-            params.push(phone_number);
-            param_count += 1;
-        }
+        let (request, identity) = complete_oidc(pool, config, &state, &code).await?;
 
-        if let Some(bio) = input.bio {
-            query.push_str(&format!(", bio = ${}", param_count));
-            params.push(bio);
-            param_count += 1;
+        if request.link_user_id.is_some() {
+            return Err(async_graphql::Error::new("This sign-in was started as an account link"));
         }
 
-        query.push_str(&format!(" WHERE id = ${} RETURNING *", param_count));
+        let user = external_account(pool, config, &request.provider, &identity).await?;
 
-        let mut sql_query = sqlx::query_as::<_, User>(&query);
-        
-        for param in params {
-            sql_query = sql_query.bind(param);
-        }
-        sql_query = sql_query.bind(*user_id);
+        start_session(pool, config, user).await
+    }
 
-        let updated_user = sql_query.fetch_one(pool).await?;
+    /// Start linking an external provider to the current account
+    #[graphql(guard = "SessionGuard")]
+    async fn begin_oidc_link(&self, ctx: &Context<'_>, provider: String) -> Result<OidcAuthorization> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
 
-        info!("User {} updated profile", updated_user.email);
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
 
-        Ok(updated_user)
+        begin_oidc(pool, config, &provider, Some(*user_id)).await
     }
 
-    /// Create a new comment
-    async fn create_comment(
-        &self,
-        ctx: &Context<'_>,
-        content: String,
-        url: String,
-        parent_id: Option<Uuid>,
-    ) -> Result<Comment> {
+    /// Finish linking an external provider started by beginOidcLink
+    #[graphql(guard = "SessionGuard")]
+    async fn complete_oidc_link(&self, ctx: &Context<'_>, state: String, code: String) -> Result<Vec<LinkedIdentity>> {
         let pool = ctx.data::<PgPool>()?;
-        
-        // Get user ID from context
+        let config = ctx.data::<Config>()?;
+
         let user_id = ctx.data::<Uuid>()
             .map_err(|_| async_graphql::Error::new("Authentication required"))?;
 
-        // Normalize URL
-        let (normalized_url, url_hash) = normalize_url(&url)
-            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let (request, identity) = complete_oidc(pool, config, &state, &code).await?;
 
-        // Validate content
-        if content.trim().is_empty() {
-            return Err(async_graphql::Error::new("Comment content cannot be empty"));
+        if request.link_user_id != Some(*user_id) {
+            return Err(async_graphql::Error::new("This link request belongs to a different session"));
         }
 
-        if content.len() > 5000 {
-            return Err(async_graphql::Error::new("Comment content too long (max 5000 characters)"));
+        let already_linked = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM linked_identities WHERE provider = $1 AND subject = $2 AND user_id <> $3)"
+        )
+        .bind(&request.provider)
+        .bind(&identity.subject)
+        .bind(*user_id)
+        .fetch_one(pool)
+        .await?;
+
+        if already_linked {
+            return Err(async_graphql::Error::new("This account is already linked to another user"));
         }
 
-        // If parent_id is provided, verify it exists
-        if let Some(parent_id) = parent_id {
-            let parent_exists = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM comments WHERE id = $1)"
-            )
-            .bind(parent_id)
-            .fetch_one(pool)
-            .await?;
+        link_identity(pool, *user_id, &request.provider, &identity).await?;
 
-            if !parent_exists {
-                return Err(async_graphql::Error::new("Parent comment not found"));
-            }
-        }
+        info!("User {} linked {}", user_id, request.provider);
 
-        // Create comment
-        let comment = sqlx::query_as::<_, Comment>(
-            r#"
-            INSERT INTO comments (content, url, normalized_url, url_hash, user_id, parent_id, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, NOW(), NOW())
-            RETURNING *
-            "#
+        let identities = sqlx::query_as::<_, LinkedIdentity>(
+            "SELECT * FROM linked_identities WHERE user_id = $1 ORDER BY created_at ASC"
         )
-        .bind(&content.trim())
-        .bind(&url)
-        .bind(&normalized_url)
-        .bind(&url_hash)
         .bind(*user_id)
-        .bind(parent_id)
-        .fetch_one(pool)
+        .fetch_all(pool)
         .await?;
 
-        info!("Comment created by user {} on URL {}", user_id, url);
+        Ok(identities)
+    }
 
-        Ok(comment)
+    /// Disconnect an external provider from the current account
+    #[graphql(guard = "SessionGuard")]
+    async fn unlink_identity(&self, ctx: &Context<'_>, provider: String) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let result = sqlx::query("DELETE FROM linked_identities WHERE user_id = $1 AND provider = $2")
+            .bind(*user_id)
+            .bind(&provider)
+            .execute(pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(async_graphql::Error::new("No linked identity for this provider"));
+        }
+
+        info!("User {} unlinked {}", user_id, provider);
+
+        Ok(true)
     }
 
-    /// Update an existing comment
-    async fn update_comment(
+    /// Create a personal API key for scripted access.
+    /// The returned key is shown once; only its hash is stored.
+    #[graphql(guard = "SessionGuard")]
+    async fn create_api_key(
         &self,
         ctx: &Context<'_>,
-        id: Uuid,
-        content: String,
-    ) -> Result<Comment> {
+        name: String,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CreatedApiKey> {
         let pool = ctx.data::<PgPool>()?;
-        
-        // Get user ID from context
+
         let user_id = ctx.data::<Uuid>()
             .map_err(|_| async_graphql::Error::new("Authentication required"))?;
 
-        // Validate content
-        if content.trim().is_empty() {
-            return Err(async_graphql::Error::new("Comment content cannot be empty"));
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > 100 {
+            return Err(async_graphql::Error::new("API key name must be between 1 and 100 characters"));
         }
 
-        if content.len() > 5000 {
-            return Err(async_graphql::Error::new("Comment content too long (max 5000 characters)"));
+        let scopes = ApiKeyService::validate_scopes(&scopes)
+            .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+
+        if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(async_graphql::Error::new("Expiry must be in the future"));
         }
 
-        // Update comment (only if owned by user)
-        let updated_comment = sqlx::query_as::<_, Comment>(
+        let (key, prefix) = ApiKeyService::generate_key();
+
+        let api_key = sqlx::query_as::<_, ApiKey>(
             r#"
-            UPDATE comments 
-            SET content = $1, updated_at = NOW()
-            WHERE id = $2 AND user_id = $3
+            INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING *
             "#
         )
-        .bind(&content.trim())
-        .bind(id)
         .bind(*user_id)
-        .fetch_optional(pool)
+        .bind(name)
+        .bind(&prefix)
+        .bind(hash_token(&key))
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(pool)
         .await?;
 
-        let comment = updated_comment.ok_or_else(|| 
-            async_graphql::Error::new("Comment not found or you don't have permission to edit it")
-        )?;
-
-        info!("Comment {} updated by user {}", id, user_id);
+        info!("User {} created API key {}", user_id, api_key.prefix);
 
-        Ok(comment)
+        Ok(CreatedApiKey { key, api_key })
     }
 
-    /// Delete a comment
-    async fn delete_comment(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
+    /// Revoke one of the current user's API keys
+    #[graphql(guard = "SessionGuard")]
+    async fn revoke_api_key(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
         let pool = ctx.data::<PgPool>()?;
-        
-        // Get user ID from context
+
         let user_id = ctx.data::<Uuid>()
             .map_err(|_| async_graphql::Error::new("Authentication required"))?;
 
-        // Delete comment (only if owned by user)
         let result = sqlx::query(
-            "DELETE FROM comments WHERE id = $1 AND user_id = $2"
+            "UPDATE api_keys SET revoked_at = NOW() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL"
         )
         .bind(id)
         .bind(*user_id)
@@ -351,11 +479,1493 @@ impl Mutation {
         .await?;
 
         if result.rows_affected() == 0 {
-            return Err(async_graphql::Error::new("Comment not found or you don't have permission to delete it"));
+            return Err(async_graphql::Error::new("API key not found or already revoked"));
         }
 
-        info!("Comment {} deleted by user {}", id, user_id);
+        info!("User {} revoked API key {}", user_id, id);
 
         Ok(true)
     }
-}
\ No newline at end of file
+
+    /// Start TOTP enrollment, returning a new secret and its otpauth URI
+    #[graphql(guard = "SessionGuard")]
+    async fn begin_totp_enrollment(&self, ctx: &Context<'_>) -> Result<TotpEnrollment> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let totp_service = TotpService::new(config.totp_issuer.clone());
+        let secret = totp_service.generate_secret();
+
+        // The secret stays pending until confirmTotpEnrollment proves the app is set up
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET totp_secret = $1, totp_last_used_step = NULL
+            WHERE id = $2 AND totp_enabled = FALSE
+            RETURNING *
+            "#
+        )
+        .bind(&secret)
+        .bind(*user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| async_graphql::Error::new("Two-factor authentication is already enabled"))?;
+
+        let otpauth_uri = totp_service.provisioning_uri(&secret, &user.email);
+
+        info!("User {} started TOTP enrollment", user.email);
+
+        Ok(TotpEnrollment { secret, otpauth_uri })
+    }
+
+    /// Confirm TOTP enrollment with a code from the authenticator app.
+    /// Returns the recovery codes, which are only ever shown once.
+    #[graphql(guard = "SessionGuard")]
+    async fn confirm_totp_enrollment(&self, ctx: &Context<'_>, code: String) -> Result<Vec<String>> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(*user_id)
+            .fetch_one(pool)
+            .await?;
+
+        if user.totp_enabled {
+            return Err(async_graphql::Error::new("Two-factor authentication is already enabled"));
+        }
+
+        let secret = user.totp_secret.as_deref()
+            .ok_or_else(|| async_graphql::Error::new("No TOTP enrollment in progress"))?;
+
+        let totp_service = TotpService::new(config.totp_issuer.clone());
+        let step = totp_service
+            .verify_code(secret, &code, Utc::now().timestamp(), None)
+            .map_err(|e| async_graphql::Error::new(format!("Authentication error: {}", e)))?
+            .ok_or_else(|| async_graphql::Error::new("Invalid authentication code"))?;
+
+        let recovery_codes = totp_service.generate_recovery_codes();
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("UPDATE users SET totp_enabled = TRUE, totp_last_used_step = $1 WHERE id = $2")
+            .bind(step)
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        for recovery_code in &recovery_codes {
+            sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2)")
+                .bind(user.id)
+                .bind(hash_token(&TotpService::normalize_recovery_code(recovery_code)))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        info!("User {} enabled two-factor authentication", user.email);
+
+        Ok(recovery_codes)
+    }
+
+    /// User signup with verification code
+    async fn sign_up(
+        &self,
+        ctx: &Context<'_>,
+        email: String,
+        password: Option<String>,
+        verification_code: String,
+        name: String,
+        #[graphql(desc = "Generated from the name when omitted")] handle: Option<String>,
+    ) -> Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+        let handle_service = HandleService::new(pool.clone(), config.handle_rename_cooldown_days);
+        
+        let mut validator = Validator::new();
+        let email = validator.check(validate_email("email", &email)).unwrap_or_default();
+        let name = validator.check(validate_name("name", &name)).unwrap_or_default();
+        if let Some(password) = &password {
+            validator.check(validate_password("password", password));
+        }
+        let handle = match handle.map(|handle| validate_handle("handle", &handle)) {
+            Some(Ok(handle)) if !handle_service.is_available(&handle, None).await? => {
+                validator.add(FieldError::new("handle", "TAKEN", "This handle is already taken"));
+                None
+            }
+            Some(result) => validator.check(result),
+            None => None,
+        };
+        validator.finish()?;
+
+        // Check if user already exists
+        let user_exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)"
+        )
+        .bind(&email)
+        .fetch_one(pool)
+        .await?;
+
+        if user_exists {
+            return Err(async_graphql::Error::new("User with this email already exists"));
+        }
+
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let code_valid = verification_service
+            .verify_code(&email, VerificationPurpose::SignUp, &verification_code)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        if !code_valid {
+            return Err(async_graphql::Error::new("Invalid verification code"));
+        }
+
+        // Hash password; accounts created without one sign in with emailed codes
+        let auth_service = AuthService::new(config.jwt_secret.clone());
+        let password_hash = password
+            .map(|password| auth_service.hash_password(&password))
+            .transpose()
+            .map_err(|e| async_graphql::Error::new(format!("Password hashing error: {}", e)))?;
+
+        let handle = match handle {
+            Some(handle) => handle,
+            None => handle_service.generate(&name).await
+                .map_err(|e| async_graphql::Error::new(format!("Handle error: {}", e)))?,
+        };
+
+        // Create user
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (name, email, password_hash, handle, handle_skeleton, email_verified, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, true, NOW(), NOW())
+            RETURNING *
+            "#
+        )
+        .bind(&name)
+        .bind(&email)
+        .bind(&password_hash)
+        .bind(&handle)
+        .bind(handle_skeleton(&handle))
+        .fetch_one(pool)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Sign up error: {}", e)))?;
+
+        // Generate JWT token
+        let token = auth_service.generate_jwt_token(&user)
+            .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
+
+        // Send welcome email
+        let email_service = EmailService::new(config.smtp.clone())
+            .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
+        
+        if let Err(e) = email_service.send_welcome_email(&email, &name).await {
+            warn!("Failed to send welcome email to {}: {}", email, e);
+        }
+
+        info!("User {} signed up successfully", user.email);
+
+        Ok(AuthPayload { token, user: user.for_session() })
+    }
+
+    /// Request deletion of the current account. The account is purged after a
+    /// grace period; until then it can be cancelled from the emailed link.
+    /// Accounts without a password confirm with a code from requestLoginCode.
+    #[graphql(guard = "SessionGuard")]
+    async fn delete_account(
+        &self,
+        ctx: &Context<'_>,
+        password: Option<String>,
+        code: Option<String>,
+        #[graphql(default = false)] delete_comments: bool,
+    ) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(*user_id)
+            .fetch_one(pool)
+            .await?;
+
+        let confirmed = match (&user.password_hash, password, code) {
+            (Some(password_hash), Some(password), _) => AuthService::new(config.jwt_secret.clone())
+                .verify_password(&password, password_hash)
+                .map_err(|e| async_graphql::Error::new(format!("Authentication error: {}", e)))?,
+            (None, _, Some(code)) => VerificationService::new(pool.clone(), config.verification_code_expiry_minutes)
+                .verify_code(&user.email, VerificationPurpose::Login, &code)
+                .await
+                .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?,
+            _ => false,
+        };
+
+        if !confirmed {
+            return Err(async_graphql::Error::new("Invalid password or code"));
+        }
+
+        let cancel_token = generate_secure_token();
+
+        let mut tx = pool.begin().await?;
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET deletion_requested_at = NOW(),
+                deletion_scheduled_for = NOW() + make_interval(days => $1),
+                deletion_delete_comments = $2,
+                deletion_cancel_token_hash = $3
+            WHERE id = $4
+            RETURNING *
+            "#
+        )
+        .bind(config.account_deletion_grace_days as i32)
+        .bind(delete_comments)
+        .bind(hash_token(&cancel_token))
+        .bind(user.id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Scripted access stops immediately rather than at the end of the grace period
+        sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+            .bind(user.id)
+            .execute(&mut *tx)
+            .await?;
+
+        audit::record_event(
+            &mut *tx,
+            user.id,
+            "deletion_requested",
+            json!({
+                "scheduled_for": user.deletion_scheduled_for,
+                "delete_comments": delete_comments,
+            }),
+        )
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+        tx.commit().await?;
+
+        let mut cancel_link = Url::parse(&config.account_deletion_cancel_url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid cancellation URL: {}", e)))?;
+        cancel_link.query_pairs_mut().append_pair("token", &cancel_token);
+
+        if let Some(scheduled_for) = &user.deletion_scheduled_for {
+            let email_service = EmailService::new(config.smtp.clone())
+                .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
+
+            if let Err(e) = email_service
+                .send_account_deletion_scheduled(&user.email, &user.name, scheduled_for, cancel_link.as_str())
+                .await
+            {
+                warn!("Failed to send account deletion email to {}: {}", user.email, e);
+            }
+        }
+
+        info!("User {} requested account deletion", user.id);
+
+        Ok(user)
+    }
+
+    /// Cancel a pending account deletion, either signed in or with the emailed token
+    async fn cancel_account_deletion(&self, ctx: &Context<'_>, token: Option<String>) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let cancelled_user_id = match (token, ctx.data_opt::<Uuid>()) {
+            (Some(token), _) => sqlx::query_scalar::<_, Uuid>(
+                r#"
+                UPDATE users
+                SET deletion_requested_at = NULL, deletion_scheduled_for = NULL,
+                    deletion_delete_comments = FALSE, deletion_cancel_token_hash = NULL
+                WHERE deletion_cancel_token_hash = $1 AND deletion_scheduled_for > NOW()
+                RETURNING id
+                "#
+            )
+            .bind(hash_token(&token))
+            .fetch_optional(pool)
+            .await?,
+            (None, Some(user_id)) if ctx.data_opt::<ApiKeyAuth>().is_none() => sqlx::query_scalar::<_, Uuid>(
+                r#"
+                UPDATE users
+                SET deletion_requested_at = NULL, deletion_scheduled_for = NULL,
+                    deletion_delete_comments = FALSE, deletion_cancel_token_hash = NULL
+                WHERE id = $1 AND deletion_scheduled_for > NOW()
+                RETURNING id
+                "#
+            )
+            .bind(*user_id)
+            .fetch_optional(pool)
+            .await?,
+            _ => return Err(async_graphql::Error::new("Authentication required")),
+        };
+
+        let user_id = cancelled_user_id
+            .ok_or_else(|| async_graphql::Error::new("No pending account deletion to cancel"))?;
+
+        audit::record_event(pool, user_id, "deletion_cancelled", json!({}))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+        info!("User {} cancelled account deletion", user_id);
+
+        Ok(true)
+    }
+
+    /// Request a machine-readable archive of everything stored about the current user.
+    /// The archive is built in the background; poll dataExports for its download link.
+    #[graphql(guard = "SessionGuard")]
+    async fn request_data_export(&self, ctx: &Context<'_>) -> Result<DataExport> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let pending = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM data_exports WHERE user_id = $1 AND status = 'pending')"
+        )
+        .bind(*user_id)
+        .fetch_one(pool)
+        .await?;
+
+        if pending {
+            return Err(async_graphql::Error::new("A data export is already being prepared"));
+        }
+
+        let export = sqlx::query_as::<_, DataExport>(
+            "INSERT INTO data_exports (user_id) VALUES ($1) RETURNING *"
+        )
+        .bind(*user_id)
+        .fetch_one(pool)
+        .await?;
+
+        audit::record_event(pool, *user_id, "data_export_requested", json!({ "export_id": export.id }))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+        let export_service = DataExportService::new(pool.clone(), config.export_dir.clone().into(), config.jwt_secret.clone());
+        let (export_id, export_user_id) = (export.id, export.user_id);
+        tokio::spawn(async move {
+            export_service.process(export_id, export_user_id).await;
+        });
+
+        info!("User {} requested a data export", user_id);
+
+        Ok(export)
+    }
+
+    /// Start changing the account email. A code is sent to the new address and
+    /// a notice to the current one. Accounts without a password confirm with a
+    /// code from requestLoginCode instead.
+    #[graphql(guard = "SessionGuard")]
+    async fn request_email_change(
+        &self,
+        ctx: &Context<'_>,
+        new_email: String,
+        password: Option<String>,
+        code: Option<String>,
+    ) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let mut validator = Validator::new();
+        let new_email = validator.check(validate_email("newEmail", &new_email)).unwrap_or_default();
+        validator.finish()?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(*user_id)
+            .fetch_one(pool)
+            .await?;
+
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+
+        let confirmed = match (&user.password_hash, password, code) {
+            (Some(password_hash), Some(password), _) => AuthService::new(config.jwt_secret.clone())
+                .verify_password(&password, password_hash)
+                .map_err(|e| async_graphql::Error::new(format!("Authentication error: {}", e)))?,
+            (None, _, Some(code)) => verification_service
+                .verify_code(&user.email, VerificationPurpose::Login, &code)
+                .await
+                .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?,
+            _ => false,
+        };
+
+        if !confirmed {
+            return Err(async_graphql::Error::new("Invalid password or code"));
+        }
+
+        if new_email == user.email {
+            return Err(async_graphql::Error::new("This is already your email address"));
+        }
+
+        let email_taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM users WHERE email = $1)"
+        )
+        .bind(&new_email)
+        .fetch_one(pool)
+        .await?;
+
+        if email_taken {
+            return Err(async_graphql::Error::new("User with this email already exists"));
+        }
+
+        sqlx::query("UPDATE users SET pending_email = $1 WHERE id = $2")
+            .bind(&new_email)
+            .bind(user.id)
+            .execute(pool)
+            .await?;
+
+        let issued = verification_service.issue(&new_email, VerificationPurpose::EmailChange).await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        let email_service = EmailService::new(config.smtp.clone())
+            .map_err(|e| async_graphql::Error::new(format!("Email service error: {}", e)))?;
+
+        email_service.send_email_change_code(&new_email, &issued.code, config.verification_code_expiry_minutes).await
+            .map_err(|e| async_graphql::Error::new(format!("Failed to send email: {}", e)))?;
+
+        if let Err(e) = email_service.send_email_change_notice(&user.email, &new_email).await {
+            warn!("Failed to send email change notice to {}: {}", user.email, e);
+        }
+
+        audit::record_event(pool, user.id, "email_change_requested", json!({}))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+        info!("User {} requested an email change", user.id);
+
+        Ok(true)
+    }
+
+    /// Confirm an email change with the code sent to the new address.
+    /// All other sessions are signed out; the returned token replaces the current one.
+    #[graphql(guard = "SessionGuard")]
+    async fn confirm_email_change(&self, ctx: &Context<'_>, code: String) -> Result<AuthPayload> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let pending_email = sqlx::query_scalar::<_, Option<String>>("SELECT pending_email FROM users WHERE id = $1")
+            .bind(*user_id)
+            .fetch_one(pool)
+            .await?
+            .ok_or_else(|| async_graphql::Error::new("No email change in progress"))?;
+
+        let verification_service = VerificationService::new(pool.clone(), config.verification_code_expiry_minutes);
+        let valid = verification_service
+            .verify_code(&pending_email, VerificationPurpose::EmailChange, &code)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Verification error: {}", e)))?;
+
+        if !valid {
+            return Err(async_graphql::Error::new("Invalid or expired verification code"));
+        }
+
+        let mut tx = pool.begin().await?;
+
+        // The unique constraint on email settles any race with a signup for the same address
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET email = pending_email, pending_email = NULL, email_verified = TRUE, tokens_valid_after = NOW()
+            WHERE id = $1 AND pending_email = $2
+            RETURNING *
+            "#
+        )
+        .bind(*user_id)
+        .bind(&pending_email)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                async_graphql::Error::new("User with this email already exists")
+            }
+            e => e.into(),
+        })?
+        .ok_or_else(|| async_graphql::Error::new("No email change in progress"))?;
+
+        audit::record_event(&mut *tx, user.id, "email_changed", json!({}))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+        tx.commit().await?;
+
+        let auth_service = AuthService::new(config.jwt_secret.clone());
+        let token = auth_service.generate_jwt_token(&user)
+            .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
+
+        info!("User {} changed their email address", user.id);
+
+        Ok(AuthPayload { token, user: user.for_session() })
+    }
+
+    /// Update user profile
+    #[graphql(guard = "SessionGuard")]
+    async fn update_profile(
+        &self,
+        ctx: &Context<'_>,
+        input: UpdateProfileInput,
+    ) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        
+        // Get user ID from context (set by auth middleware)
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        // Validate every provided field up front; empty phone number or bio clears it
+        let mut validator = Validator::new();
+        let name = input.name.and_then(|name| validator.check(validate_name("name", &name)));
+        let phone_number = input.phone_number
+            .and_then(|phone_number| validator.check(validate_phone_number("phoneNumber", &phone_number)));
+        let bio = input.bio.and_then(|bio| validator.check(validate_bio("bio", &bio)));
+        validator.finish()?;
+
+        let mut query = "UPDATE users SET updated_at = NOW()".to_string();
+        let mut params: Vec<Option<String>> = vec![];
+        let mut param_count = 1;
+
+        if let Some(name) = name {
+            query.push_str(&format!(", name = ${}", param_count));
+            params.push(Some(name));
+            param_count += 1;
+        }
+
+        if let Some(phone_number) = phone_number {
+            query.push_str(&format!(", phone_number = ${}", param_count));
+            params.push(phone_number);
+            param_count += 1;
+        }
+
+        if let Some(bio) = bio {
+            query.push_str(&format!(", bio = ${}", param_count));
+            params.push(bio);
+            param_count += 1;
+        }
+
+        query.push_str(&format!(" WHERE id = ${} RETURNING *", param_count));
+
+        let mut sql_query = sqlx::query_as::<_, User>(&query);
+        
+        for param in params {
+            sql_query = sql_query.bind(param);
+        }
+        sql_query = sql_query.bind(*user_id);
+
+        let updated_user = sql_query.fetch_one(pool).await?;
+
+        info!("User {} updated profile", updated_user.email);
+
+        Ok(updated_user)
+    }
+
+    /// Change the current user's handle. The previous handle keeps redirecting
+    /// to this account, and handles can only be changed once per cooldown period.
+    #[graphql(guard = "SessionGuard")]
+    async fn change_handle(&self, ctx: &Context<'_>, handle: String) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let mut validator = Validator::new();
+        let handle = validator.check(validate_handle("handle", &handle)).unwrap_or_default();
+        validator.finish()?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(*user_id)
+            .fetch_one(pool)
+            .await?;
+
+        if user.handle == handle {
+            return Ok(user);
+        }
+
+        let handle_service = HandleService::new(pool.clone(), config.handle_rename_cooldown_days);
+        if let Some(available_at) = handle_service.next_rename_at(&user) {
+            return Err(async_graphql::Error::new(format!(
+                "You can change your handle again after {}",
+                available_at.format("%Y-%m-%d %H:%M UTC")
+            )));
+        }
+
+        let available = handle_service.is_available(&handle, Some(*user_id)).await
+            .map_err(|e| async_graphql::Error::new(format!("Handle error: {}", e)))?;
+        if !available {
+            let mut validator = Validator::new();
+            validator.add(FieldError::new("handle", "TAKEN", "This handle is already taken"));
+            validator.finish()?;
+        }
+
+        let old_handle = user.handle;
+        let user = handle_service.rename(*user_id, &handle).await
+            .map_err(|e| async_graphql::Error::new(format!("Handle error: {}", e)))?;
+
+        audit::record_event(pool, *user_id, "handle_changed", json!({})).await?;
+
+        info!("User {} changed handle from {} to {}", user_id, old_handle, user.handle);
+
+        Ok(user)
+    }
+
+    /// Update the current user's privacy settings
+    #[graphql(guard = "SessionGuard")]
+    async fn update_privacy_settings(&self, ctx: &Context<'_>, input: UpdatePrivacySettingsInput) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET
+                show_in_feeds = COALESCE($1, show_in_feeds),
+                show_connections = COALESCE($2, show_connections)
+            WHERE id = $3
+            RETURNING *
+            "#
+        )
+        .bind(input.show_in_feeds)
+        .bind(input.show_connections)
+        .bind(*user_id)
+        .fetch_one(pool)
+        .await?;
+
+        info!("User {} updated privacy settings", user_id);
+
+        Ok(user)
+    }
+
+    /// Follow a user, adding their comments to the current user's feed
+    #[graphql(guard = "SessionGuard")]
+    async fn follow_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let follower_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        if user_id == *follower_id {
+            return Err(async_graphql::Error::new("You cannot follow yourself"));
+        }
+        if user_id == DELETED_USER_ID {
+            return Err(async_graphql::Error::new("User not found"));
+        }
+
+        let blocked = BlockService::new(pool.clone())
+            .is_blocked_between(*follower_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Block check error: {}", e)))?;
+        if blocked {
+            return Err(async_graphql::Error::new("User not found"));
+        }
+
+        let followed = FollowService::new(pool.clone())
+            .follow(*follower_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Follow error: {}", e)))?;
+        if !followed {
+            return Err(async_graphql::Error::new("User not found"));
+        }
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+
+        info!("User {} followed {}", follower_id, user_id);
+
+        Ok(user)
+    }
+
+    /// Stop following a user
+    #[graphql(guard = "SessionGuard")]
+    async fn unfollow_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let follower_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        FollowService::new(pool.clone())
+            .unfollow(*follower_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Unfollow error: {}", e)))?;
+
+        let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| async_graphql::Error::new("User not found"))?;
+
+        info!("User {} unfollowed {}", follower_id, user_id);
+
+        Ok(user)
+    }
+
+    /// Block a user: their comments are hidden from you and yours from them,
+    /// neither of you can reply to the other, and any follows between you end
+    #[graphql(guard = "SessionGuard")]
+    async fn block_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let blocker_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        if user_id == *blocker_id {
+            return Err(async_graphql::Error::new("You cannot block yourself"));
+        }
+
+        let blocked = BlockService::new(pool.clone())
+            .block(*blocker_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Block error: {}", e)))?;
+        if !blocked {
+            return Err(async_graphql::Error::new("User not found"));
+        }
+
+        info!("User {} blocked {}", blocker_id, user_id);
+
+        Ok(true)
+    }
+
+    #[graphql(guard = "SessionGuard")]
+    async fn unblock_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let blocker_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let unblocked = BlockService::new(pool.clone())
+            .unblock(*blocker_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Unblock error: {}", e)))?;
+
+        info!("User {} unblocked {}", blocker_id, user_id);
+
+        Ok(unblocked)
+    }
+
+    /// Mute a user: their comments stay visible but are returned collapsed
+    #[graphql(guard = "SessionGuard")]
+    async fn mute_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let muter_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        if user_id == *muter_id {
+            return Err(async_graphql::Error::new("You cannot mute yourself"));
+        }
+
+        let muted = BlockService::new(pool.clone())
+            .mute(*muter_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Mute error: {}", e)))?;
+        if !muted {
+            return Err(async_graphql::Error::new("User not found"));
+        }
+
+        info!("User {} muted {}", muter_id, user_id);
+
+        Ok(true)
+    }
+
+    #[graphql(guard = "SessionGuard")]
+    async fn unmute_user(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let muter_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let unmuted = BlockService::new(pool.clone())
+            .unmute(*muter_id, user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Unmute error: {}", e)))?;
+
+        info!("User {} unmuted {}", muter_id, user_id);
+
+        Ok(unmuted)
+    }
+
+    /// Upload a new avatar as a GraphQL multipart upload. PNG, JPEG, GIF and
+    /// WebP are accepted; metadata is stripped and 32/64/128px variants stored.
+    #[graphql(guard = "SessionGuard")]
+    async fn upload_avatar(&self, ctx: &Context<'_>, file: Upload) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+        let store = ctx.data::<Arc<dyn BlobStore>>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let upload = file.value(ctx)?;
+        if upload.size()? > config.avatar_max_bytes as u64 {
+            let mut validator = Validator::new();
+            validator.add(FieldError::new(
+                "file",
+                "TOO_LARGE",
+                format!("Avatars must be at most {} KB", config.avatar_max_bytes / 1024),
+            ));
+            validator.finish()?;
+        }
+
+        let mut bytes = Vec::new();
+        upload.into_read().read_to_end(&mut bytes)?;
+
+        // Decoding and resizing is CPU-bound, keep it off the async workers
+        let processed = tokio::task::spawn_blocking(move || process_avatar("file", &bytes)).await?;
+        let mut validator = Validator::new();
+        let variants = validator.check(processed).unwrap_or_default();
+        validator.finish()?;
+
+        let user = AvatarService::new(pool.clone(), store.clone())
+            .replace(*user_id, variants)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Avatar upload error: {}", e)))?;
+
+        info!("User {} uploaded a new avatar", user_id);
+
+        Ok(user)
+    }
+
+    /// Remove the current avatar, falling back to the generated identicon
+    #[graphql(guard = "SessionGuard")]
+    async fn remove_avatar(&self, ctx: &Context<'_>) -> Result<User> {
+        let pool = ctx.data::<PgPool>()?;
+        let store = ctx.data::<Arc<dyn BlobStore>>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let user = AvatarService::new(pool.clone(), store.clone())
+            .remove(*user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Avatar removal error: {}", e)))?;
+
+        info!("User {} removed their avatar", user_id);
+
+        Ok(user)
+    }
+
+    /// Create a new comment
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    #[allow(clippy::too_many_arguments)] // each one is a GraphQL argument
+    async fn create_comment(
+        &self,
+        ctx: &Context<'_>,
+        content: String,
+        url: String,
+        parent_id: Option<Uuid>,
+        #[graphql(desc = "ISO 639-1 code of the comment's language, used to stem it for search")]
+        language: Option<String>,
+        #[graphql(desc = "The text on the page the comment is about; not allowed on replies")]
+        anchor: Option<TextAnchor>,
+        #[graphql(desc = "Title, site name and favicon of the page, read by the client; refreshes what is stored for it")]
+        page: Option<PageMetadataInput>,
+    ) -> Result<Comment> {
+        let pool = ctx.data::<PgPool>()?;
+        
+        // Get user ID from context
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let comment = CommentService::new(pool.clone())
+            .create(*user_id, NewComment { content, url: url.clone(), parent_id, language, anchor, page })
+            .await
+            .map_err(comment_error)?;
+
+        info!("Comment created by user {} on URL {}", user_id, url);
+
+        Ok(comment)
+    }
+
+    /// Update an existing comment
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    async fn update_comment(
+        &self,
+        ctx: &Context<'_>,
+        id: Uuid,
+        content: String,
+    ) -> Result<Comment> {
+        let pool = ctx.data::<PgPool>()?;
+        
+        // Get user ID from context
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let comment = CommentService::new(pool.clone())
+            .update(*user_id, id, &content, None)
+            .await
+            .map_err(comment_error)?;
+
+        info!("Comment {} updated by user {}", id, user_id);
+
+        Ok(comment)
+    }
+
+    /// Delete a comment
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    async fn delete_comment(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        
+        // Get user ID from context
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        CommentService::new(pool.clone())
+            .delete(*user_id, id)
+            .await
+            .map_err(comment_error)?;
+
+        info!("Comment {} deleted by user {}", id, user_id);
+
+        Ok(true)
+    }
+
+    /// Vote on another user's comment: 1 to upvote, -1 to downvote, 0 to
+    /// clear the vote. Returns the comment's new score.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    async fn vote_comment(&self, ctx: &Context<'_>, id: Uuid, value: i32) -> Result<i64> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        if ![-1, 0, 1].contains(&value) {
+            return Err(async_graphql::Error::new("Vote must be 1, -1 or 0"));
+        }
+
+        let author_id = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM comments WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| async_graphql::Error::new("Comment not found"))?;
+
+        if author_id == *user_id {
+            return Err(async_graphql::Error::new("You can't vote on your own comment"));
+        }
+
+        let score = VoteService::new(pool.clone())
+            .vote(*user_id, id, value as i16)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Vote error: {}", e)))?
+            .ok_or_else(|| async_graphql::Error::new("Comment not found"))?;
+
+        info!("User {} voted {} on comment {}", user_id, value, id);
+
+        Ok(score)
+    }
+
+    /// Report a comment for moderator review; reporting again updates the reason
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    async fn report_comment(&self, ctx: &Context<'_>, id: Uuid, reason: String) -> Result<CommentReport> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let reason = reason.trim();
+        if reason.is_empty() || reason.chars().count() > 1000 {
+            return Err(async_graphql::Error::new("Report reason must be between 1 and 1000 characters"));
+        }
+
+        let author_id = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM comments WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| async_graphql::Error::new("Comment not found"))?;
+
+        if author_id == *user_id {
+            return Err(async_graphql::Error::new("You can't report your own comment"));
+        }
+
+        let trust_level = ReputationService::new(pool.clone())
+            .trust_level(*user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Reputation error: {}", e)))?;
+
+        let report = ReportService::new(pool.clone())
+            .report(*user_id, trust_level, id, reason)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Report error: {}", e)))?
+            .ok_or_else(|| async_graphql::Error::new("Comment not found"))?;
+
+        info!("User {} reported comment {}", user_id, id);
+
+        Ok(report)
+    }
+
+    /// Accept or reject an open report (moderators only). Accepted reports
+    /// raise the reporter's reputation and lower the author's.
+    #[graphql(guard = "ModeratorGuard")]
+    async fn resolve_report(&self, ctx: &Context<'_>, id: Uuid, accept: bool) -> Result<CommentReport> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let report = ReportService::new(pool.clone())
+            .resolve(id, *user_id, accept)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Report error: {}", e)))?
+            .ok_or_else(|| async_graphql::Error::new("Open report not found"))?;
+
+        info!("Moderator {} {} report {}", user_id, report.status, id);
+
+        Ok(report)
+    }
+
+    /// Report the canonical URL a page declares through `<link rel=canonical>`
+    /// or `og:url`, so its comments join that URL's thread. Only believable
+    /// reports from established accounts are recorded, and the threads merge
+    /// once several accounts agree. Returns the alias if this report merged
+    /// them, and null otherwise.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_WRITE)")]
+    async fn report_canonical(&self, ctx: &Context<'_>, url: String, canonical_url: String) -> Result<Option<UrlAlias>> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let (normalized_url, url_hash) = normalize_url(&url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let (normalized_canonical_url, canonical_hash) = normalize_url(&canonical_url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid canonical URL: {}", e)))?;
+
+        let trust_level = ReputationService::new(pool.clone())
+            .trust_level(*user_id)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Reputation error: {}", e)))?;
+        if !trust_level.can_post_links() {
+            return Ok(None);
+        }
+
+        let alias = UrlAliasService::new(pool.clone())
+            .report(*user_id, (&normalized_url, &url_hash), (&normalized_canonical_url, &canonical_hash))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        if let Some(alias) = &alias {
+            info!("User {} reported {} as an alias of {}", user_id, alias.alias_url, alias.canonical_url);
+        }
+
+        Ok(alias)
+    }
+
+    /// Merge the thread for `url` into the thread `intoUrl` belongs to
+    /// (moderators only). URLs already merged into `url` move along with it.
+    #[graphql(guard = "ModeratorGuard")]
+    async fn merge_urls(&self, ctx: &Context<'_>, url: String, into_url: String) -> Result<UrlAlias> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let (normalized_url, url_hash) = normalize_url(&url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let (normalized_into_url, into_hash) = normalize_url(&into_url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+
+        let alias = UrlAliasService::new(pool.clone())
+            .merge(*user_id, (&normalized_url, &url_hash), (&normalized_into_url, &into_hash))
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        info!("Moderator {} merged {} into {}", user_id, alias.alias_url, alias.canonical_url);
+
+        Ok(alias)
+    }
+
+    /// Give a merged URL its own thread again (moderators only); returns
+    /// false if the URL wasn't merged into another
+    #[graphql(guard = "ModeratorGuard")]
+    async fn split_url(&self, ctx: &Context<'_>, url: String) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let (normalized_url, url_hash) = normalize_url(&url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+
+        let split = UrlAliasService::new(pool.clone())
+            .split(&url_hash)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        if split {
+            info!("Moderator {} split {} into its own thread", user_id, normalized_url);
+        }
+
+        Ok(split)
+    }
+}
+
+/// Issues a session for a user who has passed primary authentication, or a
+/// second-factor challenge if the account has TOTP enabled
+async fn start_session(pool: &PgPool, config: &Config, user: User) -> Result<LoginPayload> {
+    if user.totp_enabled {
+        let challenge = generate_secure_token();
+
+        sqlx::query(
+            r#"
+            INSERT INTO mfa_challenges (token_hash, user_id, expires_at)
+            VALUES ($1, $2, NOW() + make_interval(mins => $3))
+            "#
+        )
+        .bind(hash_token(&challenge))
+        .bind(user.id)
+        .bind(MFA_CHALLENGE_TTL_MINUTES as i32)
+        .execute(pool)
+        .await?;
+
+        info!("User {} passed primary authentication, awaiting second factor", user.email);
+
+        return Ok(LoginPayload {
+            token: None,
+            user: None,
+            mfa_required: true,
+            mfa_challenge: Some(challenge),
+        });
+    }
+
+    let auth_service = AuthService::new(config.jwt_secret.clone());
+    let token = auth_service.generate_jwt_token(&user)
+        .map_err(|e| async_graphql::Error::new(format!("Token generation error: {}", e)))?;
+
+    info!("User {} logged in successfully", user.email);
+
+    Ok(LoginPayload {
+        token: Some(token),
+        user: Some(user.for_session()),
+        mfa_required: false,
+        mfa_challenge: None,
+    })
+}
+
+/// Records a new authorization request and returns the provider URL to visit
+async fn begin_oidc(
+    pool: &PgPool,
+    config: &Config,
+    provider: &str,
+    link_user_id: Option<Uuid>,
+) -> Result<OidcAuthorization> {
+    let provider_config = config.oidc.provider(provider)
+        .ok_or_else(|| async_graphql::Error::new("Unknown sign-in provider"))?;
+
+    let oidc_service = OidcService::new(provider_config.clone(), config.oidc.redirect_uri.clone());
+    let metadata = oidc_service.metadata().await
+        .map_err(|e| async_graphql::Error::new(format!("Provider discovery failed: {}", e)))?;
+
+    let state = generate_secure_token();
+    let nonce = generate_secure_token();
+    let pkce = OidcService::generate_pkce();
+
+    sqlx::query(
+        r#"
+        INSERT INTO oidc_auth_requests (state, provider, code_verifier, nonce, link_user_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(mins => $6))
+        "#
+    )
+    .bind(&state)
+    .bind(&provider_config.name)
+    .bind(&pkce.verifier)
+    .bind(&nonce)
+    .bind(link_user_id)
+    .bind(OIDC_REQUEST_TTL_MINUTES as i32)
+    .execute(pool)
+    .await?;
+
+    let authorization_url = oidc_service.authorization_url(&metadata, &state, &nonce, &pkce.challenge)
+        .map_err(|e| async_graphql::Error::new(format!("Provider configuration error: {}", e)))?;
+
+    Ok(OidcAuthorization { authorization_url, state })
+}
+
+/// Consumes an authorization request and exchanges the code for a verified identity
+async fn complete_oidc(
+    pool: &PgPool,
+    config: &Config,
+    state: &str,
+    code: &str,
+) -> Result<(OidcAuthRequest, ExternalIdentity)> {
+    let request = sqlx::query_as::<_, OidcAuthRequest>(
+        r#"
+        DELETE FROM oidc_auth_requests WHERE state = $1 AND expires_at > NOW()
+        RETURNING provider, code_verifier, nonce, link_user_id
+        "#
+    )
+    .bind(state)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| async_graphql::Error::new("Sign-in request is invalid or has expired"))?;
+
+    let provider_config = config.oidc.provider(&request.provider)
+        .ok_or_else(|| async_graphql::Error::new("Unknown sign-in provider"))?;
+
+    let oidc_service = OidcService::new(provider_config.clone(), config.oidc.redirect_uri.clone());
+    let metadata = oidc_service.metadata().await
+        .map_err(|e| async_graphql::Error::new(format!("Provider discovery failed: {}", e)))?;
+
+    let identity = oidc_service
+        .exchange_code(&metadata, code, &request.code_verifier, &request.nonce)
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Sign-in failed: {}", e)))?;
+
+    Ok((request, identity))
+}
+
+/// The account an external sign-in belongs to: the one the identity is
+/// linked to, else the one with the provider-verified email, else a new one.
+/// The identity is linked to the account found or created.
+async fn external_account(pool: &PgPool, config: &Config, provider: &str, identity: &ExternalIdentity) -> Result<User> {
+    let linked_user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT u.* FROM users u
+        JOIN linked_identities li ON li.user_id = u.id
+        WHERE li.provider = $1 AND li.subject = $2
+        "#
+    )
+    .bind(provider)
+    .bind(&identity.subject)
+    .fetch_optional(pool)
+    .await?;
+
+    if let Some(user) = linked_user {
+        return Ok(user);
+    }
+
+    // Only a provider-verified email may be used to match or create an account
+    let email = identity.email.clone()
+        .filter(|_| identity.email_verified)
+        .ok_or_else(|| async_graphql::Error::new("The provider did not supply a verified email address"))?;
+
+    let existing_user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_optional(pool)
+        .await?;
+
+    let user = match existing_user {
+        Some(user) if user.email_verified => user,
+        // Whoever registered the address never proved they own it; the
+        // provider just did, so the account goes to this sign-in
+        Some(user) => claim_unverified_account(pool, user.id).await?,
+        None => {
+            // Provider names are checked like any other; the email's local
+            // part stands in for one that doesn't pass
+            let name = identity.name.as_deref()
+                .and_then(|name| validate_name("name", name).ok())
+                .or_else(|| validate_name("name", email.split('@').next().unwrap_or_default()).ok())
+                .unwrap_or_else(|| "User".to_string());
+
+            let handle = HandleService::new(pool.clone(), config.handle_rename_cooldown_days)
+                .generate(&name)
+                .await
+                .map_err(|e| async_graphql::Error::new(format!("Handle error: {}", e)))?;
+
+            let user = sqlx::query_as::<_, User>(
+                r#"
+                INSERT INTO users (name, email, handle, handle_skeleton, email_verified, created_at, updated_at)
+                VALUES ($1, $2, $3, $4, true, NOW(), NOW())
+                RETURNING *
+                "#
+            )
+            .bind(&name)
+            .bind(&email)
+            .bind(&handle)
+            .bind(handle_skeleton(&handle))
+            .fetch_one(pool)
+            .await?;
+
+            info!("User {} signed up with {}", user.email, provider);
+            user
+        }
+    };
+
+    link_identity(pool, user.id, provider, identity).await?;
+
+    Ok(user)
+}
+
+/// Hands an account whose email was never verified to the owner of that
+/// address: everything its registrant could sign in with is cleared, and
+/// existing sessions, API keys and linked identities are revoked.
+async fn claim_unverified_account(pool: &PgPool, user_id: Uuid) -> Result<User> {
+    let mut tx = pool.begin().await?;
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        UPDATE users
+        SET email_verified = TRUE, password_hash = NULL, totp_secret = NULL, totp_enabled = FALSE,
+            totp_last_used_step = NULL, pending_email = NULL, tokens_valid_after = NOW(), updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#
+    )
+    .bind(user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM linked_identities WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    audit::record_event(&mut *tx, user_id, "unverified_account_claimed", json!({}))
+        .await
+        .map_err(|e| async_graphql::Error::new(format!("Audit log error: {}", e)))?;
+
+    tx.commit().await?;
+
+    info!("Unverified account {} claimed through an external sign-in", user_id);
+
+    Ok(user)
+}
+
+/// Links an external identity to an account. An account has one identity per
+/// provider, and linking a different one requires unlinking the old one first,
+/// so the sign-in can't be handed to another external account.
+async fn link_identity(pool: &PgPool, user_id: Uuid, provider: &str, identity: &ExternalIdentity) -> Result<()> {
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO linked_identities (user_id, provider, subject, email)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        "#
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(pool)
+    .await?;
+
+    if inserted.rows_affected() > 0 {
+        return Ok(());
+    }
+
+    // Already linked: only refresh the email if it is the same identity
+    let refreshed = sqlx::query(
+        "UPDATE linked_identities SET email = $4 WHERE user_id = $1 AND provider = $2 AND subject = $3"
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(pool)
+    .await?;
+
+    if refreshed.rows_affected() == 0 {
+        return Err(async_graphql::Error::new(format!(
+            "This account is already linked to a different {} account; unlink it first",
+            provider
+        )));
+    }
+
+    Ok(())
+}
+
+/// The error a comment write reports to the client; internal errors are
+/// logged instead of shown
+fn comment_error(e: CommentError) -> async_graphql::Error {
+    match e {
+        CommentError::Internal(e) => {
+            warn!("Comment write failed: {:#}", e);
+            async_graphql::Error::new("Internal error")
+        }
+        e => async_graphql::Error::new(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    fn identity(email: &str) -> ExternalIdentity {
+        ExternalIdentity {
+            subject: Uuid::new_v4().to_string(),
+            email: Some(email.to_string()),
+            email_verified: true,
+            name: Some("Victim".to_string()),
+        }
+    }
+
+    async fn linked_providers(pool: &PgPool, user_id: Uuid) -> Vec<String> {
+        sqlx::query_scalar::<_, String>("SELECT provider FROM linked_identities WHERE user_id = $1 ORDER BY provider")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_external_sign_in_claims_unverified_accounts() {
+        let Some(pool) = testing::pool().await else { return };
+        let config = Config::from_env().unwrap();
+
+        // Someone registered the victim's address with a password and 2FA,
+        // linked their own provider account, and never verified the email
+        let squatter_id = testing::create_user(&pool).await;
+        let email = sqlx::query_scalar::<_, String>(
+            r#"
+            UPDATE users SET email_verified = FALSE, password_hash = 'squatter', totp_secret = 'SECRET', totp_enabled = TRUE
+            WHERE id = $1
+            RETURNING email
+            "#
+        )
+        .bind(squatter_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO linked_identities (user_id, provider, subject) VALUES ($1, 'squatter', $2)")
+            .bind(squatter_id)
+            .bind(Uuid::new_v4().to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let user = external_account(&pool, &config, "mock", &identity(&email)).await.unwrap();
+        assert_eq!(user.id, squatter_id);
+        assert!(user.email_verified);
+        assert!(user.password_hash.is_none());
+        assert!(user.totp_secret.is_none() && !user.totp_enabled);
+        assert_eq!(linked_providers(&pool, user.id).await, vec!["mock"]);
+        let tokens_valid_after = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT tokens_valid_after FROM users WHERE id = $1")
+            .bind(user.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(tokens_valid_after.is_some());
+
+        // A verified account keeps its password when a provider is linked
+        let owner_id = testing::create_user(&pool).await;
+        let email = sqlx::query_scalar::<_, String>("UPDATE users SET password_hash = 'owner' WHERE id = $1 RETURNING email")
+            .bind(owner_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let user = external_account(&pool, &config, "mock", &identity(&email)).await.unwrap();
+        assert_eq!(user.id, owner_id);
+        assert_eq!(user.password_hash.as_deref(), Some("owner"));
+        assert_eq!(linked_providers(&pool, user.id).await, vec!["mock"]);
+    }
+}
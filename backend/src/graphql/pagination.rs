@@ -0,0 +1,141 @@
+use async_graphql::connection::{Connection, CursorType, Edge};
+use async_graphql::OutputType;
+use chrono::{DateTime, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: i32 = 20;
+pub const MAX_PAGE_SIZE: i32 = 100;
+
+/// Opaque keyset cursor for lists ordered newest first by (timestamp, id)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeCursor {
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl CursorType for TimeCursor {
+    type Error = &'static str;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        let decoded = BASE64URL_NOPAD.decode(s.as_bytes()).map_err(|_| "Invalid cursor")?;
+        let decoded = String::from_utf8(decoded).map_err(|_| "Invalid cursor")?;
+        let (micros, id) = decoded.split_once(':').ok_or("Invalid cursor")?;
+
+        Ok(TimeCursor {
+            at: micros.parse().ok().and_then(DateTime::from_timestamp_micros).ok_or("Invalid cursor")?,
+            id: id.parse().map_err(|_| "Invalid cursor")?,
+        })
+    }
+
+    fn encode_cursor(&self) -> String {
+        BASE64URL_NOPAD.encode(format!("{}:{}", self.at.timestamp_micros(), self.id).as_bytes())
+    }
+}
+
+/// Keyset cursor for search results ordered by relevance, then newest first.
+/// The rank is stored as its exact bit pattern so it compares equal when bound back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankCursor {
+    pub rank: f32,
+    pub at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl CursorType for RankCursor {
+    type Error = &'static str;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        let decoded = BASE64URL_NOPAD.decode(s.as_bytes()).map_err(|_| "Invalid cursor")?;
+        let decoded = String::from_utf8(decoded).map_err(|_| "Invalid cursor")?;
+        let (rank, rest) = decoded.split_once(':').ok_or("Invalid cursor")?;
+        let time = TimeCursor::decode_cursor(&BASE64URL_NOPAD.encode(rest.as_bytes()))?;
+
+        Ok(RankCursor {
+            rank: u32::from_str_radix(rank, 16).map(f32::from_bits).map_err(|_| "Invalid cursor")?,
+            at: time.at,
+            id: time.id,
+        })
+    }
+
+    fn encode_cursor(&self) -> String {
+        BASE64URL_NOPAD.encode(
+            format!("{:08x}:{}:{}", self.rank.to_bits(), self.at.timestamp_micros(), self.id).as_bytes(),
+        )
+    }
+}
+
+/// Validated page size and decoded `after` cursor for a forward-paginated query
+pub fn page_args<C: CursorType>(first: Option<i32>, after: Option<String>) -> async_graphql::Result<(i64, Option<C>)>
+where
+    C::Error: std::fmt::Display,
+{
+    let first = first.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(0..=MAX_PAGE_SIZE).contains(&first) {
+        return Err(async_graphql::Error::new(format!("first must be between 0 and {}", MAX_PAGE_SIZE)));
+    }
+
+    let after = after
+        .map(|after| C::decode_cursor(&after))
+        .transpose()
+        .map_err(|e| async_graphql::Error::new(e.to_string()))?;
+
+    Ok((first as i64, after))
+}
+
+/// Builds a connection from rows fetched with `LIMIT first + 1`; the extra row,
+/// if present, only signals that another page exists
+pub fn connection<C: CursorType + Send + Sync, T, N: OutputType>(
+    mut rows: Vec<T>,
+    first: i64,
+    after: Option<C>,
+    cursor: impl Fn(&T) -> C,
+    node: impl Fn(T) -> N,
+) -> Connection<C, N> {
+    let has_next_page = rows.len() as i64 > first;
+    rows.truncate(first as usize);
+
+    let mut connection = Connection::new(after.is_some(), has_next_page);
+    connection.edges.extend(rows.into_iter().map(|row| Edge::new(cursor(&row), node(row))));
+    connection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_cursor_round_trip() {
+        let cursor = TimeCursor {
+            at: DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        assert_eq!(TimeCursor::decode_cursor(&cursor.encode_cursor()), Ok(cursor));
+        assert!(TimeCursor::decode_cursor("not a cursor").is_err());
+        assert!(TimeCursor::decode_cursor(&BASE64URL_NOPAD.encode(b"12:nope")).is_err());
+    }
+
+    #[test]
+    fn test_rank_cursor_round_trip() {
+        let cursor = RankCursor {
+            rank: 0.0607927,
+            at: DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+
+        let decoded = RankCursor::decode_cursor(&cursor.encode_cursor()).unwrap();
+        assert_eq!(decoded.rank.to_bits(), cursor.rank.to_bits());
+        assert_eq!(decoded, cursor);
+        assert!(RankCursor::decode_cursor(&cursor.at.timestamp_micros().to_string()).is_err());
+    }
+
+    #[test]
+    fn test_page_args() {
+        assert_eq!(page_args::<TimeCursor>(None, None).unwrap(), (DEFAULT_PAGE_SIZE as i64, None));
+        assert!(page_args::<TimeCursor>(Some(-1), None).is_err());
+        assert!(page_args::<TimeCursor>(Some(MAX_PAGE_SIZE + 1), None).is_err());
+        assert!(page_args::<TimeCursor>(Some(10), Some("garbage".to_string())).is_err());
+        assert!(page_args::<RankCursor>(Some(10), Some("garbage".to_string())).is_err());
+    }
+}
@@ -1,7 +1,25 @@
-use crate::models::{Comment, User};
-use crate::utils::normalize_url;
+use crate::config::Config;
+use crate::graphql::guards::{ModeratorGuard, ScopeGuard, SessionGuard};
+use crate::graphql::pagination::{connection, page_args, RankCursor, TimeCursor};
+use crate::models::{
+    ApiKey, Comment, CommentReport, DataExport, DomainActivity, DomainPage, HashCommentCount, PageSort, SearchFilter,
+    PendingUrlAlias, SearchResult, UrlAlias, UrlCommentCount, User,
+};
+use crate::services::api_keys::SCOPE_COMMENTS_READ;
+use crate::services::comment_counts::{
+    hash_prefix, CommentCountService, HASH_PREFIX_LEN, MAX_COUNT_URLS,
+};
+use crate::services::domains::DomainService;
+use crate::services::follows::FollowService;
+use crate::services::handles::HandleService;
+use crate::services::reports::ReportService;
+use crate::services::search::{search_language, snippet_html, SearchFilters, SearchHit, SearchService};
+use crate::services::url_aliases::UrlAliasService;
+use crate::utils::{normalize_host, normalize_url, registrable_domain, url_domain};
+use async_graphql::connection::Connection;
 use async_graphql::{Context, Object, Result};
 use sqlx::PgPool;
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[derive(Default)]
@@ -28,6 +46,49 @@ impl Query {
         }
     }
 
+    /// The current user's API keys, including revoked and expired ones
+    #[graphql(guard = "SessionGuard")]
+    async fn api_keys(&self, ctx: &Context<'_>) -> Result<Vec<ApiKey>> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let api_keys = sqlx::query_as::<_, ApiKey>(
+            "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(*user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(api_keys)
+    }
+
+    /// The current user's data export requests, newest first
+    #[graphql(guard = "SessionGuard")]
+    async fn data_exports(&self, ctx: &Context<'_>) -> Result<Vec<DataExport>> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let exports = sqlx::query_as::<_, DataExport>(
+            "SELECT * FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(*user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(exports)
+    }
+
+    /// Names of the external sign-in providers configured on this server
+    async fn oidc_providers(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        let config = ctx.data::<Config>()?;
+
+        Ok(config.oidc.providers.iter().map(|provider| provider.name.clone()).collect())
+    }
+
     /// Get user profile by ID
     async fn user_profile(&self, ctx: &Context<'_>, user_id: Uuid) -> Result<Option<User>> {
         let pool = ctx.data::<PgPool>()?;
@@ -42,29 +103,217 @@ impl Query {
         Ok(user)
     }
 
-    /// Get comments for a specific URL
+    /// Look up a user by handle, case-insensitively. Former handles resolve to
+    /// their owner; compare the returned handle to detect a redirect.
+    async fn user_by_handle(&self, ctx: &Context<'_>, handle: String) -> Result<Option<User>> {
+        let pool = ctx.data::<PgPool>()?;
+        let config = ctx.data::<Config>()?;
+
+        let user = HandleService::new(pool.clone(), config.handle_rename_cooldown_days)
+            .find(&handle)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Handle lookup error: {}", e)))?;
+
+        Ok(user)
+    }
+
+    /// Get comments for a specific URL, including comments left on URLs merged
+    /// into the same thread (AMP versions, pages with a rel=canonical link)
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
     async fn comments_for_url(&self, ctx: &Context<'_>, url: String) -> Result<Vec<Comment>> {
         let pool = ctx.data::<PgPool>()?;
         
         // Normalize the URL to ensure consistent grouping
         let (_normalized_url, url_hash) = normalize_url(&url)
             .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let url_hashes = UrlAliasService::new(pool.clone())
+            .thread_hashes(&url_hash)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
 
+        // Authors blocked by or blocking the viewer are left out; muted ones come back collapsed
         let comments = sqlx::query_as::<_, Comment>(
             r#"
             SELECT c.* FROM comments c
-            WHERE c.url_hash = $1
+            WHERE c.url_hash = ANY($1) AND NOT is_blocked_between($2, c.user_id)
             ORDER BY c.created_at ASC
             "#
         )
-        .bind(&url_hash)
+        .bind(&url_hashes)
+        .bind(ctx.data_opt::<Uuid>())
         .fetch_all(pool)
         .await?;
 
         Ok(comments)
     }
 
+    /// Comments on a URL's thread that are anchored to text on the page, in
+    /// the order they were written
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn annotations_for_url(&self, ctx: &Context<'_>, url: String) -> Result<Vec<Comment>> {
+        let pool = ctx.data::<PgPool>()?;
+        let (_normalized_url, url_hash) = normalize_url(&url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let url_hashes = UrlAliasService::new(pool.clone())
+            .thread_hashes(&url_hash)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        let annotations = sqlx::query_as::<_, Comment>(
+            r#"
+            SELECT c.* FROM comments c
+            WHERE c.url_hash = ANY($1) AND c.anchor IS NOT NULL AND NOT is_blocked_between($2, c.user_id)
+            ORDER BY c.created_at ASC
+            "#
+        )
+        .bind(&url_hashes)
+        .bind(ctx.data_opt::<Uuid>())
+        .fetch_all(pool)
+        .await?;
+
+        Ok(annotations)
+    }
+
+    /// URLs merged into the same thread as `url`, each naming the thread's canonical URL
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn url_aliases(&self, ctx: &Context<'_>, url: String) -> Result<Vec<UrlAlias>> {
+        let pool = ctx.data::<PgPool>()?;
+        let (_normalized_url, url_hash) = normalize_url(&url)
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+
+        let alias_service = UrlAliasService::new(pool.clone());
+        let canonical_hash = alias_service
+            .resolve(&url_hash)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+        let aliases = alias_service
+            .aliases_of(&canonical_hash)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        Ok(aliases)
+    }
+
+    /// Comment counts for many URLs at once, for toolbar badges and "N
+    /// comments" link decorations. Each count covers the URL's whole thread,
+    /// including URLs merged into it. Counts are the same for every viewer,
+    /// so responses may be cached. URLs that can't be parsed are left out.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)", cache_control(max_age = 60))]
+    async fn comment_counts(&self, ctx: &Context<'_>, urls: Vec<String>) -> Result<Vec<UrlCommentCount>> {
+        let pool = ctx.data::<PgPool>()?;
+        if urls.len() > MAX_COUNT_URLS {
+            return Err(async_graphql::Error::new(format!("At most {} URLs can be counted at once", MAX_COUNT_URLS)));
+        }
+
+        let mut seen = HashSet::new();
+        let urls: Vec<(String, String)> = urls
+            .iter()
+            .filter_map(|url| normalize_url(url).ok())
+            .filter(|(_, url_hash)| seen.insert(url_hash.clone()))
+            .collect();
+
+        let counts = CommentCountService::new(pool.clone())
+            .counts(&urls)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Comment count error: {}", e)))?;
+
+        Ok(counts)
+    }
+
+    /// Comment counts looked up by url_hash prefix, so clients don't reveal
+    /// which page is open: every hash in the thread of a commented page that
+    /// starts with one of `prefixes` (4 hex digits) is returned, and
+    /// the client picks out its own hash. Clients compute url_hash with the
+    /// rules served at /url-rules.toml.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)", cache_control(max_age = 60))]
+    async fn comment_counts_by_hash_prefix(&self, ctx: &Context<'_>, prefixes: Vec<String>) -> Result<Vec<HashCommentCount>> {
+        let pool = ctx.data::<PgPool>()?;
+        if prefixes.len() > MAX_COUNT_URLS {
+            return Err(async_graphql::Error::new(format!("At most {} prefixes can be looked up at once", MAX_COUNT_URLS)));
+        }
+
+        let prefixes = prefixes
+            .iter()
+            .map(|prefix| hash_prefix(prefix.trim()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| async_graphql::Error::new(format!(
+                "Hash prefixes must be {} hex digits",
+                HASH_PREFIX_LEN
+            )))?;
+
+        let counts = CommentCountService::new(pool.clone())
+            .by_hash_prefix(&prefixes)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Comment count error: {}", e)))?;
+
+        Ok(counts)
+    }
+
+    /// Comment counts and activity across every page of a site. `domain` may be
+    /// any host or URL on the site; it is reduced to its registrable domain.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn domain_activity(&self, ctx: &Context<'_>, domain: String) -> Result<DomainActivity> {
+        let pool = ctx.data::<PgPool>()?;
+        let domain = domain_arg(&domain)?;
+
+        let activity = DomainService::new(pool.clone())
+            .activity(&domain, ctx.data_opt::<Uuid>().copied())
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Domain error: {}", e)))?;
+
+        Ok(activity)
+    }
+
+    /// Commented pages on a site. `excludeUrl` leaves out the page the
+    /// client is on, for "discussion elsewhere on this site" lists.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn pages_for_domain(
+        &self,
+        ctx: &Context<'_>,
+        domain: String,
+        #[graphql(default_with = "PageSort::Recent")] sort: PageSort,
+        exclude_url: Option<String>,
+        limit: Option<i32>,
+    ) -> Result<Vec<DomainPage>> {
+        let pool = ctx.data::<PgPool>()?;
+        let domain = domain_arg(&domain)?;
+        let limit = limit.unwrap_or(20).clamp(1, 100);
+        let exclude_url_hash = exclude_url
+            .map(|url| normalize_url(&url).map(|(_, hash)| hash))
+            .transpose()
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+
+        let pages = DomainService::new(pool.clone())
+            .pages(&domain, sort, exclude_url_hash.as_deref(), ctx.data_opt::<Uuid>().copied(), limit as i64)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Domain error: {}", e)))?;
+
+        Ok(pages)
+    }
+
+    /// Comments anywhere on a site, newest first
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn comments_for_domain(
+        &self,
+        ctx: &Context<'_>,
+        domain: String,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<TimeCursor, Comment>> {
+        let pool = ctx.data::<PgPool>()?;
+        let domain = domain_arg(&domain)?;
+        let (first, after) = page_args(first, after)?;
+
+        let comments = DomainService::new(pool.clone())
+            .comments(&domain, ctx.data_opt::<Uuid>().copied(), after, first + 1)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Domain error: {}", e)))?;
+
+        Ok(connection(comments, first, after, |c| TimeCursor { at: c.created_at, id: c.id }, |c| c))
+    }
+
     /// Get comments by user ID
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
     async fn user_comments(&self, ctx: &Context<'_>, user_id: Uuid, limit: Option<i32>) -> Result<Vec<Comment>> {
         let pool = ctx.data::<PgPool>()?;
         let limit = limit.unwrap_or(50).min(100); // Default to 50, max 100
@@ -72,60 +321,150 @@ impl Query {
         let comments = sqlx::query_as::<_, Comment>(
             r#"
             SELECT * FROM comments 
-            WHERE user_id = $1 
+            WHERE user_id = $1 AND NOT is_blocked_between($3, user_id)
             ORDER BY created_at DESC 
             LIMIT $2
             "#
         )
         .bind(user_id)
         .bind(limit as i64)
+        .bind(ctx.data_opt::<Uuid>())
         .fetch_all(pool)
         .await?;
 
         Ok(comments)
     }
 
+    /// Recent comments by users the current user follows, across all URLs,
+    /// newest first
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
+    async fn feed(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<TimeCursor, Comment>> {
+        let pool = ctx.data::<PgPool>()?;
+
+        let user_id = ctx.data::<Uuid>()
+            .map_err(|_| async_graphql::Error::new("Authentication required"))?;
+
+        let (first, after) = page_args(first, after)?;
+        let comments = FollowService::new(pool.clone())
+            .feed(*user_id, after, first + 1)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Feed error: {}", e)))?;
+
+        Ok(connection(comments, first, after, |c| TimeCursor { at: c.created_at, id: c.id }, |c| c))
+    }
+
     /// Get replies to a specific comment
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
     async fn comment_replies(&self, ctx: &Context<'_>, parent_id: Uuid) -> Result<Vec<Comment>> {
         let pool = ctx.data::<PgPool>()?;
         
         let replies = sqlx::query_as::<_, Comment>(
             r#"
             SELECT * FROM comments 
-            WHERE parent_id = $1 
+            WHERE parent_id = $1 AND NOT is_blocked_between($2, user_id)
             ORDER BY created_at ASC
             "#
         )
         .bind(parent_id)
+        .bind(ctx.data_opt::<Uuid>())
         .fetch_all(pool)
         .await?;
 
         Ok(replies)
     }
 
-    /// Search comments by content
+    /// Full-text search over comments, most relevant first. `query` takes web
+    /// search syntax: "quoted phrases", `or`, and -excluded words.
+    #[graphql(guard = "ScopeGuard::new(SCOPE_COMMENTS_READ)")]
     async fn search_comments(
-        &self, 
-        ctx: &Context<'_>, 
-        search_term: String,
-        limit: Option<i32>
-    ) -> Result<Vec<Comment>> {
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        #[graphql(default)] filter: SearchFilter,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<RankCursor, SearchResult>> {
         let pool = ctx.data::<PgPool>()?;
-        let limit = limit.unwrap_or(20).min(50); // Default to 20, max 50
-        
-        let comments = sqlx::query_as::<_, Comment>(
-            r#"
-            SELECT * FROM comments 
-            WHERE content ILIKE $1 
-            ORDER BY created_at DESC 
-            LIMIT $2
-            "#
-        )
-        .bind(format!("%{}%", search_term))
-        .bind(limit as i64)
-        .fetch_all(pool)
-        .await?;
+        let (first, after) = page_args(first, after)?;
 
-        Ok(comments)
+        let query = query.trim();
+        if query.is_empty() || query.len() > 500 {
+            return Err(async_graphql::Error::new("Search query must be between 1 and 500 characters"));
+        }
+
+        let url_hash = filter.url
+            .map(|url| normalize_url(&url).map(|(_, hash)| hash))
+            .transpose()
+            .map_err(|e| async_graphql::Error::new(format!("Invalid URL: {}", e)))?;
+        let language = filter.language
+            .map(|language| search_language(&language).ok_or_else(|| async_graphql::Error::new("Unsupported language")))
+            .transpose()?;
+
+        let filters = SearchFilters {
+            domain: filter.domain.map(|domain| normalize_host(domain.trim())),
+            url_hash,
+            author_id: filter.author_id,
+            since: filter.since,
+            until: filter.until,
+            language,
+        };
+
+        let hits = SearchService::new(pool.clone())
+            .search(query, &filters, ctx.data_opt::<Uuid>().copied(), after, first + 1)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Search error: {}", e)))?;
+
+        Ok(connection(hits, first, after, SearchHit::cursor, |hit| SearchResult {
+            snippet: snippet_html(&hit.snippet),
+            rank: hit.rank,
+            comment: hit.comment,
+        }))
+    }
+
+    /// Open comment reports for review (moderators only), comments with the
+    /// heaviest combined report weight first
+    #[graphql(guard = "ModeratorGuard")]
+    async fn open_reports(&self, ctx: &Context<'_>, limit: Option<i32>) -> Result<Vec<CommentReport>> {
+        let pool = ctx.data::<PgPool>()?;
+        let limit = limit.unwrap_or(50).clamp(1, 200);
+
+        let reports = ReportService::new(pool.clone())
+            .open_reports(limit as i64)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("Report error: {}", e)))?;
+
+        Ok(reports)
     }
+
+    /// Canonical URLs reported for pages that don't have enough reporters
+    /// to merge yet, most reported first (moderators only)
+    #[graphql(guard = "ModeratorGuard")]
+    async fn pending_url_aliases(&self, ctx: &Context<'_>, limit: Option<i32>) -> Result<Vec<PendingUrlAlias>> {
+        let pool = ctx.data::<PgPool>()?;
+        let limit = limit.unwrap_or(50).clamp(1, 200);
+
+        let pending = UrlAliasService::new(pool.clone())
+            .pending(limit as i64)
+            .await
+            .map_err(|e| async_graphql::Error::new(format!("URL alias error: {}", e)))?;
+
+        Ok(pending)
+    }
+}
+
+/// Registrable domain from a `domain` argument holding either a host or a URL
+fn domain_arg(input: &str) -> Result<String> {
+    let input = input.trim();
+    let domain = if input.contains("://") {
+        url_domain(input)
+    } else {
+        Some(registrable_domain(input)).filter(|domain| !domain.is_empty() && !domain.contains(['/', ' ']))
+    };
+
+    domain.ok_or_else(|| async_graphql::Error::new("Invalid domain"))
 }
\ No newline at end of file
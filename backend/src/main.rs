@@ -1,50 +1,185 @@
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer, Result, HttpRequest};
-use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig, MultipartOptions};
+use async_graphql::dataloader::DataLoader;
 use async_graphql::{EmptySubscription, Schema};
 use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use serde::Deserialize;
 use tracing::{info, warn};
+use uuid::Uuid;
 
+mod annotation_protocol;
+mod cli;
 mod config;
 mod database;
 mod graphql;
 mod models;
 mod services;
+mod url_rules;
 mod utils;
+mod validation;
 
 use config::Config;
+use graphql::loaders::{PageLoader, UserLoader};
 use graphql::{mutation::Mutation, query::Query, VotpSchema};
+use services::account_deletion::AccountDeletionService;
+use services::api_keys::{ApiKeyAuth, ApiKeyService, API_KEY_PREFIX};
+use services::avatars::identicon_svg;
+use services::blob_store::{self, BlobStore};
+use services::blocks::ViewerMutes;
+use services::data_export::DataExportService;
+use services::reputation::ReputationService;
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often to run background maintenance (account purges, export cleanup,
+/// reputation recalculation)
+const MAINTENANCE_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Deserialize)]
+struct ExportDownloadQuery {
+    expires: i64,
+    signature: String,
+}
+
+/// The user signed in by a request's bearer token, a session JWT or an API
+/// key, along with the key's scopes if it is one. Invalid tokens are logged
+/// and the request treated as anonymous.
+async fn authenticate(http_req: &HttpRequest, config: &Config, pool: &PgPool) -> Option<(Uuid, Option<ApiKeyAuth>)> {
+    let token = http_req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))?;
+
+    if token.starts_with(API_KEY_PREFIX) {
+        match ApiKeyService::new(pool.clone()).authenticate(token).await {
+            Ok(Some(api_key)) => Some((api_key.user_id, Some(api_key))),
+            Ok(None) => {
+                warn!("Invalid or revoked API key");
+                None
+            }
+            Err(e) => {
+                warn!("API key lookup failed: {}", e);
+                None
+            }
+        }
+    } else {
+        // Decode JWT token to get user ID
+        match services::auth::AuthService::new(config.jwt_secret.clone())
+            .authenticate(pool, token)
+            .await {
+            Ok(user_id) => Some((user_id, None)),
+            Err(e) => {
+                warn!("Invalid JWT token: {}", e);
+                None
+            }
+        }
+    }
+}
 
 async fn graphql_handler(
     schema: web::Data<VotpSchema>,
     http_req: HttpRequest,
     req: GraphQLRequest,
     config: web::Data<Config>,
+    pool: web::Data<PgPool>,
 ) -> GraphQLResponse {
     let mut request = req.into_inner();
     
-    // Extract JWT token from Authorization header
-    if let Some(auth_header) = http_req.headers().get("authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..]; // Remove "Bearer " prefix
-                
-                // Decode JWT token to get user ID
-                match services::auth::AuthService::new(config.jwt_secret.clone())
-                    .extract_user_id_from_token(token) {
-                    Ok(user_id) => {
-                        request = request.data(user_id);
-                    }
-                    Err(e) => {
-                        warn!("Invalid JWT token: {}", e);
-                    }
-                }
-            }
+    if let Some((user_id, api_key)) = authenticate(&http_req, &config, &pool).await {
+        request = request.data(user_id).data(ViewerMutes::new(user_id));
+        // An API key's scopes are enforced by the resolvers' guards
+        if let Some(api_key) = api_key {
+            request = request.data(api_key);
         }
     }
     
     schema.execute(request).await.into()
-}async fn graphql_playground() -> Result<actix_web::HttpResponse> {
+}
+
+async fn download_export(
+    path: web::Path<Uuid>,
+    query: web::Query<ExportDownloadQuery>,
+    config: web::Data<Config>,
+    pool: web::Data<PgPool>,
+) -> actix_web::HttpResponse {
+    let export_id = path.into_inner();
+    let export_service = DataExportService::new(pool.get_ref().clone(), config.export_dir.clone().into(), config.jwt_secret.clone());
+
+    if !export_service.verify(export_id, query.expires, &query.signature) {
+        return actix_web::HttpResponse::Forbidden().body("Download link is invalid or has expired");
+    }
+
+    let ready = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS(SELECT 1 FROM data_exports WHERE id = $1 AND status = 'ready')"
+    )
+    .bind(export_id)
+    .fetch_one(pool.get_ref())
+    .await;
+
+    match ready {
+        Ok(true) => {}
+        Ok(false) => return actix_web::HttpResponse::NotFound().body("Export not found"),
+        Err(e) => {
+            warn!("Failed to look up data export {}: {}", export_id, e);
+            return actix_web::HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    match tokio::fs::read(export_service.file_path(export_id)).await {
+        Ok(archive) => actix_web::HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"votp-export-{}.zip\"", export_id),
+            ))
+            .body(archive),
+        Err(e) => {
+            warn!("Failed to read data export {}: {}", export_id, e);
+            actix_web::HttpResponse::NotFound().body("Export not found")
+        }
+    }
+}
+
+async fn download_blob(path: web::Path<String>, store: web::Data<Arc<dyn BlobStore>>) -> actix_web::HttpResponse {
+    let key = path.into_inner();
+
+    match store.get(&key).await {
+        Ok(Some(bytes)) => actix_web::HttpResponse::Ok()
+            .content_type(if key.ends_with(".png") { "image/png" } else { "application/octet-stream" })
+            // Keys are never reused, so blobs can be cached indefinitely
+            .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+            .body(bytes),
+        Ok(None) => actix_web::HttpResponse::NotFound().finish(),
+        Err(e) => {
+            warn!("Failed to read blob {}: {}", key, e);
+            actix_web::HttpResponse::NotFound().finish()
+        }
+    }
+}
+
+async fn identicon(path: web::Path<Uuid>) -> actix_web::HttpResponse {
+    actix_web::HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .insert_header(("Cache-Control", "public, max-age=86400"))
+        .body(identicon_svg(&path.into_inner().to_string()))
+}
+
+/// The URL rules in use, so clients can compute url_hash themselves and look
+/// up comment counts by hash prefix without sending the URL
+async fn url_rules_file() -> actix_web::HttpResponse {
+    let rules = url_rules::current();
+
+    actix_web::HttpResponse::Ok()
+        .content_type("application/toml; charset=utf-8")
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .insert_header(("ETag", format!("\"{}\"", rules.version())))
+        .body(rules.source().to_string())
+}
+
+async fn graphql_playground() -> Result<actix_web::HttpResponse> {
     let source = playground_source(GraphQLPlaygroundConfig::new("/api"));
     Ok(actix_web::HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
@@ -70,10 +205,84 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to run migrations");
 
+    let blob_store = blob_store::from_config(&config.blob_store, &config.public_base_url);
+
+    // Load URL rules before anything normalizes a URL
+    if let Some(path) = &config.url_rules_file {
+        let rules = url_rules::UrlRules::load(path.as_ref()).expect("Failed to load URL rules");
+        url_rules::install(rules);
+    }
+
+    // Run an admin command instead of the server if one was given
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args, &pool, &config).await {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Purge accounts whose deletion grace period has ended and expired data exports,
+    // fail exports whose build was lost, and recompute reputation so account age
+    // keeps counting
+    let maintenance_pool = pool.clone();
+    let maintenance_config = config.clone();
+    let maintenance_store = blob_store.clone();
+    actix_web::rt::spawn(async move {
+        let deletion_service = AccountDeletionService::new(
+            maintenance_pool.clone(),
+            maintenance_store,
+            DataExportService::new(
+                maintenance_pool.clone(),
+                maintenance_config.export_dir.clone().into(),
+                maintenance_config.jwt_secret.clone(),
+            ),
+        );
+        let reputation_service = ReputationService::new(maintenance_pool.clone());
+        let export_service = DataExportService::new(
+            maintenance_pool,
+            maintenance_config.export_dir.clone().into(),
+            maintenance_config.jwt_secret.clone(),
+        );
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(MAINTENANCE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match deletion_service.purge_due_accounts().await {
+                Ok(0) => {}
+                Ok(purged) => info!("Purged {} deleted accounts", purged),
+                Err(e) => warn!("Account purge failed: {}", e),
+            }
+            match export_service.cleanup_expired().await {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} expired data exports", removed),
+                Err(e) => warn!("Data export cleanup failed: {}", e),
+            }
+            match export_service.fail_stalled().await {
+                Ok(0) => {}
+                Ok(failed) => info!("Failed {} stalled data exports", failed),
+                Err(e) => warn!("Stalled data export check failed: {}", e),
+            }
+            match reputation_service.recompute_due().await {
+                Ok(0) => {}
+                Ok(updated) => info!("Recomputed reputation for {} users", updated),
+                Err(e) => warn!("Reputation recalculation failed: {}", e),
+            }
+        }
+    });
+
+    // Pick up edits to the URL rules file without a restart
+    if let Some(path) = &config.url_rules_file {
+        url_rules::watch(path.into(), Duration::from_secs(config.url_rules_reload_secs.max(1)));
+    }
+
     // Create GraphQL schema
-    let schema = Schema::build(Query::default(), Mutation::default(), EmptySubscription)
+    let schema = Schema::build(Query, Mutation, EmptySubscription)
         .data(pool.clone())
         .data(config.clone())
+        .data(blob_store.clone())
+        .data(DataLoader::new(UserLoader::new(pool.clone()), tokio::spawn))
+        .data(DataLoader::new(PageLoader::new(pool.clone()), tokio::spawn))
         .finish();
 
     info!("Starting server on {}:{}", config.host, config.port);
@@ -85,17 +294,45 @@ async fn main() -> std::io::Result<()> {
             .allow_any_origin()
             .allow_any_method()
             .allow_any_header()
+            .expose_headers(["ETag", "Location", "Link", "Allow", "Accept-Post", "Preference-Applied"])
             .max_age(3600);
 
         App::new()
             .app_data(web::Data::new(schema.clone()))
             .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(blob_store.clone()))
+            .app_data(
+                MultipartOptions::default()
+                    .max_file_size(config.avatar_max_bytes)
+                    .max_num_files(1),
+            )
             .wrap(cors)
             .service(
                 web::resource("/api")
                     .route(web::post().to(graphql_handler))
                     .route(web::get().to(graphql_handler)),
             )
+            .service(web::resource("/exports/{id}").route(web::get().to(download_export)))
+            .service(web::resource("/blobs/{key:.*}").route(web::get().to(download_blob)))
+            .service(web::resource("/identicons/{id}.svg").route(web::get().to(identicon)))
+            .service(web::resource("/url-rules.toml").route(web::get().to(url_rules_file)))
+            .service(web::resource("/annotations/pages").route(web::get().to(annotation_protocol::find_container)))
+            .service(
+                web::resource("/annotations/pages/{url_hash}/")
+                    .route(web::get().to(annotation_protocol::get_container))
+                    .route(web::head().to(annotation_protocol::get_container))
+                    .route(web::post().to(annotation_protocol::create_annotation))
+                    .route(web::method(actix_web::http::Method::OPTIONS).to(annotation_protocol::container_options)),
+            )
+            .service(
+                web::resource("/annotations/{id}")
+                    .route(web::get().to(annotation_protocol::get_annotation))
+                    .route(web::head().to(annotation_protocol::get_annotation))
+                    .route(web::put().to(annotation_protocol::update_annotation))
+                    .route(web::delete().to(annotation_protocol::delete_annotation))
+                    .route(web::method(actix_web::http::Method::OPTIONS).to(annotation_protocol::annotation_options)),
+            )
             .service(web::resource("/playground").route(web::get().to(graphql_playground)))
     })
     .bind(bind_address)?
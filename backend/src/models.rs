@@ -1,7 +1,22 @@
-use async_graphql::{SimpleObject, InputObject, Object};
+use crate::config::Config;
+use crate::graphql::loaders::{PageLoader, UserLoader};
+use crate::graphql::pagination::{connection, page_args, TimeCursor};
+use crate::services::annotations::{annotation_json_ld, TextAnchor};
+use crate::services::avatars::{variant_key, variant_size};
+use crate::services::blob_store::BlobStore;
+use crate::services::blocks::{BlockService, ViewerMutes};
+use crate::services::data_export::DataExportService;
+use crate::services::follows::{FollowRow, FollowService};
+use crate::services::reputation::TrustLevel;
+use crate::services::votes::VoteService;
+use async_graphql::connection::Connection;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{ComplexObject, Context, Enum, SimpleObject, InputObject, Object, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use std::sync::Arc;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -11,23 +26,284 @@ pub struct User {
     pub email: String,
     pub phone_number: Option<String>,
     pub bio: Option<String>,
-    pub password_hash: String,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
     pub email_verified: bool,
     pub verification_code: Option<String>,
     pub verification_code_expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    #[serde(skip)]
+    pub totp_last_used_step: Option<i64>,
+    pub deletion_scheduled_for: Option<DateTime<Utc>>,
+    pub pending_email: Option<String>,
+    pub handle: String,
+    pub handle_changed_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub avatar_key: Option<String>,
+    pub show_in_feeds: bool,
+    pub show_connections: bool,
+    pub reputation: i32,
+    pub trust_level: i16,
+    pub is_moderator: bool,
+    /// Set on the user a sign-in returns, whose request carried no token yet
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub session_owner: bool,
+}
+
+impl User {
+    /// The user as returned to the person who just signed in as them
+    pub fn for_session(self) -> Self {
+        Self { session_owner: true, ..self }
+    }
+
+    /// Whether the viewer is this user, for fields only the account owner sees
+    fn viewed_by_owner(&self, ctx: &Context<'_>) -> bool {
+        self.session_owner || ctx.data_opt::<Uuid>() == Some(&self.id)
+    }
 }
 
 #[Object]
 impl User {
     async fn id(&self) -> &Uuid { &self.id }
     async fn name(&self) -> &String { &self.name }
-    async fn email(&self) -> &String { &self.email }
-    async fn phone_number(&self) -> &Option<String> { &self.phone_number }
+    /// Unique, case-insensitive public handle, used for mentions and profile links
+    async fn handle(&self) -> &String { &self.handle }
+    /// Only visible to the account owner
+    async fn email(&self, ctx: &Context<'_>) -> Option<&String> {
+        self.viewed_by_owner(ctx).then_some(&self.email)
+    }
+    /// Only visible to the account owner
+    async fn phone_number(&self, ctx: &Context<'_>) -> Option<&String> {
+        if !self.viewed_by_owner(ctx) {
+            return None;
+        }
+        self.phone_number.as_ref()
+    }
     async fn bio(&self) -> &Option<String> { &self.bio }
     async fn created_at(&self) -> &DateTime<Utc> { &self.created_at }
     async fn updated_at(&self) -> &DateTime<Utc> { &self.updated_at }
+    /// Whether two-factor authentication is on (only visible to the account owner)
+    async fn totp_enabled(&self, ctx: &Context<'_>) -> Option<bool> {
+        if !self.viewed_by_owner(ctx) {
+            return None;
+        }
+        Some(self.totp_enabled)
+    }
+    /// New address awaiting confirmation through confirmEmailChange
+    async fn pending_email(&self, ctx: &Context<'_>) -> Option<&String> {
+        if !self.viewed_by_owner(ctx) {
+            return None;
+        }
+        self.pending_email.as_ref()
+    }
+    /// When a pending account deletion takes effect, if one has been requested
+    /// (only visible to the account owner)
+    async fn deletion_scheduled_for(&self, ctx: &Context<'_>) -> Option<&DateTime<Utc>> {
+        if !self.viewed_by_owner(ctx) {
+            return None;
+        }
+        self.deletion_scheduled_for.as_ref()
+    }
+    /// False for passwordless-only accounts that sign in with emailed codes
+    async fn has_password(&self) -> bool { self.password_hash.is_some() }
+    /// Score from votes received, accepted reports and account age
+    async fn reputation(&self) -> i32 { self.reputation }
+    /// Privileges earned through reputation: links, posting rate, report weight and edit window
+    async fn trust_level(&self) -> TrustLevel { TrustLevel::from_i16(self.trust_level) }
+    async fn is_moderator(&self) -> bool { self.is_moderator }
+
+    /// Square avatar of at least `size` pixels (32, 64 or 128 are stored),
+    /// or a generated identicon if the user hasn't uploaded one
+    async fn avatar_url(&self, ctx: &Context<'_>, #[graphql(default = 64)] size: i32) -> Result<String> {
+        let size = variant_size(size);
+
+        if let Some(avatar_key) = &self.avatar_key {
+            let store = ctx.data::<Arc<dyn BlobStore>>()?;
+            return Ok(store.url(&variant_key(avatar_key, size)));
+        }
+
+        let config = ctx.data::<Config>()?;
+        Ok(format!("{}/identicons/{}.svg", config.public_base_url.trim_end_matches('/'), self.id))
+    }
+
+    /// Privacy settings (only visible to the account owner)
+    async fn privacy_settings(&self, ctx: &Context<'_>) -> Option<PrivacySettings> {
+        if !self.viewed_by_owner(ctx) {
+            return None;
+        }
+        Some(PrivacySettings {
+            show_in_feeds: self.show_in_feeds,
+            show_connections: self.show_connections,
+        })
+    }
+
+    /// Users following this user, most recent first. Empty unless the user
+    /// shows their connections or is the viewer.
+    async fn followers(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<TimeCursor, User>> {
+        let (first, after) = page_args(first, after)?;
+        if !self.show_connections && !self.viewed_by_owner(ctx) {
+            return Ok(Connection::new(false, false));
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        let rows = FollowService::new(pool.clone()).followers(self.id, after, first + 1).await?;
+        Ok(connection(rows, first, after, FollowRow::cursor, |row| row.user))
+    }
+
+    /// Users this user follows, most recent first. Empty unless the user
+    /// shows their connections or is the viewer.
+    async fn following(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<TimeCursor, User>> {
+        let (first, after) = page_args(first, after)?;
+        if !self.show_connections && !self.viewed_by_owner(ctx) {
+            return Ok(Connection::new(false, false));
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        let rows = FollowService::new(pool.clone()).following(self.id, after, first + 1).await?;
+        Ok(connection(rows, first, after, FollowRow::cursor, |row| row.user))
+    }
+
+    /// Whether the signed-in viewer follows this user
+    async fn viewer_follows(&self, ctx: &Context<'_>) -> Result<bool> {
+        let Some(viewer_id) = ctx.data_opt::<Uuid>() else {
+            return Ok(false);
+        };
+
+        let pool = ctx.data::<PgPool>()?;
+        Ok(FollowService::new(pool.clone()).is_following(*viewer_id, self.id).await?)
+    }
+
+    /// Users this user has blocked (only visible to the account owner)
+    async fn blocked_users(&self, ctx: &Context<'_>) -> Result<Vec<User>> {
+        if !self.viewed_by_owner(ctx) {
+            return Ok(vec![]);
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        Ok(BlockService::new(pool.clone()).blocked_users(self.id).await?)
+    }
+
+    /// Users this user has muted (only visible to the account owner)
+    async fn muted_users(&self, ctx: &Context<'_>) -> Result<Vec<User>> {
+        if !self.viewed_by_owner(ctx) {
+            return Ok(vec![]);
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        Ok(BlockService::new(pool.clone()).muted_users(self.id).await?)
+    }
+
+    /// External sign-in providers linked to this account (only visible to the account owner)
+    async fn linked_identities(&self, ctx: &Context<'_>) -> Result<Vec<LinkedIdentity>> {
+        if !self.viewed_by_owner(ctx) {
+            return Ok(vec![]);
+        }
+
+        let pool = ctx.data::<PgPool>()?;
+        let identities = sqlx::query_as::<_, LinkedIdentity>(
+            "SELECT * FROM linked_identities WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(identities)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct PrivacySettings {
+    /// Whether followers see this user's comments in their feed
+    pub show_in_feeds: bool,
+    /// Whether other users can list this user's followers and followed users
+    pub show_connections: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct LinkedIdentity {
+    pub id: Uuid,
+    #[graphql(skip)]
+    pub user_id: Uuid,
+    pub provider: String,
+    #[graphql(skip)]
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct ApiKey {
+    pub id: Uuid,
+    #[graphql(skip)]
+    pub user_id: Uuid,
+    pub name: String,
+    /// Leading characters of the key, shown so users can tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A newly created API key. The full key is only ever returned here.
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct CreatedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[Object]
+impl DataExport {
+    async fn id(&self) -> &Uuid { &self.id }
+    /// One of pending, ready, failed or expired
+    async fn status(&self) -> &String { &self.status }
+    async fn created_at(&self) -> &DateTime<Utc> { &self.created_at }
+    async fn completed_at(&self) -> &Option<DateTime<Utc>> { &self.completed_at }
+
+    /// Signed, expiring link to download the archive once it is ready
+    async fn download_url(&self, ctx: &Context<'_>) -> Result<Option<String>> {
+        if self.status != "ready" {
+            return Ok(None);
+        }
+
+        let config = ctx.data::<Config>()?;
+        let pool = ctx.data::<PgPool>()?;
+        let export_service = DataExportService::new(pool.clone(), config.export_dir.clone().into(), config.jwt_secret.clone());
+
+        let expires = (Utc::now() + chrono::Duration::hours(config.export_link_ttl_hours)).timestamp();
+        Ok(Some(format!(
+            "{}/exports/{}?expires={}&signature={}",
+            config.public_base_url.trim_end_matches('/'),
+            self.id,
+            expires,
+            export_service.sign(self.id, expires)
+        )))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -41,6 +317,9 @@ pub struct Comment {
     pub parent_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub language: Option<String>,
+    pub domain: Option<String>,
+    pub anchor: Option<Json<TextAnchor>>,
 }
 
 #[Object]
@@ -53,6 +332,200 @@ impl Comment {
     async fn parent_id(&self) -> &Option<Uuid> { &self.parent_id }
     async fn created_at(&self) -> &DateTime<Utc> { &self.created_at }
     async fn updated_at(&self) -> &DateTime<Utc> { &self.updated_at }
+    /// ISO 639-1 code given by the author, used to stem the comment for search
+    async fn language(&self) -> &Option<String> { &self.language }
+    /// Registrable domain of the page, e.g. bbc.co.uk for news.bbc.co.uk
+    async fn domain(&self) -> &Option<String> { &self.domain }
+    /// The text on the page the comment is about; None for the whole page
+    async fn anchor(&self) -> Option<&TextAnchor> { self.anchor.as_deref() }
+
+    /// This comment as a W3C Web Annotation (JSON-LD)
+    async fn annotation(&self, ctx: &Context<'_>) -> Result<async_graphql::Json<serde_json::Value>> {
+        let author = ctx.data::<DataLoader<UserLoader>>()?.load_one(self.user_id).await?;
+        let config = ctx.data::<Config>()?;
+        Ok(async_graphql::Json(annotation_json_ld(self, author.as_ref(), &config.public_base_url)))
+    }
+
+    /// True when the viewer has muted the author; clients should show the comment collapsed
+    async fn collapsed(&self, ctx: &Context<'_>) -> Result<bool> {
+        let Some(viewer_mutes) = ctx.data_opt::<ViewerMutes>() else {
+            return Ok(false);
+        };
+
+        let pool = ctx.data::<PgPool>()?;
+        Ok(viewer_mutes.contains(pool, self.user_id).await?)
+    }
+
+    /// Net votes received
+    async fn score(&self, ctx: &Context<'_>) -> Result<i64> {
+        let pool = ctx.data::<PgPool>()?;
+        Ok(VoteService::new(pool.clone()).score(self.id).await?)
+    }
+
+    /// The signed-in viewer's vote on this comment: 1, -1, or 0 if none
+    async fn viewer_vote(&self, ctx: &Context<'_>) -> Result<i32> {
+        let Some(viewer_id) = ctx.data_opt::<Uuid>() else {
+            return Ok(0);
+        };
+
+        let pool = ctx.data::<PgPool>()?;
+        Ok(VoteService::new(pool.clone()).vote_of(*viewer_id, self.id).await?.into())
+    }
+
+    /// Title, site name and favicon of the page the comment is on, if a
+    /// commenter's client supplied them
+    async fn page(&self, ctx: &Context<'_>) -> Result<Option<Page>> {
+        Ok(ctx.data::<DataLoader<PageLoader>>()?.load_one(self.url_hash.clone()).await?)
+    }
+
+    /// The comment's author, for display name, handle and avatar
+    async fn author(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        Ok(ctx.data::<DataLoader<UserLoader>>()?.load_one(self.user_id).await?)
+    }
+}
+
+/// Summary of discussion across every page of a site
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct DomainActivity {
+    pub domain: String,
+    pub comment_count: i64,
+    pub page_count: i64,
+    pub commenter_count: i64,
+    pub first_comment_at: Option<DateTime<Utc>>,
+    pub last_comment_at: Option<DateTime<Utc>>,
+}
+
+/// A commented page on a site
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+#[graphql(complex)]
+pub struct DomainPage {
+    #[graphql(skip)]
+    pub url_hash: String,
+    pub normalized_url: String,
+    pub comment_count: i64,
+    pub last_comment_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl DomainPage {
+    /// Title, site name and favicon of the page, if known
+    async fn page(&self, ctx: &Context<'_>) -> Result<Option<Page>> {
+        Ok(ctx.data::<DataLoader<PageLoader>>()?.load_one(self.url_hash.clone()).await?)
+    }
+}
+
+/// How much discussion a URL's thread has
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct UrlCommentCount {
+    pub normalized_url: String,
+    pub url_hash: String,
+    pub comment_count: i64,
+    /// When the newest comment in the thread was written; None without comments
+    pub last_activity_at: Option<DateTime<Utc>>,
+}
+
+/// Comment count for a URL known only by its hash, from a hash prefix lookup
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct HashCommentCount {
+    pub url_hash: String,
+    pub comment_count: i64,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+/// What a commented page is, as reported by commenters' clients
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct Page {
+    #[graphql(skip)]
+    pub url_hash: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    /// BCP 47 language tag of the page, e.g. en-GB
+    pub language: Option<String>,
+    pub favicon_url: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSort {
+    /// Most recently commented first
+    Recent,
+    MostComments,
+}
+
+/// A comment matching a search, with its relevance and a highlighted excerpt
+#[derive(Debug, Clone, SimpleObject)]
+pub struct SearchResult {
+    pub comment: Comment,
+    pub rank: f32,
+    /// HTML-escaped excerpt with matched terms wrapped in <mark> tags
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct SearchFilter {
+    /// Only comments on this site or its subdomains
+    pub domain: Option<String>,
+    /// Only comments on this page
+    pub url: Option<String>,
+    pub author_id: Option<Uuid>,
+    /// Only comments posted at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only comments posted before this time
+    pub until: Option<DateTime<Utc>>,
+    /// ISO 639-1 code; stems the query for that language and only matches comments in it
+    pub language: Option<String>,
+}
+
+/// A user's report of a comment, reviewed by moderators
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct CommentReport {
+    pub id: Uuid,
+    /// None once the comment has been deleted
+    pub comment_id: Option<Uuid>,
+    #[graphql(skip)]
+    pub reporter_id: Uuid,
+    pub author_id: Uuid,
+    pub reason: String,
+    /// Weight from the reporter's trust level
+    pub weight: i32,
+    /// One of open, accepted or rejected
+    pub status: String,
+    #[graphql(skip)]
+    pub resolved_by: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A URL whose comments are shown in another URL's thread
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct UrlAlias {
+    #[graphql(skip)]
+    pub alias_hash: String,
+    pub alias_url: String,
+    #[graphql(skip)]
+    pub canonical_hash: String,
+    pub canonical_url: String,
+    /// "reported" from a page's canonical link, or "moderator" for a manual merge
+    pub source: String,
+    #[graphql(skip)]
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A canonical URL clients reported for a page that hasn't had enough
+/// reporters yet to merge the threads
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, SimpleObject)]
+pub struct PendingUrlAlias {
+    #[graphql(skip)]
+    pub alias_hash: String,
+    pub alias_url: String,
+    #[graphql(skip)]
+    pub canonical_hash: String,
+    pub canonical_url: String,
+    /// Distinct accounts that reported it
+    pub reporters: i64,
+    pub first_reported_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
@@ -61,6 +534,37 @@ pub struct AuthPayload {
     pub user: User,
 }
 
+/// Result of a password login: either a full session, or a second-factor
+/// challenge to be exchanged through `completeLogin`
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct LoginPayload {
+    pub token: Option<String>,
+    pub user: Option<User>,
+    pub mfa_required: bool,
+    pub mfa_challenge: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct OidcAuthRequest {
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub link_user_id: Option<Uuid>,
+}
+
+/// Where to send the browser to sign in with an external provider
+#[derive(Debug, Clone, Serialize, Deserialize, SimpleObject)]
+pub struct OidcAuthorization {
+    pub authorization_url: String,
+    pub state: String,
+}
+
 #[derive(Debug, Clone, InputObject)]
 pub struct UpdateProfileInput {
     pub name: Option<String>,
@@ -68,6 +572,25 @@ pub struct UpdateProfileInput {
     pub bio: Option<String>,
 }
 
+/// Page metadata read by the client from the page being commented on, e.g.
+/// from <title>, og:site_name and <link rel="icon">. Values are cleaned up
+/// server-side; ones that can't be are dropped.
+#[derive(Debug, Clone, Default, InputObject)]
+pub struct PageMetadataInput {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub language: Option<String>,
+    /// May be relative to the page's URL
+    pub favicon_url: Option<String>,
+}
+
+#[derive(Debug, Clone, InputObject)]
+pub struct UpdatePrivacySettingsInput {
+    pub show_in_feeds: Option<bool>,
+    pub show_connections: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String, // Subject (user id)
@@ -76,9 +599,74 @@ pub struct Claims {
     pub email: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VerificationCode {
-    pub email: String,
-    pub code: String,
-    pub expires_at: DateTime<Utc>,
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    async fn comment_authors(pool: &PgPool, url: &str, viewer_id: Option<Uuid>) -> Vec<serde_json::Value> {
+        let mut request = async_graphql::Request::new(format!(
+            r#"{{ commentsForUrl(url: "{}") {{ author {{ email phoneNumber }} }} }}"#,
+            url
+        ));
+        if let Some(viewer_id) = viewer_id {
+            request = request.data(viewer_id).data(ViewerMutes::new(viewer_id));
+        }
+        let response = testing::schema(pool).execute(request).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        data["commentsForUrl"].as_array().unwrap().iter().map(|comment| comment["author"].clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_contact_details_are_only_shown_to_their_owner() {
+        let Some(pool) = testing::pool().await else { return };
+        let (author, reader) = (testing::create_user(&pool).await, testing::create_user(&pool).await);
+        sqlx::query("UPDATE users SET phone_number = '+15555550100' WHERE id = $1")
+            .bind(author)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let url = testing::unique_url();
+        testing::create_comment(&pool, author, &url).await;
+
+        for viewer_id in [None, Some(reader)] {
+            let authors = comment_authors(&pool, &url, viewer_id).await;
+            assert_eq!(authors, vec![serde_json::json!({ "email": null, "phoneNumber": null })]);
+        }
+        let authors = comment_authors(&pool, &url, Some(author)).await;
+        assert_eq!(authors[0]["phoneNumber"], "+15555550100");
+        assert!(authors[0]["email"].as_str().is_some_and(|email| email.ends_with("@votp.test")));
+    }
+
+    #[tokio::test]
+    async fn test_profiles_and_connections_hide_contact_details() {
+        let Some(pool) = testing::pool().await else { return };
+        let (alice, bob) = (testing::create_user(&pool).await, testing::create_user(&pool).await);
+        FollowService::new(pool.clone()).follow(alice, bob).await.unwrap();
+        let handles = sqlx::query_scalar::<_, String>("SELECT handle FROM users WHERE id = ANY($1) ORDER BY id = $2 DESC")
+            .bind([alice, bob])
+            .bind(alice)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+
+        for handle in handles {
+            let query = format!(
+                r#"{{ userByHandle(handle: "{}") {{ email phoneNumber followers {{ nodes {{ email }} }} following {{ nodes {{ email }} }} }} }}"#,
+                handle
+            );
+            let request = async_graphql::Request::new(query).data(Config::from_env().unwrap());
+            let response = testing::schema(&pool).execute(request).await;
+            assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+            let user = &response.data.into_json().unwrap()["userByHandle"];
+            assert_eq!(user["email"], serde_json::Value::Null);
+            assert_eq!(user["phoneNumber"], serde_json::Value::Null);
+            let connections = [&user["followers"]["nodes"], &user["following"]["nodes"]];
+            assert_eq!(connections.iter().map(|nodes| nodes.as_array().unwrap().len()).sum::<usize>(), 1);
+            assert!(connections.iter().flat_map(|nodes| nodes.as_array().unwrap()).all(|node| node["email"].is_null()));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::services::audit;
+use crate::services::avatars::AvatarService;
+use crate::services::blob_store::BlobStore;
+use crate::services::data_export::DataExportService;
+use crate::validation::handle_skeleton;
+use anyhow::Result;
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Stand-in author for comments whose account has been deleted
+pub const DELETED_USER_ID: Uuid = Uuid::nil();
+
+/// Purges accounts whose deletion grace period has ended
+pub struct AccountDeletionService {
+    pool: PgPool,
+    store: Arc<dyn BlobStore>,
+    exports: DataExportService,
+}
+
+impl AccountDeletionService {
+    pub fn new(pool: PgPool, store: Arc<dyn BlobStore>, exports: DataExportService) -> Self {
+        Self { pool, store, exports }
+    }
+
+    /// Purges every account that is due, returning how many were purged
+    pub async fn purge_due_accounts(&self) -> Result<usize> {
+        let due = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM users WHERE deletion_scheduled_for IS NOT NULL AND deletion_scheduled_for <= NOW()"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut purged = 0;
+        for user_id in due {
+            match self.purge_account(user_id).await {
+                Ok(()) => purged += 1,
+                Err(e) => error!("Failed to purge account {}: {}", user_id, e),
+            }
+        }
+
+        Ok(purged)
+    }
+
+    /// Removes the account and its personal data. Comments are reattributed
+    /// to the deleted-user tombstone, or removed if the user asked for that.
+    pub async fn purge_account(&self, user_id: Uuid) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        // Re-check under lock in case the deletion was cancelled meanwhile
+        let delete_comments = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT deletion_delete_comments FROM users
+            WHERE id = $1 AND deletion_scheduled_for <= NOW()
+            FOR UPDATE
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(delete_comments) = delete_comments else {
+            return Ok(());
+        };
+
+        let avatar_key = sqlx::query_scalar::<_, Option<String>>("SELECT avatar_key FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, name, email, email_verified, handle, handle_skeleton)
+            VALUES ($1, '[deleted]', 'deleted-user@votp.invalid', FALSE, 'deleted', $2)
+            ON CONFLICT (id) DO NOTHING
+            "#
+        )
+        .bind(DELETED_USER_ID)
+        .bind(handle_skeleton("deleted"))
+        .execute(&mut *tx)
+        .await?;
+
+        if delete_comments {
+            // Comments with replies from others anywhere below them are blanked
+            // rather than deleted, so ON DELETE CASCADE doesn't take the thread with them
+            let blanked = sqlx::query(
+                r#"
+                WITH RECURSIVE subtree AS (
+                    SELECT id AS root_id, id FROM comments WHERE user_id = $1
+                    UNION ALL
+                    SELECT s.root_id, c.id FROM comments c JOIN subtree s ON c.parent_id = s.id
+                )
+                UPDATE comments SET content = '[deleted]', user_id = $2
+                WHERE id IN (
+                    SELECT s.root_id FROM subtree s
+                    JOIN comments c ON c.id = s.id
+                    WHERE c.user_id <> $1
+                )
+                "#
+            )
+            .bind(user_id)
+            .bind(DELETED_USER_ID)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+            let removed = sqlx::query("DELETE FROM comments WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+
+            audit::record_event(
+                &mut *tx,
+                user_id,
+                "comments_removed",
+                json!({ "removed": removed, "blanked": blanked }),
+            )
+            .await?;
+        } else {
+            let reattributed = sqlx::query("UPDATE comments SET user_id = $2 WHERE user_id = $1")
+                .bind(user_id)
+                .bind(DELETED_USER_ID)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+
+            audit::record_event(
+                &mut *tx,
+                user_id,
+                "comments_reattributed",
+                json!({ "reattributed": reattributed }),
+            )
+            .await?;
+        }
+
+        // Export archives live outside the database, so note them before
+        // their rows cascade away
+        let export_ids = sqlx::query_scalar::<_, Uuid>("SELECT id FROM data_exports WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        // Cascades to identities, API keys, recovery codes, challenges and exports
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        audit::record_event(&mut *tx, user_id, "account_purged", json!({})).await?;
+
+        tx.commit().await?;
+
+        self.exports.remove_archives(&export_ids).await;
+
+        if let Some(avatar_key) = avatar_key {
+            AvatarService::new(self.pool.clone(), self.store.clone())
+                .delete_variants(&avatar_key)
+                .await;
+        }
+
+        info!("Purged account {}", user_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,420 @@
+use crate::models::{Comment, User};
+use anyhow::{anyhow, bail, Result};
+use async_graphql::{InputObject, SimpleObject};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// JSON-LD context of the W3C Web Annotation Data Model
+pub const ANNOTATION_CONTEXT: &str = "http://www.w3.org/ns/anno.jsonld";
+
+const MAX_QUOTE_CHARS: usize = 2000;
+const MAX_AFFIX_CHARS: usize = 256;
+const MAX_CSS_SELECTOR_CHARS: usize = 500;
+
+/// Selector types an anchor can be built from; others, such as Hypothesis'
+/// RangeSelector, are skipped when reading annotations from other tools
+const SUPPORTED_SELECTORS: [&str; 3] = ["TextQuoteSelector", "TextPositionSelector", "CssSelector"];
+
+/// The quoted text, with a little of the text around it to tell repeated
+/// quotes apart
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject, InputObject)]
+#[graphql(input_name = "TextQuoteSelectorInput")]
+pub struct TextQuoteSelector {
+    pub exact: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+}
+
+/// Offsets of the text in the page's text content, counted in Unicode code
+/// points; `end` is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, SimpleObject, InputObject)]
+#[graphql(input_name = "TextPositionSelectorInput")]
+pub struct TextPositionSelector {
+    pub start: i32,
+    pub end: i32,
+}
+
+/// The part of a page a comment is about, as W3C Web Annotation selectors
+/// that each describe the same text. Serialized as the annotation's
+/// `selector` array, which is how anchors are stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SimpleObject, InputObject)]
+#[serde(try_from = "Vec<Selector>", into = "Vec<Selector>")]
+#[graphql(input_name = "TextAnchorInput")]
+pub struct TextAnchor {
+    pub quote: Option<TextQuoteSelector>,
+    pub position: Option<TextPositionSelector>,
+    /// CSS selector of the element holding the text, for when the text
+    /// itself can no longer be found
+    pub css: Option<String>,
+}
+
+/// One W3C selector, tagged with its `type` as in JSON-LD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Selector {
+    #[serde(rename = "TextQuoteSelector")]
+    TextQuote(TextQuoteSelector),
+    #[serde(rename = "TextPositionSelector")]
+    TextPosition(TextPositionSelector),
+    #[serde(rename = "CssSelector")]
+    Css { value: String },
+}
+
+impl TryFrom<Vec<Selector>> for TextAnchor {
+    type Error = anyhow::Error;
+
+    fn try_from(selectors: Vec<Selector>) -> Result<Self> {
+        let mut anchor = TextAnchor { quote: None, position: None, css: None };
+        for selector in selectors {
+            let duplicate = match selector {
+                Selector::TextQuote(quote) => anchor.quote.replace(quote).is_some(),
+                Selector::TextPosition(position) => anchor.position.replace(position).is_some(),
+                Selector::Css { value } => anchor.css.replace(value).is_some(),
+            };
+            if duplicate {
+                bail!("an anchor can have only one selector of each type");
+            }
+        }
+
+        anchor.validate()?;
+        Ok(anchor)
+    }
+}
+
+impl From<TextAnchor> for Vec<Selector> {
+    fn from(anchor: TextAnchor) -> Self {
+        let quote = anchor.quote.map(Selector::TextQuote);
+        let position = anchor.position.map(Selector::TextPosition);
+        let css = anchor.css.map(|value| Selector::Css { value });
+
+        quote.into_iter().chain(position).chain(css).collect()
+    }
+}
+
+impl TextAnchor {
+    /// Checks the selectors are usable and agree with each other
+    pub fn validate(&self) -> Result<()> {
+        if self.quote.is_none() && self.position.is_none() && self.css.is_none() {
+            bail!("an anchor needs at least one selector");
+        }
+
+        if let Some(quote) = &self.quote {
+            if quote.exact.trim().is_empty() {
+                bail!("the quoted text can't be empty");
+            }
+            if quote.exact.chars().count() > MAX_QUOTE_CHARS {
+                bail!("the quoted text is too long (max {} characters)", MAX_QUOTE_CHARS);
+            }
+            let affix_too_long = |affix: &Option<String>| affix.as_ref().is_some_and(|affix| affix.chars().count() > MAX_AFFIX_CHARS);
+            if affix_too_long(&quote.prefix) || affix_too_long(&quote.suffix) {
+                bail!("the quote's prefix and suffix can be at most {} characters", MAX_AFFIX_CHARS);
+            }
+        }
+
+        if let Some(position) = self.position {
+            if position.start < 0 || position.end <= position.start {
+                bail!("the text position must have 0 <= start < end");
+            }
+            let quoted_chars = self.quote.as_ref().map(|quote| quote.exact.chars().count());
+            if quoted_chars.is_some_and(|chars| chars != (position.end - position.start) as usize) {
+                bail!("the text position and the quoted text have different lengths");
+            }
+        }
+
+        let css_invalid = |css: &String| {
+            css.trim().is_empty() || css.chars().count() > MAX_CSS_SELECTOR_CHARS || css.chars().any(char::is_control)
+        };
+        if self.css.as_ref().is_some_and(css_invalid) {
+            bail!("the CSS selector must be 1 to {} characters on one line", MAX_CSS_SELECTOR_CHARS);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the anchor from an annotation's `target`, which may be a bare
+    /// IRI or a specific resource whose `selector` is one selector or a list
+    /// of alternatives. Returns None when there is no selector we support.
+    pub fn from_target(target: &Value) -> Result<Option<Self>> {
+        let selectors = match target.get("selector") {
+            Some(Value::Array(selectors)) => selectors.iter().collect(),
+            Some(selector @ Value::Object(_)) => vec![selector],
+            Some(_) => bail!("an annotation's selector must be an object or a list"),
+            None => Vec::new(),
+        };
+
+        let supported = selectors
+            .into_iter()
+            .filter(|selector| selector.get("type").and_then(Value::as_str).is_some_and(|kind| SUPPORTED_SELECTORS.contains(&kind)))
+            .map(|selector| Ok(serde_json::from_value::<Selector>(selector.clone())?))
+            .collect::<Result<Vec<_>>>()?;
+        if supported.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(TextAnchor::try_from(supported)?))
+    }
+}
+
+/// IRI of a comment as an annotation, served by the annotation protocol endpoint
+pub fn annotation_iri(base_url: &str, comment_id: Uuid) -> String {
+    format!("{}/annotations/{}", base_url.trim_end_matches('/'), comment_id)
+}
+
+/// IRI of the annotation container holding the comments on a URL
+pub fn container_iri(base_url: &str, url_hash: &str) -> String {
+    format!("{}/annotations/pages/{}/", base_url.trim_end_matches('/'), url_hash)
+}
+
+/// The comment an IRI from `annotation_iri` names
+pub fn comment_id_from_iri(base_url: &str, iri: &str) -> Option<Uuid> {
+    let prefix = format!("{}/annotations/", base_url.trim_end_matches('/'));
+    iri.strip_prefix(&prefix)?.parse().ok()
+}
+
+/// A comment as a W3C Web Annotation. Replies target the comment they reply
+/// to; other comments target their page, narrowed down by the anchor if any.
+pub fn annotation_json_ld(comment: &Comment, author: Option<&User>, base_url: &str) -> Value {
+    let mut body = json!({
+        "type": "TextualBody",
+        "value": comment.content,
+        "format": "text/plain",
+    });
+    if let Some(language) = &comment.language {
+        body["language"] = json!(language);
+    }
+
+    let target = match (comment.parent_id, &comment.anchor) {
+        (Some(parent_id), _) => json!(annotation_iri(base_url, parent_id)),
+        (None, Some(anchor)) => json!({ "source": comment.normalized_url, "selector": anchor }),
+        (None, None) => json!(comment.normalized_url),
+    };
+
+    let mut creator = json!({ "id": format!("urn:uuid:{}", comment.user_id), "type": "Person" });
+    if let Some(author) = author {
+        creator["name"] = json!(author.name);
+        creator["nickname"] = json!(author.handle);
+    }
+
+    json!({
+        "@context": ANNOTATION_CONTEXT,
+        "id": annotation_iri(base_url, comment.id),
+        "type": "Annotation",
+        "motivation": if comment.parent_id.is_some() { "replying" } else { "commenting" },
+        "created": comment.created_at.to_rfc3339(),
+        "modified": comment.updated_at.to_rfc3339(),
+        "creator": creator,
+        "body": body,
+        "target": target,
+    })
+}
+
+/// What an annotation sent by a client is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotationTarget {
+    /// A page, or text on it
+    Page { source: String, anchor: Option<TextAnchor> },
+    /// Another annotation, which makes this one a reply
+    Reply(Uuid),
+}
+
+/// The parts of an annotation sent by a client that a comment is made from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationInput {
+    pub content: String,
+    pub language: Option<String>,
+    pub target: AnnotationTarget,
+}
+
+/// Reads an annotation sent by a client. It needs one textual body and one
+/// target; ids, creators, dates and motivations are the server's to assign
+/// and are ignored.
+pub fn parse_annotation(annotation: &Value, base_url: &str) -> Result<AnnotationInput> {
+    let is_annotation = match annotation.get("type") {
+        Some(Value::String(kind)) => kind == "Annotation",
+        Some(Value::Array(kinds)) => kinds.iter().any(|kind| kind == "Annotation"),
+        _ => false,
+    };
+    if !is_annotation {
+        bail!("the resource must have type Annotation");
+    }
+
+    let (content, language) = match (annotation.get("bodyValue"), one(annotation.get("body"))?) {
+        (Some(Value::String(value)), _) => (value.clone(), None),
+        (_, Some(body)) => {
+            if body.get("type").is_some_and(|kind| kind != "TextualBody") {
+                bail!("only TextualBody bodies are supported");
+            }
+            let value = body.get("value").and_then(Value::as_str).ok_or_else(|| anyhow!("the annotation body has no value"))?;
+            (value.to_string(), body.get("language").and_then(Value::as_str).map(str::to_string))
+        }
+        _ => bail!("an annotation needs a textual body"),
+    };
+
+    let target = one(annotation.get("target"))?.ok_or_else(|| anyhow!("an annotation needs a target"))?;
+    let source = match target {
+        Value::String(iri) => iri.as_str(),
+        Value::Object(_) => target.get("source").and_then(Value::as_str).ok_or_else(|| anyhow!("the annotation target has no source"))?,
+        _ => bail!("the annotation target must be an IRI or an object"),
+    };
+    let target = match comment_id_from_iri(base_url, source) {
+        Some(parent_id) => AnnotationTarget::Reply(parent_id),
+        None => AnnotationTarget::Page { source: source.to_string(), anchor: TextAnchor::from_target(target)? },
+    };
+
+    Ok(AnnotationInput { content, language, target })
+}
+
+/// A property that may be a value or a list; only lists of one are accepted
+fn one(value: Option<&Value>) -> Result<Option<&Value>> {
+    match value {
+        Some(Value::Array(values)) if values.len() == 1 => Ok(values.first()),
+        Some(Value::Array(_)) => bail!("annotations with several bodies or targets aren't supported"),
+        value => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sqlx::types::Json;
+
+    const BASE_URL: &str = "https://votp.example";
+
+    fn anchor() -> TextAnchor {
+        TextAnchor {
+            quote: Some(TextQuoteSelector {
+                exact: "naïve café".to_string(),
+                prefix: Some("a ".to_string()),
+                suffix: None,
+            }),
+            position: Some(TextPositionSelector { start: 2, end: 12 }),
+            css: Some("article > p:nth-of-type(3)".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_anchor_json_is_a_w3c_selector_list() {
+        assert_eq!(
+            serde_json::to_value(anchor()).unwrap(),
+            json!([
+                { "type": "TextQuoteSelector", "exact": "naïve café", "prefix": "a " },
+                { "type": "TextPositionSelector", "start": 2, "end": 12 },
+                { "type": "CssSelector", "value": "article > p:nth-of-type(3)" },
+            ])
+        );
+        assert_eq!(serde_json::from_value::<TextAnchor>(serde_json::to_value(anchor()).unwrap()).unwrap(), anchor());
+    }
+
+    #[test]
+    fn test_invalid_anchors() {
+        let invalid = [
+            json!([]),
+            json!([{ "type": "TextQuoteSelector", "exact": " " }]),
+            json!([{ "type": "TextPositionSelector", "start": 5, "end": 5 }]),
+            json!([{ "type": "TextPositionSelector", "start": -1, "end": 5 }]),
+            json!([{ "type": "CssSelector", "value": "p\n" }]),
+            json!([{ "type": "CssSelector", "value": "p" }, { "type": "CssSelector", "value": "div" }]),
+            json!([{ "type": "TextQuoteSelector", "exact": "abc" }, { "type": "TextPositionSelector", "start": 0, "end": 4 }]),
+            json!([{ "type": "RangeSelector" }]),
+        ];
+        for anchor in invalid {
+            assert!(serde_json::from_value::<TextAnchor>(anchor.clone()).is_err(), "{} was accepted", anchor);
+        }
+    }
+
+    fn comment() -> Comment {
+        Comment {
+            id: Uuid::new_v4(),
+            content: "Not quite".to_string(),
+            url: "https://example.com/a?utm_source=x".to_string(),
+            normalized_url: "https://example.com/a".to_string(),
+            url_hash: String::new(),
+            user_id: Uuid::new_v4(),
+            parent_id: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            language: Some("en".to_string()),
+            domain: Some("example.com".to_string()),
+            anchor: Some(Json(anchor())),
+        }
+    }
+
+    #[test]
+    fn test_annotation_round_trip() {
+        let comment = comment();
+        let annotation = annotation_json_ld(&comment, None, BASE_URL);
+        assert_eq!(annotation["id"], format!("https://votp.example/annotations/{}", comment.id));
+        assert_eq!(annotation["motivation"], "commenting");
+        assert_eq!(
+            parse_annotation(&annotation, BASE_URL).unwrap(),
+            AnnotationInput {
+                content: "Not quite".to_string(),
+                language: Some("en".to_string()),
+                target: AnnotationTarget::Page { source: "https://example.com/a".to_string(), anchor: Some(anchor()) },
+            }
+        );
+
+        let unanchored = Comment { anchor: None, ..comment.clone() };
+        let annotation = annotation_json_ld(&unanchored, None, BASE_URL);
+        assert_eq!(annotation["target"], "https://example.com/a");
+        assert_eq!(
+            parse_annotation(&annotation, BASE_URL).unwrap().target,
+            AnnotationTarget::Page { source: "https://example.com/a".to_string(), anchor: None }
+        );
+
+        let reply = Comment { parent_id: Some(comment.id), anchor: None, ..comment.clone() };
+        let annotation = annotation_json_ld(&reply, None, BASE_URL);
+        assert_eq!(annotation["motivation"], "replying");
+        assert_eq!(parse_annotation(&annotation, BASE_URL).unwrap().target, AnnotationTarget::Reply(comment.id));
+    }
+
+    #[test]
+    fn test_parse_annotation() {
+        let annotation = json!({
+            "@context": ANNOTATION_CONTEXT,
+            "type": "Annotation",
+            "bodyValue": "Short form",
+            "target": ["https://example.com/b"],
+        });
+        let input = parse_annotation(&annotation, BASE_URL).unwrap();
+        assert_eq!(input.content, "Short form");
+        assert_eq!(input.target, AnnotationTarget::Page { source: "https://example.com/b".to_string(), anchor: None });
+
+        let invalid = [
+            json!({ "type": "Note", "bodyValue": "x", "target": "https://example.com/" }),
+            json!({ "type": "Annotation", "target": "https://example.com/" }),
+            json!({ "type": "Annotation", "bodyValue": "x" }),
+            json!({ "type": "Annotation", "body": { "type": "Image", "id": "https://example.com/i.png" }, "target": "https://example.com/" }),
+            json!({ "type": "Annotation", "bodyValue": "x", "target": ["https://example.com/", "https://example.org/"] }),
+            json!({ "type": "Annotation", "bodyValue": "x", "target": { "selector": { "type": "CssSelector", "value": "p" } } }),
+        ];
+        for annotation in invalid {
+            assert!(parse_annotation(&annotation, BASE_URL).is_err(), "{} was accepted", annotation);
+        }
+    }
+
+    #[test]
+    fn test_targets_from_other_tools() {
+        // Hypothesis-style alternatives: unsupported selectors are skipped
+        let target = json!({
+            "source": "https://example.com/a",
+            "selector": [
+                { "type": "RangeSelector", "startContainer": "/p[1]", "startOffset": 0, "endContainer": "/p[1]", "endOffset": 5 },
+                { "type": "TextQuoteSelector", "exact": "Hello", "prefix": "", "suffix": " world" },
+            ],
+        });
+        let anchor = TextAnchor::from_target(&target).unwrap().unwrap();
+        assert_eq!(anchor.quote.unwrap().exact, "Hello");
+
+        // A single selector object
+        let target = json!({ "source": "https://example.com/a", "selector": { "type": "CssSelector", "value": "#intro" } });
+        assert_eq!(TextAnchor::from_target(&target).unwrap().unwrap().css.as_deref(), Some("#intro"));
+
+        let target = json!({ "source": "https://example.com/a", "selector": "#intro" });
+        assert!(TextAnchor::from_target(&target).is_err());
+    }
+}
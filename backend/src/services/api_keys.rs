@@ -0,0 +1,114 @@
+use crate::utils::hash_token;
+use anyhow::Result;
+use rand::Rng;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Every API key starts with this, so it can be told apart from a JWT
+pub const API_KEY_PREFIX: &str = "votp_";
+
+pub const SCOPE_COMMENTS_READ: &str = "comments:read";
+pub const SCOPE_COMMENTS_WRITE: &str = "comments:write";
+pub const SCOPE_MODERATION: &str = "moderation";
+
+pub const SCOPES: &[&str] = &[SCOPE_COMMENTS_READ, SCOPE_COMMENTS_WRITE, SCOPE_MODERATION];
+
+/// Length of the random identifier stored in clear and shown to the user
+const DISPLAY_PREFIX_LEN: usize = 8;
+const KEY_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The identity and scopes of a request authenticated with an API key
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuth {
+    pub user_id: Uuid,
+    pub scopes: Vec<String>,
+}
+
+impl ApiKeyAuth {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+pub struct ApiKeyService {
+    pool: PgPool,
+}
+
+impl ApiKeyService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Generates a new key, returning the full key and its display prefix
+    pub fn generate_key() -> (String, String) {
+        let mut rng = rand::rng();
+        let random: String = (0..40)
+            .map(|_| KEY_ALPHABET[rng.random_range(0..KEY_ALPHABET.len())] as char)
+            .collect();
+        let key = format!("{}{}", API_KEY_PREFIX, random);
+        let prefix = key[..API_KEY_PREFIX.len() + DISPLAY_PREFIX_LEN].to_string();
+        (key, prefix)
+    }
+
+    /// Rejects unknown or duplicate scopes and returns them in canonical order
+    pub fn validate_scopes(scopes: &[String]) -> Result<Vec<String>> {
+        if scopes.is_empty() {
+            return Err(anyhow::anyhow!("At least one scope is required"));
+        }
+
+        if let Some(unknown) = scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+            return Err(anyhow::anyhow!("Unknown scope: {}", unknown));
+        }
+
+        Ok(SCOPES
+            .iter()
+            .filter(|scope| scopes.iter().any(|s| s == *scope))
+            .map(|scope| scope.to_string())
+            .collect())
+    }
+
+    /// Looks up an active key and records its use
+    pub async fn authenticate(&self, key: &str) -> Result<Option<ApiKeyAuth>> {
+        let row = sqlx::query_as::<_, (Uuid, Vec<String>)>(
+            r#"
+            UPDATE api_keys SET last_used_at = NOW()
+            WHERE key_hash = $1
+              AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING user_id, scopes
+            "#,
+        )
+        .bind(hash_token(key))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(user_id, scopes)| ApiKeyAuth { user_id, scopes }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_key_format() {
+        let (key, prefix) = ApiKeyService::generate_key();
+
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(key.len(), API_KEY_PREFIX.len() + 40);
+        assert!(key.starts_with(&prefix));
+        assert_eq!(prefix.len(), API_KEY_PREFIX.len() + DISPLAY_PREFIX_LEN);
+    }
+
+    #[test]
+    fn test_validate_scopes() {
+        let scopes = vec![SCOPE_MODERATION.to_string(), SCOPE_COMMENTS_READ.to_string()];
+        assert_eq!(
+            ApiKeyService::validate_scopes(&scopes).unwrap(),
+            vec![SCOPE_COMMENTS_READ.to_string(), SCOPE_MODERATION.to_string()]
+        );
+
+        assert!(ApiKeyService::validate_scopes(&[]).is_err());
+        assert!(ApiKeyService::validate_scopes(&["admin".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+use anyhow::Result;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// Appends an entry to the account audit log.
+///
+/// Entries outlive the account they describe, so `details` must never
+/// contain personal data such as names or email addresses.
+pub async fn record_event<'e, E: PgExecutor<'e>>(
+    executor: E,
+    user_id: Uuid,
+    event: &str,
+    details: serde_json::Value,
+) -> Result<()> {
+    sqlx::query("INSERT INTO account_audit_log (user_id, event, details) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(event)
+        .bind(details)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
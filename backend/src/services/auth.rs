@@ -1,9 +1,10 @@
 use crate::models::{Claims, User};
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use argon2::password_hash::{rand_core::OsRng, SaltString};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use anyhow::Result;
+use sqlx::PgPool;
 use uuid::Uuid;
 
 pub struct AuthService {
@@ -70,12 +71,31 @@ impl AuthService {
         Ok(token_data.claims)
     }
 
-    pub fn extract_user_id_from_token(&self, token: &str) -> Result<Uuid> {
-        let claims = self.verify_jwt_token(token)?;
+    pub fn extract_user_id_from_claims(&self, claims: &Claims) -> Result<Uuid> {
         let user_id = Uuid::parse_str(&claims.sub)
             .map_err(|e| anyhow::anyhow!("Invalid user ID in token: {}", e))?;
         Ok(user_id)
     }
+
+    /// Verifies a token and checks it hasn't been revoked since it was issued
+    pub async fn authenticate(&self, pool: &PgPool, token: &str) -> Result<Uuid> {
+        let claims = self.verify_jwt_token(token)?;
+        let user_id = self.extract_user_id_from_claims(&claims)?;
+
+        let tokens_valid_after = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            "SELECT tokens_valid_after FROM users WHERE id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Token belongs to a deleted account"))?;
+
+        if tokens_valid_after.is_some_and(|cutoff| (claims.iat as i64) < cutoff.timestamp()) {
+            return Err(anyhow::anyhow!("Token has been revoked"));
+        }
+
+        Ok(user_id)
+    }
 }
 
 #[cfg(test)]
@@ -92,12 +112,26 @@ mod tests {
             email: "test@example.com".to_string(),
             phone_number: None,
             bio: None,
-            password_hash: "hashed_password".to_string(),
+            password_hash: Some("hashed_password".to_string()),
             email_verified: true,
             verification_code: None,
             verification_code_expires_at: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_used_step: None,
+            deletion_scheduled_for: None,
+            pending_email: None,
+            handle: "test_user".to_string(),
+            handle_changed_at: None,
+            avatar_key: None,
+            show_in_feeds: true,
+            show_connections: true,
+            reputation: 0,
+            trust_level: 0,
+            is_moderator: false,
+            session_owner: false,
         }
     }
 
@@ -129,7 +163,8 @@ mod tests {
         let user = create_test_user();
 
         let token = auth_service.generate_jwt_token(&user).unwrap();
-        let extracted_id = auth_service.extract_user_id_from_token(&token).unwrap();
+        let claims = auth_service.verify_jwt_token(&token).unwrap();
+        let extracted_id = auth_service.extract_user_id_from_claims(&claims).unwrap();
 
         assert_eq!(extracted_id, user.id);
     }
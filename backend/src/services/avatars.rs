@@ -0,0 +1,257 @@
+use crate::models::User;
+use crate::services::blob_store::BlobStore;
+use crate::validation::FieldError;
+use anyhow::Result;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::io::Cursor;
+use std::sync::Arc;
+use tracing::error;
+use uuid::Uuid;
+
+/// Square variants generated for every avatar, in pixels
+pub const AVATAR_SIZES: [u32; 3] = [32, 64, 128];
+
+/// Largest width or height accepted, to bound decoding memory
+const MAX_DIMENSION: u32 = 4096;
+
+const ACCEPTED_FORMATS: [ImageFormat; 4] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::WebP];
+
+/// Decodes an uploaded image and renders the PNG variants. The format is
+/// sniffed from the content, not trusted from the client, and re-encoding
+/// drops EXIF and other metadata after its orientation has been applied.
+pub fn process_avatar(field: &str, bytes: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, FieldError> {
+    let invalid = || FieldError::new(field, "INVALID_IMAGE", "The file is not a readable image");
+
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format().map_err(|_| invalid())?;
+    if !reader.format().is_some_and(|format| ACCEPTED_FORMATS.contains(&format)) {
+        return Err(FieldError::new(
+            field,
+            "UNSUPPORTED_TYPE",
+            "Avatars must be PNG, JPEG, GIF or WebP images",
+        ));
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(|e| match e {
+        image::ImageError::Limits(_) => FieldError::new(
+            field,
+            "TOO_LARGE",
+            format!("Avatars must be at most {}x{} pixels", MAX_DIMENSION, MAX_DIMENSION),
+        ),
+        _ => invalid(),
+    })?;
+    let orientation = decoder.orientation().map_err(|_| invalid())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|_| invalid())?;
+    image.apply_orientation(orientation);
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let mut png = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .into_rgba8()
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(|_| invalid())?;
+            Ok((size, png))
+        })
+        .collect()
+}
+
+/// Smallest generated variant at least as large as requested
+pub fn variant_size(requested: i32) -> u32 {
+    AVATAR_SIZES
+        .iter()
+        .copied()
+        .find(|&size| i64::from(size) >= i64::from(requested))
+        .unwrap_or(AVATAR_SIZES[AVATAR_SIZES.len() - 1])
+}
+
+pub fn variant_key(avatar_key: &str, size: u32) -> String {
+    format!("{}-{}.png", avatar_key, size)
+}
+
+/// Deterministic 5x5 mirrored identicon for users without an avatar
+pub fn identicon_svg(seed: &str) -> String {
+    let hash = Sha256::digest(seed.as_bytes());
+    let hue = u16::from_be_bytes([hash[0], hash[1]]) % 360;
+
+    let mut cells = String::new();
+    for row in 0..5 {
+        for column in 0..3 {
+            if hash[2 + row * 3 + column] % 2 == 0 {
+                continue;
+            }
+            for x in [column, 4 - column] {
+                cells.push_str(&format!(r#"<rect x="{}" y="{}" width="1" height="1"/>"#, x, row));
+                if x == 2 {
+                    break;
+                }
+            }
+        }
+    }
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-0.5 -0.5 6 6" shape-rendering="crispEdges"><rect x="-0.5" y="-0.5" width="6" height="6" fill="#f0f0f0"/><g fill="hsl({}, 55%, 50%)">{}</g></svg>"##,
+        hue, cells
+    )
+}
+
+/// Stores avatar variants and keeps `users.avatar_key` pointing at the current set
+pub struct AvatarService {
+    pool: PgPool,
+    store: Arc<dyn BlobStore>,
+}
+
+impl AvatarService {
+    pub fn new(pool: PgPool, store: Arc<dyn BlobStore>) -> Self {
+        Self { pool, store }
+    }
+
+    /// Stores a processed avatar under a fresh key, so cached URLs of the old
+    /// one never serve the new image, then removes the previous variants
+    pub async fn replace(&self, user_id: Uuid, variants: Vec<(u32, Vec<u8>)>) -> Result<User> {
+        let avatar_key = format!("avatars/{}/{}", user_id, Uuid::new_v4().simple());
+        for (size, png) in variants {
+            self.store.put(&variant_key(&avatar_key, size), png, "image/png").await?;
+        }
+
+        let previous = sqlx::query_scalar::<_, Option<String>>("SELECT avatar_key FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let user = sqlx::query_as::<_, User>("UPDATE users SET avatar_key = $1 WHERE id = $2 RETURNING *")
+            .bind(&avatar_key)
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        if let Some(previous) = previous {
+            self.delete_variants(&previous).await;
+        }
+
+        Ok(user)
+    }
+
+    /// Clears the avatar so the identicon is shown again
+    pub async fn remove(&self, user_id: Uuid) -> Result<User> {
+        let previous = sqlx::query_scalar::<_, Option<String>>("SELECT avatar_key FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        let user = sqlx::query_as::<_, User>("UPDATE users SET avatar_key = NULL WHERE id = $1 RETURNING *")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        if let Some(previous) = previous {
+            self.delete_variants(&previous).await;
+        }
+
+        Ok(user)
+    }
+
+    /// Best-effort removal; a leftover blob is unreachable once the key is replaced
+    pub async fn delete_variants(&self, avatar_key: &str) {
+        for size in AVATAR_SIZES {
+            let key = variant_key(avatar_key, size);
+            if let Err(e) = self.store.delete(&key).await {
+                error!("Failed to delete avatar blob {}: {}", key, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageEncoder, Rgb, RgbImage};
+
+    fn encode(image: &RgbImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_process_avatar_generates_square_variants() {
+        let image = RgbImage::from_pixel(300, 200, Rgb([200, 30, 30]));
+
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            let variants = process_avatar("file", &encode(&image, format)).unwrap();
+            assert_eq!(variants.iter().map(|(size, _)| *size).collect::<Vec<_>>(), AVATAR_SIZES);
+
+            for (size, png) in variants {
+                let decoded = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+                assert_eq!((decoded.width(), decoded.height()), (size, size));
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_avatar_strips_exif_and_applies_orientation() {
+        // Minimal EXIF block: little-endian TIFF header, one IFD entry with Orientation = 6 (rotate 90° CW)
+        let exif = [
+            b'I', b'I', 42, 0, 8, 0, 0, 0, 1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        // Left half dark, right half light
+        let image = RgbImage::from_fn(40, 20, |x, _| if x < 20 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) });
+
+        let mut jpeg = Vec::new();
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 100);
+        encoder.set_exif_metadata(exif.to_vec()).unwrap();
+        encoder.write_image(image.as_raw(), 40, 20, image::ExtendedColorType::Rgb8).unwrap();
+        assert!(jpeg.windows(4).any(|window| window == b"Exif"));
+
+        let variants = process_avatar("file", &jpeg).unwrap();
+        for (_, png) in &variants {
+            assert!(!png.windows(4).any(|window| window == b"Exif" || window == b"eXIf"));
+        }
+
+        // Rotated 90° clockwise, the dark half ends up on top
+        let decoded = image::load_from_memory(&variants[2].1).unwrap().into_rgba8();
+        assert!(decoded.get_pixel(118, 10)[0] < 128);
+        assert!(decoded.get_pixel(10, 118)[0] > 128);
+    }
+
+    #[test]
+    fn test_process_avatar_rejects_bad_input() {
+        assert_eq!(process_avatar("file", b"not an image").unwrap_err().code, "UNSUPPORTED_TYPE");
+
+        let mut bmp = b"BM".to_vec();
+        bmp.resize(64, 0);
+        assert_eq!(process_avatar("file", &bmp).unwrap_err().code, "UNSUPPORTED_TYPE");
+
+        let mut truncated = encode(&RgbImage::new(64, 64), ImageFormat::Png);
+        truncated.truncate(60);
+        assert_eq!(process_avatar("file", &truncated).unwrap_err().code, "INVALID_IMAGE");
+
+        let huge = encode(&RgbImage::new(MAX_DIMENSION + 1, 1), ImageFormat::Png);
+        assert_eq!(process_avatar("file", &huge).unwrap_err().code, "TOO_LARGE");
+    }
+
+    #[test]
+    fn test_variant_size() {
+        assert_eq!(variant_size(16), 32);
+        assert_eq!(variant_size(32), 32);
+        assert_eq!(variant_size(48), 64);
+        assert_eq!(variant_size(512), 128);
+    }
+
+    #[test]
+    fn test_identicon_is_deterministic() {
+        let id = "3f2b6a3e-8d4f-4a35-9d8e-2b1c0f9e7a61";
+        assert_eq!(identicon_svg(id), identicon_svg(id));
+        assert_ne!(identicon_svg(id), identicon_svg("another-user"));
+        assert!(identicon_svg(id).starts_with("<svg"));
+    }
+}
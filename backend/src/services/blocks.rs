@@ -0,0 +1,251 @@
+use crate::models::User;
+use anyhow::Result;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// Blocks and mutes between users. A block hides both users' comments from
+/// each other and stops them replying to one another; a mute only collapses
+/// the muted author's comments for the user who muted them.
+pub struct BlockService {
+    pool: PgPool,
+}
+
+impl BlockService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Blocks a user and drops any follow between the two; returns false if
+    /// the user doesn't exist
+    pub async fn block(&self, blocker_id: Uuid, blocked_id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
+            .bind(blocked_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !exists {
+            return Ok(false);
+        }
+
+        sqlx::query("INSERT INTO user_blocks (blocker_id, blocked_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM follows
+            WHERE (follower_id = $1 AND followee_id = $2) OR (follower_id = $2 AND followee_id = $1)
+            "#
+        )
+        .bind(blocker_id)
+        .bind(blocked_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn unblock(&self, blocker_id: Uuid, blocked_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2")
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    /// Mutes a user; returns false if the user doesn't exist
+    pub async fn mute(&self, muter_id: Uuid, muted_id: Uuid) -> Result<bool> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_mutes (muter_id, muted_id)
+            SELECT $1, id FROM users WHERE id = $2
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .bind(muter_id)
+        .bind(muted_id)
+        .execute(&self.pool)
+        .await?;
+
+        let muted = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM user_mutes WHERE muter_id = $1 AND muted_id = $2)"
+        )
+        .bind(muter_id)
+        .bind(muted_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(muted)
+    }
+
+    pub async fn unmute(&self, muter_id: Uuid, muted_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM user_mutes WHERE muter_id = $1 AND muted_id = $2")
+            .bind(muter_id)
+            .bind(muted_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    /// True if either user has blocked the other
+    pub async fn is_blocked_between(&self, user_id: Uuid, other_id: Uuid) -> Result<bool> {
+        let blocked = sqlx::query_scalar::<_, bool>("SELECT is_blocked_between($1, $2)")
+            .bind(user_id)
+            .bind(other_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(blocked)
+    }
+
+    pub async fn blocked_users(&self, user_id: Uuid) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT u.* FROM user_blocks b JOIN users u ON u.id = b.blocked_id
+            WHERE b.blocker_id = $1
+            ORDER BY b.created_at DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    pub async fn muted_users(&self, user_id: Uuid) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(
+            r#"
+            SELECT u.* FROM user_mutes m JOIN users u ON u.id = m.muted_id
+            WHERE m.muter_id = $1
+            ORDER BY m.created_at DESC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(users)
+    }
+}
+
+/// Per-request view of whom the signed-in user has muted, loaded on first
+/// use so each comment doesn't cost a query
+pub struct ViewerMutes {
+    user_id: Uuid,
+    muted: OnceCell<HashSet<Uuid>>,
+}
+
+impl ViewerMutes {
+    pub fn new(user_id: Uuid) -> Self {
+        Self { user_id, muted: OnceCell::new() }
+    }
+
+    pub async fn contains(&self, pool: &PgPool, author_id: Uuid) -> Result<bool> {
+        let muted = self.muted
+            .get_or_try_init(|| async {
+                let ids = sqlx::query_scalar::<_, Uuid>("SELECT muted_id FROM user_mutes WHERE muter_id = $1")
+                    .bind(self.user_id)
+                    .fetch_all(pool)
+                    .await?;
+                Ok::<_, anyhow::Error>(ids.into_iter().collect::<HashSet<_>>())
+            })
+            .await?;
+
+        Ok(muted.contains(&author_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::services::follows::FollowService;
+    use serde_json::{json, Value};
+
+    /// Authors of the comments on `url` as `viewer_id` sees them, with
+    /// whether each comes back collapsed
+    async fn visible_comments(pool: &PgPool, url: &str, viewer_id: Uuid) -> Vec<(Uuid, bool)> {
+        let schema = testing::schema(pool);
+        let request = async_graphql::Request::new(format!(r#"{{ commentsForUrl(url: "{}") {{ userId collapsed }} }}"#, url))
+            .data(viewer_id)
+            .data(ViewerMutes::new(viewer_id));
+        let response = schema.execute(request).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        data["commentsForUrl"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|comment: &Value| (comment["userId"].as_str().unwrap().parse().unwrap(), comment["collapsed"] == json!(true)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_block_hides_both_ways_until_unblocked() {
+        let Some(pool) = testing::pool().await else { return };
+        let (alice, bob) = (testing::create_user(&pool).await, testing::create_user(&pool).await);
+        let url = testing::unique_url();
+        testing::create_comment(&pool, alice, &url).await;
+        testing::create_comment(&pool, bob, &url).await;
+        FollowService::new(pool.clone()).follow(bob, alice).await.unwrap();
+
+        let service = BlockService::new(pool.clone());
+        assert!(service.block(alice, bob).await.unwrap());
+        assert!(service.is_blocked_between(alice, bob).await.unwrap());
+        assert!(service.is_blocked_between(bob, alice).await.unwrap());
+        assert_eq!(visible_comments(&pool, &url, alice).await, vec![(alice, false)]);
+        assert_eq!(visible_comments(&pool, &url, bob).await, vec![(bob, false)]);
+
+        // Blocking drops follows in either direction
+        let follows = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM follows WHERE follower_id = $1 AND followee_id = $2")
+            .bind(bob)
+            .bind(alice)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(follows, 0);
+
+        // Only the blocker can lift the block
+        assert!(!service.unblock(bob, alice).await.unwrap());
+        assert!(service.is_blocked_between(bob, alice).await.unwrap());
+        assert!(service.unblock(alice, bob).await.unwrap());
+        assert!(!service.is_blocked_between(alice, bob).await.unwrap());
+        assert!(!service.is_blocked_between(bob, alice).await.unwrap());
+        assert_eq!(visible_comments(&pool, &url, bob).await.len(), 2);
+
+        assert!(!service.block(alice, Uuid::new_v4()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mute_collapses_without_hiding() {
+        let Some(pool) = testing::pool().await else { return };
+        let (alice, bob) = (testing::create_user(&pool).await, testing::create_user(&pool).await);
+        let url = testing::unique_url();
+        testing::create_comment(&pool, alice, &url).await;
+        testing::create_comment(&pool, bob, &url).await;
+
+        let service = BlockService::new(pool.clone());
+        assert!(service.mute(alice, bob).await.unwrap());
+        assert!(!service.is_blocked_between(alice, bob).await.unwrap());
+
+        // Only the muter sees the muted author collapsed; nothing is hidden
+        assert_eq!(visible_comments(&pool, &url, alice).await, vec![(alice, false), (bob, true)]);
+        assert_eq!(visible_comments(&pool, &url, bob).await, vec![(alice, false), (bob, false)]);
+
+        assert!(service.unmute(alice, bob).await.unwrap());
+        assert_eq!(visible_comments(&pool, &url, alice).await, vec![(alice, false), (bob, false)]);
+
+        assert!(!service.mute(alice, Uuid::new_v4()).await.unwrap());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::models::{HashCommentCount, UrlCommentCount};
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Most URLs a single count lookup may ask about
+pub const MAX_COUNT_URLS: usize = 300;
+
+/// Length of the url_hash prefixes clients look counts up by, in hex
+/// digits; fixed, so a lookup can't tell clients apart
+pub const HASH_PREFIX_LEN: usize = 4;
+
+/// A url_hash prefix in the stored form, lowercase hex, if it is one
+pub fn hash_prefix(input: &str) -> Option<String> {
+    let valid = input.len() == HASH_PREFIX_LEN && input.chars().all(|c| c.is_ascii_hexdigit());
+
+    valid.then(|| input.to_ascii_lowercase())
+}
+
+/// Comment counts per URL, read from the url_comment_counts table that a
+/// trigger on comments keeps up to date. Counts cover whole threads, so a
+/// URL merged into another one reports the merged thread's count. They don't
+/// depend on the viewer, which keeps them cacheable.
+pub struct CommentCountService {
+    pool: PgPool,
+}
+
+impl CommentCountService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Counts for (normalized URL, URL hash) pairs, in the order given.
+    /// URLs without comments are returned with a count of zero.
+    pub async fn counts(&self, urls: &[(String, String)]) -> Result<Vec<UrlCommentCount>> {
+        let (normalized_urls, url_hashes): (Vec<&str>, Vec<&str>) =
+            urls.iter().map(|(url, hash)| (url.as_str(), hash.as_str())).unzip();
+
+        let counts = sqlx::query_as::<_, UrlCommentCount>(
+            r#"
+            WITH requested AS (
+                SELECT r.position, r.normalized_url, r.url_hash, COALESCE(a.canonical_hash, r.url_hash) AS canonical_hash
+                FROM UNNEST($1::text[], $2::text[]) WITH ORDINALITY AS r(normalized_url, url_hash, position)
+                LEFT JOIN url_aliases a ON a.alias_hash = r.url_hash
+            ),
+            members AS (
+                SELECT position, canonical_hash AS url_hash FROM requested
+                UNION ALL
+                SELECT r.position, a.alias_hash FROM requested r JOIN url_aliases a ON a.canonical_hash = r.canonical_hash
+            )
+            SELECT
+                r.normalized_url,
+                r.url_hash,
+                COALESCE(SUM(c.comment_count), 0)::BIGINT AS comment_count,
+                MAX(c.last_activity_at) AS last_activity_at
+            FROM requested r
+            JOIN members m ON m.position = r.position
+            LEFT JOIN url_comment_counts c ON c.url_hash = m.url_hash
+            GROUP BY r.position, r.normalized_url, r.url_hash
+            ORDER BY r.position
+            "#
+        )
+        .bind(&normalized_urls)
+        .bind(&url_hashes)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Counts for every URL hash with comments in its thread that starts
+    /// with one of `prefixes`, so clients can look up a page without saying
+    /// which one it is. Prefixes must have been checked with `hash_prefix`.
+    pub async fn by_hash_prefix(&self, prefixes: &[String]) -> Result<Vec<HashCommentCount>> {
+        // Hashes are lowercase hex, so everything starting with a prefix
+        // sorts between the prefix and the prefix followed by 'g'
+        let counts = sqlx::query_as::<_, HashCommentCount>(
+            r#"
+            WITH prefixes AS (
+                SELECT prefix, prefix || 'g' AS bound FROM UNNEST($1::text[]) AS p(prefix)
+            ),
+            candidates AS (
+                SELECT c.url_hash FROM prefixes p JOIN url_comment_counts c ON c.url_hash >= p.prefix AND c.url_hash < p.bound
+                UNION
+                SELECT a.alias_hash FROM prefixes p JOIN url_aliases a ON a.alias_hash >= p.prefix AND a.alias_hash < p.bound
+                UNION
+                SELECT a.canonical_hash FROM prefixes p JOIN url_aliases a ON a.canonical_hash >= p.prefix AND a.canonical_hash < p.bound
+            ),
+            requested AS (
+                SELECT c.url_hash, COALESCE(a.canonical_hash, c.url_hash) AS canonical_hash
+                FROM candidates c
+                LEFT JOIN url_aliases a ON a.alias_hash = c.url_hash
+            ),
+            members AS (
+                SELECT url_hash, canonical_hash AS member FROM requested
+                UNION ALL
+                SELECT r.url_hash, a.alias_hash FROM requested r JOIN url_aliases a ON a.canonical_hash = r.canonical_hash
+            )
+            SELECT
+                m.url_hash,
+                SUM(c.comment_count)::BIGINT AS comment_count,
+                MAX(c.last_activity_at) AS last_activity_at
+            FROM members m
+            JOIN url_comment_counts c ON c.url_hash = m.member
+            GROUP BY m.url_hash
+            ORDER BY m.url_hash
+            "#
+        )
+        .bind(prefixes)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Fills the counter table from the comments if it is empty, as it is
+    /// right after being created. Returns the number of URLs counted.
+    pub async fn backfill(&self) -> Result<u64> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO url_comment_counts (url_hash, comment_count, last_activity_at)
+            SELECT url_hash, COUNT(*), COALESCE(MAX(created_at), NOW())
+            FROM comments
+            WHERE NOT EXISTS (SELECT 1 FROM url_comment_counts)
+            GROUP BY url_hash
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(inserted.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_prefix() {
+        assert_eq!(hash_prefix("4425"), Some("4425".to_string()));
+        assert_eq!(hash_prefix("B8E5"), Some("b8e5".to_string()));
+        // Too short, too long, or not hex
+        assert_eq!(hash_prefix("442"), None);
+        assert_eq!(hash_prefix("4425b"), None);
+        assert_eq!(hash_prefix("4425b8e5"), None);
+        assert_eq!(hash_prefix("44g5"), None);
+        assert_eq!(hash_prefix("+425"), None);
+        assert_eq!(hash_prefix(""), None);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::models::{Comment, PageMetadataInput};
+use crate::services::annotations::TextAnchor;
+use crate::services::blocks::BlockService;
+use crate::services::pages::{PageMetadata, PageService};
+use crate::services::reputation::{contains_link, ReputationService};
+use crate::services::search::search_language;
+use crate::utils::{normalize_url_versioned, url_domain};
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+const MAX_CONTENT_BYTES: usize = 5000;
+
+/// Why a comment couldn't be written. Every variant but `Internal` carries a
+/// message meant for the user.
+#[derive(Debug, thiserror::Error)]
+pub enum CommentError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("You're commenting too quickly, please try again later")]
+    RateLimited,
+    /// The comment was edited since the version the update was based on
+    #[error("The comment has changed")]
+    Stale,
+    #[error("{0:#}")]
+    Internal(#[from] anyhow::Error),
+}
+
+impl From<sqlx::Error> for CommentError {
+    fn from(e: sqlx::Error) -> Self {
+        CommentError::Internal(e.into())
+    }
+}
+
+/// A comment to be written, as the client sent it
+pub struct NewComment {
+    pub content: String,
+    pub url: String,
+    pub parent_id: Option<Uuid>,
+    /// ISO 639-1 code, used to stem the comment for search
+    pub language: Option<String>,
+    pub anchor: Option<TextAnchor>,
+    /// Metadata of the page, as the client read it
+    pub page: Option<PageMetadataInput>,
+}
+
+/// Writes comments on behalf of their authors, enforcing the content rules
+/// and the privileges of the author's trust level. Shared by the GraphQL API
+/// and the Web Annotation Protocol endpoint.
+pub struct CommentService {
+    pool: PgPool,
+}
+
+impl CommentService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, user_id: Uuid, comment: NewComment) -> Result<Comment, CommentError> {
+        let (normalized_url, url_hash, normalizer_version) = normalize_url_versioned(&comment.url)
+            .map_err(|e| CommentError::Invalid(format!("Invalid URL: {}", e)))?;
+
+        validate_content(&comment.content)?;
+
+        let language = comment.language
+            .map(|language| search_language(&language).ok_or_else(|| CommentError::Invalid("Unsupported language".to_string())))
+            .transpose()?;
+
+        if let Some(anchor) = &comment.anchor {
+            if comment.parent_id.is_some() {
+                return Err(CommentError::Invalid("Replies can't be anchored to text".to_string()));
+            }
+            anchor.validate().map_err(|e| CommentError::Invalid(format!("Invalid anchor: {}", e)))?;
+        }
+
+        let page = comment.page.map(|page| PageMetadata::sanitize(&page, &comment.url));
+
+        // Check privileges granted by the author's trust level
+        let trust_level = ReputationService::new(self.pool.clone())
+            .trust_level(user_id)
+            .await
+            .map_err(|e| e.context("Reputation error"))?;
+
+        if !trust_level.can_post_links() && contains_link(&comment.content) {
+            return Err(CommentError::Forbidden("New accounts can't post links yet".to_string()));
+        }
+
+        let recent_comments = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM comments WHERE user_id = $1 AND created_at > NOW() - INTERVAL '1 hour'"
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        if recent_comments >= trust_level.comments_per_hour() {
+            return Err(CommentError::RateLimited);
+        }
+
+        // If parent_id is provided, verify it exists and that neither author has blocked the other
+        if let Some(parent_id) = comment.parent_id {
+            let parent_author = sqlx::query_scalar::<_, Uuid>(
+                "SELECT user_id FROM comments WHERE id = $1"
+            )
+            .bind(parent_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or_else(|| CommentError::NotFound("Parent comment not found".to_string()))?;
+
+            let blocked = BlockService::new(self.pool.clone())
+                .is_blocked_between(user_id, parent_author)
+                .await
+                .map_err(|e| e.context("Block check error"))?;
+            if blocked {
+                return Err(CommentError::Forbidden("You can't reply to this comment".to_string()));
+            }
+        }
+
+        let created = sqlx::query_as::<_, Comment>(
+            r#"
+            INSERT INTO comments (content, url, normalized_url, url_hash, normalizer_version, user_id, parent_id, language, domain, anchor, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW(), NOW())
+            RETURNING *
+            "#
+        )
+        .bind(comment.content.trim())
+        .bind(&comment.url)
+        .bind(&normalized_url)
+        .bind(&url_hash)
+        .bind(&normalizer_version)
+        .bind(user_id)
+        .bind(comment.parent_id)
+        .bind(language)
+        .bind(url_domain(&normalized_url))
+        .bind(comment.anchor.map(Json))
+        .fetch_one(&self.pool)
+        .await?;
+
+        // The comment is already posted, so a failure here must not make the
+        // client retry it
+        if let Some(page) = page {
+            let recorded = PageService::new(self.pool.clone())
+                .record(&url_hash, &page, trust_level.can_replace_page_metadata())
+                .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record metadata for page {}: {:#}", url_hash, e);
+            }
+        }
+
+        Ok(created)
+    }
+
+    /// Replaces a comment's content. With `unchanged_since`, the update only
+    /// applies if the comment was last updated then, and fails as Stale
+    /// otherwise.
+    pub async fn update(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+        content: &str,
+        unchanged_since: Option<DateTime<Utc>>,
+    ) -> Result<Comment, CommentError> {
+        validate_content(content)?;
+
+        let not_found = || CommentError::NotFound("Comment not found or you don't have permission to edit it".to_string());
+        let created_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "SELECT created_at FROM comments WHERE id = $1 AND user_id = $2"
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(not_found)?;
+
+        // Check edit window and link privileges from the author's trust level
+        let trust_level = ReputationService::new(self.pool.clone())
+            .trust_level(user_id)
+            .await
+            .map_err(|e| e.context("Reputation error"))?;
+
+        if trust_level.edit_window().is_some_and(|window| Utc::now() > created_at + window) {
+            return Err(CommentError::Forbidden("This comment can no longer be edited".to_string()));
+        }
+
+        if !trust_level.can_post_links() && contains_link(content) {
+            return Err(CommentError::Forbidden("New accounts can't post links yet".to_string()));
+        }
+
+        // Update comment (only if owned by user)
+        let updated = sqlx::query_as::<_, Comment>(
+            r#"
+            UPDATE comments
+            SET content = $1, updated_at = NOW()
+            WHERE id = $2 AND user_id = $3 AND ($4::timestamptz IS NULL OR updated_at = $4)
+            RETURNING *
+            "#
+        )
+        .bind(content.trim())
+        .bind(id)
+        .bind(user_id)
+        .bind(unchanged_since)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| if unchanged_since.is_some() { CommentError::Stale } else { not_found() })?;
+
+        Ok(updated)
+    }
+
+    /// Deletes one of the user's own comments, with its replies
+    pub async fn delete(&self, user_id: Uuid, id: Uuid) -> Result<(), CommentError> {
+        let result = sqlx::query(
+            "DELETE FROM comments WHERE id = $1 AND user_id = $2"
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(CommentError::NotFound("Comment not found or you don't have permission to delete it".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_content(content: &str) -> Result<(), CommentError> {
+    if content.trim().is_empty() {
+        return Err(CommentError::Invalid("Comment content cannot be empty".to_string()));
+    }
+
+    if content.len() > MAX_CONTENT_BYTES {
+        return Err(CommentError::Invalid(format!("Comment content too long (max {} characters)", MAX_CONTENT_BYTES)));
+    }
+
+    Ok(())
+}
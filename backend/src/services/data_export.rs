@@ -0,0 +1,435 @@
+use crate::services::audit;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use tracing::{error, info};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// How long a finished export is kept on disk
+const EXPORT_RETENTION_DAYS: i32 = 7;
+/// How long an export may stay pending before it is taken to have died
+/// with the process building it
+const EXPORT_BUILD_TIMEOUT_MINUTES: i32 = 30;
+
+/// Builds subject-access archives: one JSON document covering everything we
+/// hold about a user, plus a CSV file per section for spreadsheet use
+pub struct DataExportService {
+    pool: PgPool,
+    export_dir: PathBuf,
+    signing_key: String,
+}
+
+impl DataExportService {
+    pub fn new(pool: PgPool, export_dir: PathBuf, signing_key: String) -> Self {
+        Self { pool, export_dir, signing_key }
+    }
+
+    pub fn file_path(&self, export_id: Uuid) -> PathBuf {
+        self.export_dir.join(format!("{}.zip", export_id))
+    }
+
+    /// Signature for a download link to `export_id` that is valid until `expires`
+    pub fn sign(&self, export_id: Uuid, expires: i64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("data-export:{}:{}", export_id, expires).as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    pub fn verify(&self, export_id: Uuid, expires: i64, signature: &str) -> bool {
+        if expires < Utc::now().timestamp() {
+            return false;
+        }
+
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("data-export:{}:{}", export_id, expires).as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Builds the archive for a queued export and marks it ready, or failed
+    pub async fn process(&self, export_id: Uuid, user_id: Uuid) {
+        let result = async {
+            let archive = self.build_archive(user_id).await?;
+            tokio::fs::create_dir_all(&self.export_dir).await?;
+            tokio::fs::write(self.file_path(export_id), archive).await?;
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+
+        let status = match &result {
+            Ok(()) => "ready",
+            Err(e) => {
+                error!("Data export {} failed: {}", export_id, e);
+                "failed"
+            }
+        };
+
+        // An export that outlived its timeout has already been marked failed
+        let update = sqlx::query(
+            "UPDATE data_exports SET status = $1, completed_at = NOW() WHERE id = $2 AND status = 'pending'"
+        )
+        .bind(status)
+        .bind(export_id)
+        .execute(&self.pool)
+        .await;
+
+        match update {
+            Err(e) => error!("Failed to update data export {}: {}", export_id, e),
+            Ok(update) if update.rows_affected() == 0 => {
+                info!("Data export {} finished after it timed out", export_id);
+                self.remove_archives(&[export_id]).await;
+            }
+            Ok(_) if result.is_ok() => {
+                if let Err(e) = audit::record_event(&self.pool, user_id, "data_export_ready", json!({ "export_id": export_id })).await {
+                    error!("Failed to audit data export {}: {}", export_id, e);
+                }
+                info!("Data export {} ready", export_id);
+            }
+            Ok(_) => {}
+        }
+    }
+
+    /// Deletes archives past their retention period
+    pub async fn cleanup_expired(&self) -> Result<usize> {
+        let expired = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE data_exports SET status = 'expired'
+            WHERE status = 'ready' AND completed_at < NOW() - make_interval(days => $1)
+            RETURNING id
+            "#
+        )
+        .bind(EXPORT_RETENTION_DAYS)
+        .fetch_all(&self.pool)
+        .await?;
+
+        self.remove_archives(&expired).await;
+
+        Ok(expired.len())
+    }
+
+    /// Marks exports that have been pending past the build timeout as failed,
+    /// so a build lost to a restart doesn't block new requests forever
+    pub async fn fail_stalled(&self) -> Result<usize> {
+        let stalled = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE data_exports SET status = 'failed', completed_at = NOW()
+            WHERE status = 'pending' AND created_at < NOW() - make_interval(mins => $1)
+            RETURNING id
+            "#
+        )
+        .bind(EXPORT_BUILD_TIMEOUT_MINUTES)
+        .fetch_all(&self.pool)
+        .await?;
+
+        self.remove_archives(&stalled).await;
+
+        Ok(stalled.len())
+    }
+
+    /// Deletes the archive files of the given exports; missing files are skipped
+    pub async fn remove_archives(&self, export_ids: &[Uuid]) {
+        for export_id in export_ids {
+            match tokio::fs::remove_file(self.file_path(*export_id)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => error!("Failed to remove data export {}: {}", export_id, e),
+            }
+        }
+    }
+
+    /// Builds the zip archive for a user, entirely in memory
+    pub async fn build_archive(&self, user_id: Uuid) -> Result<Vec<u8>> {
+        let sections = self.collect(user_id).await?;
+
+        let mut document = serde_json::Map::new();
+        document.insert("generated_at".to_string(), json!(Utc::now()));
+        document.insert("user_id".to_string(), json!(user_id));
+
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+
+        for (name, rows) in &sections {
+            zip.start_file(format!("{}.csv", name), options)?;
+            zip.write_all(&to_csv(rows)?)?;
+            document.insert(name.to_string(), Value::Array(rows.clone()));
+        }
+
+        zip.start_file("export.json", options)?;
+        zip.write_all(&serde_json::to_vec_pretty(&Value::Object(document))?)?;
+
+        Ok(zip.finish()?.into_inner())
+    }
+
+    async fn collect(&self, user_id: Uuid) -> Result<Vec<(&'static str, Vec<Value>)>> {
+        let profile = sqlx::query_as::<_, (Uuid, String, String, Option<String>, Option<String>, bool, bool, DateTime<Utc>, DateTime<Utc>, String, bool, bool)>(
+            r#"
+            SELECT id, name, email, phone_number, bio, password_hash IS NOT NULL, totp_enabled, created_at, updated_at, handle,
+                   show_in_feeds, show_connections
+            FROM users WHERE id = $1
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} not found", user_id))?;
+
+        let profile = vec![json!({
+            "id": profile.0,
+            "name": profile.1,
+            "handle": profile.9,
+            "email": profile.2,
+            "phone_number": profile.3,
+            "bio": profile.4,
+            "has_password": profile.5,
+            "totp_enabled": profile.6,
+            "created_at": profile.7,
+            "updated_at": profile.8,
+            "show_in_feeds": profile.10,
+            "show_connections": profile.11,
+        })];
+
+        let comments = sqlx::query_as::<_, (Uuid, String, String, String, Option<Uuid>, DateTime<Utc>, DateTime<Utc>, Option<Value>)>(
+            r#"
+            SELECT id, content, url, normalized_url, parent_id, created_at, updated_at, anchor
+            FROM comments WHERE user_id = $1 ORDER BY created_at ASC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|c| json!({
+            "id": c.0,
+            "content": c.1,
+            "url": c.2,
+            "normalized_url": c.3,
+            "parent_id": c.4,
+            "created_at": c.5,
+            "updated_at": c.6,
+            "anchor": c.7,
+        }))
+        .collect();
+
+        let votes = sqlx::query_as::<_, (Uuid, i16, DateTime<Utc>)>(
+            "SELECT comment_id, value, created_at FROM comment_votes WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|v| json!({ "comment_id": v.0, "value": v.1, "created_at": v.2 }))
+        .collect();
+
+        let reports = sqlx::query_as::<_, (Option<Uuid>, String, String, DateTime<Utc>, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT comment_id, reason, status, created_at, resolved_at
+            FROM comment_reports WHERE reporter_id = $1 ORDER BY created_at ASC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| json!({ "comment_id": r.0, "reason": r.1, "status": r.2, "created_at": r.3, "resolved_at": r.4 }))
+        .collect();
+
+        let canonical_reports = sqlx::query_as::<_, (String, String, DateTime<Utc>)>(
+            "SELECT alias_url, canonical_url, created_at FROM url_alias_reports WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| json!({ "url": r.0, "canonical_url": r.1, "created_at": r.2 }))
+        .collect();
+
+        let following = self.user_list(
+            "SELECT f.followee_id, u.handle, f.created_at FROM follows f JOIN users u ON u.id = f.followee_id WHERE f.follower_id = $1 ORDER BY f.created_at ASC",
+            user_id,
+        ).await?;
+        let blocks = self.user_list(
+            "SELECT b.blocked_id, u.handle, b.created_at FROM user_blocks b JOIN users u ON u.id = b.blocked_id WHERE b.blocker_id = $1 ORDER BY b.created_at ASC",
+            user_id,
+        ).await?;
+        let mutes = self.user_list(
+            "SELECT m.muted_id, u.handle, m.created_at FROM user_mutes m JOIN users u ON u.id = m.muted_id WHERE m.muter_id = $1 ORDER BY m.created_at ASC",
+            user_id,
+        ).await?;
+
+        let linked_identities = sqlx::query_as::<_, (String, String, Option<String>, DateTime<Utc>)>(
+            "SELECT provider, subject, email, created_at FROM linked_identities WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|i| json!({ "provider": i.0, "subject": i.1, "email": i.2, "created_at": i.3 }))
+        .collect();
+
+        let api_keys = sqlx::query_as::<_, (String, String, Vec<String>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<DateTime<Utc>>, DateTime<Utc>)>(
+            r#"
+            SELECT name, prefix, scopes, expires_at, last_used_at, revoked_at, created_at
+            FROM api_keys WHERE user_id = $1 ORDER BY created_at ASC
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|k| json!({
+            "name": k.0,
+            "prefix": k.1,
+            "scopes": k.2.join(" "),
+            "expires_at": k.3,
+            "last_used_at": k.4,
+            "revoked_at": k.5,
+            "created_at": k.6,
+        }))
+        .collect();
+
+        let account_events = sqlx::query_as::<_, (String, Value, DateTime<Utc>)>(
+            "SELECT event, details, created_at FROM account_audit_log WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|e| json!({ "event": e.0, "details": e.1, "created_at": e.2 }))
+        .collect();
+
+        Ok(vec![
+            ("profile", profile),
+            ("comments", comments),
+            ("votes", votes),
+            ("reports", reports),
+            ("canonical_reports", canonical_reports),
+            ("following", following),
+            ("blocks", blocks),
+            ("mutes", mutes),
+            ("linked_identities", linked_identities),
+            ("api_keys", api_keys),
+            ("account_events", account_events),
+        ])
+    }
+
+    /// Other users the user has followed, blocked or muted, from a query
+    /// selecting their id, handle and when the relation was made
+    async fn user_list(&self, query: &str, user_id: Uuid) -> Result<Vec<Value>> {
+        let rows = sqlx::query_as::<_, (Uuid, String, DateTime<Utc>)>(query)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|r| json!({ "user_id": r.0, "handle": r.1, "created_at": r.2 }))
+            .collect();
+
+        Ok(rows)
+    }
+}
+
+/// Flattens a list of JSON objects into CSV, using the first row's keys as the header
+fn to_csv(rows: &[Value]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    let Some(Value::Object(first)) = rows.first() else {
+        return Ok(writer.into_inner()?);
+    };
+    let columns: Vec<&String> = first.keys().collect();
+    writer.write_record(&columns)?;
+
+    for row in rows {
+        writer.write_record(columns.iter().map(|column| match &row[column.as_str()] {
+            Value::Null => String::new(),
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }))?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+
+    fn service() -> DataExportService {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgresql://localhost/unused")
+            .unwrap();
+        DataExportService::new(pool, PathBuf::from("exports"), "test_secret".to_string())
+    }
+
+    #[test]
+    fn test_to_csv_escapes_values() {
+        let rows = vec![
+            json!({ "id": 1, "content": "hello, \"world\"", "parent_id": null }),
+            json!({ "id": 2, "content": "line\nbreak", "parent_id": 1 }),
+        ];
+
+        let csv = String::from_utf8(to_csv(&rows).unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "content,id,parent_id\n\"hello, \"\"world\"\"\",1,\n\"line\nbreak\",2,1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signed_links() {
+        let service = service();
+        let export_id = Uuid::new_v4();
+        let expires = Utc::now().timestamp() + 60;
+
+        let signature = service.sign(export_id, expires);
+        assert!(service.verify(export_id, expires, &signature));
+        assert!(!service.verify(export_id, expires + 1, &signature));
+        assert!(!service.verify(Uuid::new_v4(), expires, &signature));
+
+        let expired = Utc::now().timestamp() - 1;
+        assert!(!service.verify(export_id, expired, &service.sign(export_id, expired)));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_exports_fail_and_unblock_new_requests() {
+        let Some(pool) = testing::pool().await else { return };
+        let service = DataExportService::new(pool.clone(), std::env::temp_dir().join("votp-test-exports"), "test_secret".to_string());
+        let user_id = testing::create_user(&pool).await;
+        let export = |age_minutes: i32| {
+            sqlx::query_scalar::<_, Uuid>(
+                "INSERT INTO data_exports (user_id, created_at) VALUES ($1, NOW() - make_interval(mins => $2)) RETURNING id"
+            )
+            .bind(user_id)
+            .bind(age_minutes)
+            .fetch_one(&pool)
+        };
+        let status = |export_id: Uuid| {
+            sqlx::query_scalar::<_, String>("SELECT status FROM data_exports WHERE id = $1").bind(export_id).fetch_one(&pool)
+        };
+
+        let stalled = export(EXPORT_BUILD_TIMEOUT_MINUTES + 1).await.unwrap();
+        let building = export(1).await.unwrap();
+        assert!(service.fail_stalled().await.unwrap() >= 1);
+        assert_eq!(status(stalled).await.unwrap(), "failed");
+        assert_eq!(status(building).await.unwrap(), "pending");
+
+        // A build that finishes after timing out stays failed
+        service.process(stalled, user_id).await;
+        assert_eq!(status(stalled).await.unwrap(), "failed");
+        assert!(!service.file_path(stalled).exists());
+        service.process(building, user_id).await;
+        assert_eq!(status(building).await.unwrap(), "ready");
+        service.remove_archives(&[building]).await;
+    }
+}
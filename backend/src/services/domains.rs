@@ -0,0 +1,129 @@
+use crate::graphql::pagination::TimeCursor;
+use crate::models::{Comment, DomainActivity, DomainPage, PageSort};
+use crate::utils::url_domain;
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Discussion aggregated across a whole site, keyed by the registrable
+/// domain stored on each comment. Authors blocked by or blocking the viewer
+/// are left out of every count and list.
+pub struct DomainService {
+    pool: PgPool,
+}
+
+impl DomainService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn activity(&self, domain: &str, viewer_id: Option<Uuid>) -> Result<DomainActivity> {
+        let activity = sqlx::query_as::<_, DomainActivity>(
+            r#"
+            SELECT
+                $1 AS domain,
+                COUNT(*) AS comment_count,
+                COUNT(DISTINCT url_hash) AS page_count,
+                COUNT(DISTINCT user_id) AS commenter_count,
+                MIN(created_at) AS first_comment_at,
+                MAX(created_at) AS last_comment_at
+            FROM comments
+            WHERE domain = $1 AND NOT is_blocked_between($2, user_id)
+            "#
+        )
+        .bind(domain)
+        .bind(viewer_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(activity)
+    }
+
+    /// Pages on the domain that have comments, optionally leaving one out
+    pub async fn pages(
+        &self,
+        domain: &str,
+        sort: PageSort,
+        exclude_url_hash: Option<&str>,
+        viewer_id: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<DomainPage>> {
+        let order_by = match sort {
+            PageSort::Recent => "last_comment_at DESC, url_hash",
+            PageSort::MostComments => "comment_count DESC, last_comment_at DESC, url_hash",
+        };
+
+        let pages = sqlx::query_as::<_, DomainPage>(&format!(
+            r#"
+            SELECT
+                url_hash,
+                MIN(normalized_url) AS normalized_url,
+                COUNT(*) AS comment_count,
+                MAX(created_at) AS last_comment_at
+            FROM comments
+            WHERE domain = $1 AND NOT is_blocked_between($2, user_id)
+              AND ($4::text IS NULL OR url_hash <> $4)
+            GROUP BY url_hash
+            ORDER BY {}
+            LIMIT $3
+            "#,
+            order_by
+        ))
+        .bind(domain)
+        .bind(viewer_id)
+        .bind(limit)
+        .bind(exclude_url_hash)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(pages)
+    }
+
+    /// Comments anywhere on the domain, newest first; fetches `limit` rows
+    pub async fn comments(&self, domain: &str, viewer_id: Option<Uuid>, after: Option<TimeCursor>, limit: i64) -> Result<Vec<Comment>> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+            SELECT c.* FROM comments c
+            WHERE c.domain = $1 AND NOT is_blocked_between($2, c.user_id)
+              AND ($3::timestamptz IS NULL OR (c.created_at, c.id) < ($3, $4))
+            ORDER BY c.created_at DESC, c.id DESC
+            LIMIT $5
+            "#
+        )
+        .bind(domain)
+        .bind(viewer_id)
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(comments)
+    }
+
+    /// Fills in the domain of comments written before it was stored
+    pub async fn backfill(&self) -> Result<u64> {
+        let rows = sqlx::query_as::<_, (Uuid, String)>("SELECT id, normalized_url FROM comments WHERE domain IS NULL")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let (ids, domains): (Vec<Uuid>, Vec<String>) = rows
+            .into_iter()
+            .filter_map(|(id, normalized_url)| Some((id, url_domain(&normalized_url)?)))
+            .unzip();
+
+        let updated = sqlx::query(
+            r#"
+            UPDATE comments c SET domain = v.domain
+            FROM UNNEST($1::uuid[], $2::text[]) AS v(id, domain)
+            WHERE c.id = v.id
+            "#
+        )
+        .bind(&ids)
+        .bind(&domains)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated.rows_affected())
+    }
+}
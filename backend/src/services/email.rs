@@ -1,5 +1,6 @@
 use crate::config::SmtpConfig;
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use lettre::message::header::ContentType;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::transport::smtp::client::{Tls, TlsParameters};
@@ -78,7 +79,7 @@ impl EmailService {
         Ok(EmailService { config, transport })
     }
 
-    pub async fn send_verification_code(&self, email: &str, code: &str) -> Result<()> {
+    pub async fn send_verification_code(&self, email: &str, code: &str, expiry_minutes: i64) -> Result<()> {
         // Check if we should skip email sending for development
         if std::env::var("SKIP_EMAIL_SENDING").unwrap_or_default() == "true" {
             tracing::warn!("📧 DEVELOPMENT MODE: Skipping actual email sending");
@@ -107,7 +108,7 @@ impl EmailService {
                     </div>
                     
                     <p style="font-size: 14px; color: #666; margin-top: 20px;">
-                        This code will expire in 10 minutes for security purposes.
+                        This code will expire in {} for security purposes.
                     </p>
                 </div>
                 
@@ -119,7 +120,8 @@ impl EmailService {
             </body>
             </html>
             "#,
-            code
+            code,
+            expiry(expiry_minutes)
         );
 
         let email_message = Message::builder()
@@ -141,6 +143,223 @@ impl EmailService {
         }
     }
 
+    pub async fn send_login_code(&self, email: &str, code: &str, magic_link: &str, expiry_minutes: i64) -> Result<()> {
+        // Check if we should skip email sending for development
+        if std::env::var("SKIP_EMAIL_SENDING").unwrap_or_default() == "true" {
+            tracing::warn!("📧 DEVELOPMENT MODE: Skipping actual email sending");
+            tracing::warn!("📧 Login code for {}: {}", email, code);
+            tracing::warn!("📧 Magic link for {}: {}", email, magic_link);
+            return Ok(());
+        }
+
+        let subject = "VOTP - Your Sign-in Code";
+        let body = format!(
+            r#"
+            <html>
+            <body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+                <div style="text-align: center; margin-bottom: 30px;">
+                    <h1 style="color: #333; margin-bottom: 10px;">Voice of the People</h1>
+                    <h2 style="color: #666; font-weight: normal;">Sign In</h2>
+                </div>
+                
+                <div style="background-color: #f8f9fa; padding: 30px; border-radius: 8px; text-align: center;">
+                    <p style="font-size: 16px; color: #333; margin-bottom: 20px;">
+                        Use the following code to sign in to your account:
+                    </p>
+                    
+                    <div style="background-color: #007bff; color: white; font-size: 24px; font-weight: bold; padding: 15px 30px; border-radius: 6px; letter-spacing: 3px; margin: 20px 0;">
+                        {}
+                    </div>
+                    
+                    <p style="font-size: 16px; color: #333; margin: 20px 0;">
+                        Or sign in directly with this link:
+                    </p>
+                    
+                    <a href="{}" style="display: inline-block; background-color: #28a745; color: white; font-size: 16px; padding: 12px 24px; border-radius: 6px; text-decoration: none;">
+                        Sign in to VOTP
+                    </a>
+                    
+                    <p style="font-size: 14px; color: #666; margin-top: 20px;">
+                        This code and link will expire in {} and can only be used once.
+                    </p>
+                </div>
+                
+                <div style="margin-top: 30px; padding-top: 20px; border-top: 1px solid #eee; text-align: center;">
+                    <p style="font-size: 12px; color: #999;">
+                        If you didn't try to sign in, you can safely ignore this email.
+                    </p>
+                </div>
+            </body>
+            </html>
+            "#,
+            code, magic_link, expiry(expiry_minutes)
+        );
+
+        self.send_html(email, subject, body, "Login code").await
+    }
+
+    pub async fn send_account_deletion_scheduled(
+        &self,
+        email: &str,
+        name: &str,
+        scheduled_for: &DateTime<Utc>,
+        cancel_link: &str,
+    ) -> Result<()> {
+        if std::env::var("SKIP_EMAIL_SENDING").unwrap_or_default() == "true" {
+            tracing::warn!("📧 DEVELOPMENT MODE: Skipping actual email sending");
+            tracing::warn!("📧 Account deletion cancel link for {}: {}", email, cancel_link);
+            return Ok(());
+        }
+
+        let subject = "VOTP - Your account is scheduled for deletion";
+        let body = format!(
+            r#"
+            <html>
+            <body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+                <div style="text-align: center; margin-bottom: 30px;">
+                    <h1 style="color: #333; margin-bottom: 10px;">Voice of the People</h1>
+                    <h2 style="color: #666; font-weight: normal;">Account Deletion</h2>
+                </div>
+                
+                <div style="padding: 20px;">
+                    <p style="font-size: 16px; color: #333;">Hi {},</p>
+                    
+                    <p style="font-size: 16px; color: #333; line-height: 1.6;">
+                        We received a request to delete your account. It will be permanently deleted on
+                        <strong>{}</strong>. After that, your personal information is removed and cannot be recovered.
+                    </p>
+                    
+                    <div style="text-align: center; margin: 30px 0;">
+                        <a href="{}" style="display: inline-block; background-color: #dc3545; color: white; font-size: 16px; padding: 12px 24px; border-radius: 6px; text-decoration: none;">
+                            Cancel account deletion
+                        </a>
+                    </div>
+                </div>
+                
+                <div style="margin-top: 30px; padding-top: 20px; border-top: 1px solid #eee; text-align: center;">
+                    <p style="font-size: 12px; color: #999;">
+                        If you didn't request this, cancel the deletion and change your password.
+                    </p>
+                </div>
+            </body>
+            </html>
+            "#,
+            name,
+            scheduled_for.format("%B %-d, %Y at %H:%M UTC"),
+            cancel_link
+        );
+
+        self.send_html(email, subject, body, "Account deletion").await
+    }
+
+    pub async fn send_email_change_code(&self, new_email: &str, code: &str, expiry_minutes: i64) -> Result<()> {
+        if std::env::var("SKIP_EMAIL_SENDING").unwrap_or_default() == "true" {
+            tracing::warn!("📧 DEVELOPMENT MODE: Skipping actual email sending");
+            tracing::warn!("📧 Email change code for {}: {}", new_email, code);
+            return Ok(());
+        }
+
+        let subject = "VOTP - Confirm Your New Email Address";
+        let body = format!(
+            r#"
+            <html>
+            <body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+                <div style="text-align: center; margin-bottom: 30px;">
+                    <h1 style="color: #333; margin-bottom: 10px;">Voice of the People</h1>
+                    <h2 style="color: #666; font-weight: normal;">Confirm Email Change</h2>
+                </div>
+                
+                <div style="background-color: #f8f9fa; padding: 30px; border-radius: 8px; text-align: center;">
+                    <p style="font-size: 16px; color: #333; margin-bottom: 20px;">
+                        Use the following code to confirm this as the new email address for your account:
+                    </p>
+                    
+                    <div style="background-color: #007bff; color: white; font-size: 24px; font-weight: bold; padding: 15px 30px; border-radius: 6px; letter-spacing: 3px; margin: 20px 0;">
+                        {}
+                    </div>
+                    
+                    <p style="font-size: 14px; color: #666; margin-top: 20px;">
+                        This code will expire in {} for security purposes.
+                    </p>
+                </div>
+                
+                <div style="margin-top: 30px; padding-top: 20px; border-top: 1px solid #eee; text-align: center;">
+                    <p style="font-size: 12px; color: #999;">
+                        If you didn't request this change, please ignore this email.
+                    </p>
+                </div>
+            </body>
+            </html>
+            "#,
+            code,
+            expiry(expiry_minutes)
+        );
+
+        self.send_html(new_email, subject, body, "Email change code").await
+    }
+
+    pub async fn send_email_change_notice(&self, old_email: &str, new_email: &str) -> Result<()> {
+        if std::env::var("SKIP_EMAIL_SENDING").unwrap_or_default() == "true" {
+            tracing::warn!("📧 DEVELOPMENT MODE: Skipping actual email sending");
+            tracing::warn!("📧 Email change notice for {} (new address {})", old_email, new_email);
+            return Ok(());
+        }
+
+        let subject = "VOTP - Email Change Requested";
+        let body = format!(
+            r#"
+            <html>
+            <body style="font-family: Arial, sans-serif; max-width: 600px; margin: 0 auto; padding: 20px;">
+                <div style="text-align: center; margin-bottom: 30px;">
+                    <h1 style="color: #333; margin-bottom: 10px;">Voice of the People</h1>
+                    <h2 style="color: #666; font-weight: normal;">Email Change Requested</h2>
+                </div>
+                
+                <div style="padding: 20px;">
+                    <p style="font-size: 16px; color: #333; line-height: 1.6;">
+                        Someone asked to change the email address on your account to <strong>{}</strong>.
+                        The change takes effect once the new address is confirmed.
+                    </p>
+                    
+                    <p style="font-size: 16px; color: #333; line-height: 1.6;">
+                        If this wasn't you, change your password right away.
+                    </p>
+                </div>
+                
+                <div style="margin-top: 30px; padding-top: 20px; border-top: 1px solid #eee; text-align: center;">
+                    <p style="font-size: 12px; color: #999;">
+                        This is an automated message from Voice of the People.
+                    </p>
+                </div>
+            </body>
+            </html>
+            "#,
+            new_email
+        );
+
+        self.send_html(old_email, subject, body, "Email change notice").await
+    }
+
+    async fn send_html(&self, email: &str, subject: &str, body: String, kind: &str) -> Result<()> {
+        let email_message = Message::builder()
+            .from(self.config.from_email.parse()?)
+            .to(email.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_HTML)
+            .body(body)?;
+
+        match self.transport.send(&email_message) {
+            Ok(_) => {
+                info!("{} email sent successfully to {}", kind, email);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to send {} email to {}: {}", kind.to_lowercase(), email, e);
+                Err(anyhow::anyhow!("Failed to send email: {}", e))
+            }
+        }
+    }
+
     pub async fn send_welcome_email(&self, email: &str, name: &str) -> Result<()> {
         let subject = "Welcome to VOTP - Voice of the People!";
         let body = format!(
@@ -203,4 +422,28 @@ impl EmailService {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// How long a code stays valid, as the emails phrase it
+fn expiry(minutes: i64) -> String {
+    match minutes {
+        1 => "1 minute".to_string(),
+        60 => "1 hour".to_string(),
+        minutes if minutes > 60 && minutes % 60 == 0 => format!("{} hours", minutes / 60),
+        minutes => format!("{} minutes", minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry() {
+        assert_eq!(expiry(1), "1 minute");
+        assert_eq!(expiry(10), "10 minutes");
+        assert_eq!(expiry(60), "1 hour");
+        assert_eq!(expiry(90), "90 minutes");
+        assert_eq!(expiry(120), "2 hours");
+    }
+}
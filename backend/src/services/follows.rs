@@ -0,0 +1,143 @@
+use crate::graphql::pagination::TimeCursor;
+use crate::models::{Comment, User};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// A user in a followers/following list, with when the follow happened
+#[derive(Debug, Clone, FromRow)]
+pub struct FollowRow {
+    #[sqlx(flatten)]
+    pub user: User,
+    pub followed_at: DateTime<Utc>,
+}
+
+impl FollowRow {
+    pub fn cursor(&self) -> TimeCursor {
+        TimeCursor { at: self.followed_at, id: self.user.id }
+    }
+}
+
+/// The follow graph and the feed of comments it produces
+pub struct FollowService {
+    pool: PgPool,
+}
+
+impl FollowService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Returns false if the followee doesn't exist
+    pub async fn follow(&self, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO follows (follower_id, followee_id)
+            SELECT $1, id FROM users WHERE id = $2
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .bind(follower_id)
+        .bind(followee_id)
+        .execute(&self.pool)
+        .await?;
+
+        if inserted.rows_affected() > 0 {
+            return Ok(true);
+        }
+
+        self.is_following(follower_id, followee_id).await
+    }
+
+    pub async fn unfollow(&self, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM follows WHERE follower_id = $1 AND followee_id = $2")
+            .bind(follower_id)
+            .bind(followee_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(deleted.rows_affected() > 0)
+    }
+
+    pub async fn is_following(&self, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+        let following = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM follows WHERE follower_id = $1 AND followee_id = $2)"
+        )
+        .bind(follower_id)
+        .bind(followee_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(following)
+    }
+
+    /// Users following `user_id`, most recent first; fetches `limit` rows
+    pub async fn followers(&self, user_id: Uuid, after: Option<TimeCursor>, limit: i64) -> Result<Vec<FollowRow>> {
+        let rows = sqlx::query_as::<_, FollowRow>(
+            r#"
+            SELECT u.*, f.created_at AS followed_at
+            FROM follows f JOIN users u ON u.id = f.follower_id
+            WHERE f.followee_id = $1
+              AND ($2::timestamptz IS NULL OR (f.created_at, f.follower_id) < ($2, $3))
+            ORDER BY f.created_at DESC, f.follower_id DESC
+            LIMIT $4
+            "#
+        )
+        .bind(user_id)
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Users `user_id` follows, most recent first; fetches `limit` rows
+    pub async fn following(&self, user_id: Uuid, after: Option<TimeCursor>, limit: i64) -> Result<Vec<FollowRow>> {
+        let rows = sqlx::query_as::<_, FollowRow>(
+            r#"
+            SELECT u.*, f.created_at AS followed_at
+            FROM follows f JOIN users u ON u.id = f.followee_id
+            WHERE f.follower_id = $1
+              AND ($2::timestamptz IS NULL OR (f.created_at, f.followee_id) < ($2, $3))
+            ORDER BY f.created_at DESC, f.followee_id DESC
+            LIMIT $4
+            "#
+        )
+        .bind(user_id)
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Recent comments by followed users across all URLs, newest first.
+    /// Authors who opted out of appearing in feeds, or who blocked the
+    /// viewer since being followed, are left out.
+    pub async fn feed(&self, user_id: Uuid, after: Option<TimeCursor>, limit: i64) -> Result<Vec<Comment>> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+            SELECT c.* FROM comments c
+            JOIN follows f ON f.followee_id = c.user_id AND f.follower_id = $1
+            JOIN users u ON u.id = c.user_id
+            WHERE u.show_in_feeds AND NOT is_blocked_between($1, c.user_id)
+              AND ($2::timestamptz IS NULL OR (c.created_at, c.id) < ($2, $3))
+            ORDER BY c.created_at DESC, c.id DESC
+            LIMIT $4
+            "#
+        )
+        .bind(user_id)
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(comments)
+    }
+}
@@ -0,0 +1,217 @@
+use crate::models::User;
+use crate::validation::{handle_skeleton, validate_handle, HANDLE_MAX_CHARS, HANDLE_MIN_CHARS};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sqlx::PgPool;
+use tracing::info;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+/// Numeric suffixes tried, and then as many UUID fragments, before giving up
+const GENERATE_ATTEMPTS: usize = 10;
+
+/// Assigns, renames and resolves user handles. Previous handles are kept as
+/// redirects so old profile links and mentions keep resolving to their owner.
+pub struct HandleService {
+    pool: PgPool,
+    rename_cooldown_days: i64,
+}
+
+impl HandleService {
+    pub fn new(pool: PgPool, rename_cooldown_days: i64) -> Self {
+        Self { pool, rename_cooldown_days }
+    }
+
+    /// When the user may next change their handle; None if they can now
+    pub fn next_rename_at(&self, user: &User) -> Option<DateTime<Utc>> {
+        user.handle_changed_at
+            .map(|changed_at| changed_at + Duration::days(self.rename_cooldown_days))
+            .filter(|available_at| *available_at > Utc::now())
+    }
+
+    /// True unless another user holds, or used to hold, a confusable handle
+    pub async fn is_available(&self, handle: &str, user_id: Option<Uuid>) -> Result<bool> {
+        let taken = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM users WHERE handle_skeleton = $1 AND id IS DISTINCT FROM $2)
+                OR EXISTS(SELECT 1 FROM handle_redirects WHERE skeleton = $1 AND user_id IS DISTINCT FROM $2)
+            "#
+        )
+        .bind(handle_skeleton(handle))
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(!taken)
+    }
+
+    /// Picks a free handle based on a display name, e.g. "José Álvarez" -> "jose_alvarez".
+    /// Every candidate, suffixed or not, must pass `validate_handle` and be free.
+    pub async fn generate(&self, name: &str) -> Result<String> {
+        let base = handle_base(name);
+
+        let suffixes: Vec<String> = (0..GENERATE_ATTEMPTS)
+            .map(|_| rand::rng().random_range(1000..10000).to_string())
+            .chain((0..GENERATE_ATTEMPTS).map(|_| Uuid::new_v4().simple().to_string()[..8].to_string()))
+            .collect();
+
+        let candidates = std::iter::once(base.clone()).chain(suffixes.iter().map(|suffix| with_suffix(&base, suffix)));
+        for candidate in candidates {
+            if validate_handle("handle", &candidate).is_ok() && self.is_available(&candidate, None).await? {
+                return Ok(candidate);
+            }
+        }
+
+        Err(anyhow::anyhow!("No free handle found for {:?}", name))
+    }
+
+    /// Changes a user's handle, keeping the old one as a redirect
+    pub async fn rename(&self, user_id: Uuid, handle: &str) -> Result<User> {
+        let mut tx = self.pool.begin().await?;
+
+        let old_handle = sqlx::query_scalar::<_, String>("SELECT handle FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        // Reclaiming one of your own previous handles drops its redirect
+        sqlx::query("DELETE FROM handle_redirects WHERE user_id = $1 AND skeleton = $2")
+            .bind(user_id)
+            .bind(handle_skeleton(handle))
+            .execute(&mut *tx)
+            .await?;
+
+        if handle_skeleton(&old_handle) != handle_skeleton(handle) {
+            sqlx::query(
+                r#"
+                INSERT INTO handle_redirects (handle, skeleton, user_id)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (handle) DO UPDATE SET user_id = EXCLUDED.user_id, created_at = NOW()
+                "#
+            )
+            .bind(old_handle.to_lowercase())
+            .bind(handle_skeleton(&old_handle))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET handle = $1, handle_skeleton = $2, handle_changed_at = NOW()
+            WHERE id = $3
+            RETURNING *
+            "#
+        )
+        .bind(handle)
+        .bind(handle_skeleton(handle))
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// Finds a user by their current handle, or by a handle they used to have
+    pub async fn find(&self, handle: &str) -> Result<Option<User>> {
+        let handle = handle.trim().trim_start_matches('@').to_lowercase();
+
+        // A current handle wins over a redirect from a previous one
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            SELECT u.* FROM (
+                SELECT id AS user_id, 0 AS rank FROM users WHERE LOWER(handle) = $1
+                UNION ALL
+                SELECT user_id, 1 FROM handle_redirects WHERE handle = $1
+            ) m
+            JOIN users u ON u.id = m.user_id
+            ORDER BY m.rank, u.id
+            LIMIT 1
+            "#
+        )
+        .bind(&handle)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Gives every user created before handles existed a generated one
+    pub async fn backfill(&self) -> Result<usize> {
+        let users = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, name FROM users WHERE handle IS NULL ORDER BY created_at ASC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for (user_id, name) in &users {
+            let handle = self.generate(name).await?;
+            sqlx::query("UPDATE users SET handle = $1, handle_skeleton = $2 WHERE id = $3")
+                .bind(&handle)
+                .bind(handle_skeleton(&handle))
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        if !users.is_empty() {
+            info!("Backfilled handles for {} users", users.len());
+        }
+        Ok(users.len())
+    }
+}
+
+/// Folds a display name to ASCII handle characters
+fn handle_base(name: &str) -> String {
+    let mut base = String::new();
+    for c in name.nfkd().filter(char::is_ascii) {
+        if c.is_ascii_alphanumeric() {
+            base.push(c.to_ascii_lowercase());
+        } else if !base.is_empty() && !base.ends_with('_') {
+            base.push('_');
+        }
+    }
+
+    let base: String = base.trim_end_matches('_').chars().take(HANDLE_MAX_CHARS).collect();
+    let base = base.trim_end_matches('_').to_string();
+
+    if base.chars().count() < HANDLE_MIN_CHARS || !base.chars().any(|c| c.is_ascii_alphabetic()) {
+        "user".to_string()
+    } else {
+        base
+    }
+}
+
+/// `base` with `_suffix` appended, shortening `base` so the result still fits
+fn with_suffix(base: &str, suffix: &str) -> String {
+    let base: String = base.chars().take(HANDLE_MAX_CHARS - suffix.len() - 1).collect();
+    format!("{}_{}", base.trim_end_matches('_'), suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_base() {
+        assert_eq!(handle_base("Jane Doe"), "jane_doe");
+        assert_eq!(handle_base("José  Álvarez-Smith"), "jose_alvarez_smith");
+        assert_eq!(handle_base("  O'Brien!  "), "o_brien");
+        assert_eq!(handle_base("山田"), "user");
+        assert_eq!(handle_base("42"), "user");
+        assert_eq!(handle_base(&"a".repeat(50)).len(), HANDLE_MAX_CHARS);
+        assert!(validate_handle("handle", &handle_base("Jane Doe")).is_ok());
+    }
+
+    #[test]
+    fn test_with_suffix() {
+        assert_eq!(with_suffix("jane_doe", "1234"), "jane_doe_1234");
+        assert_eq!(with_suffix(&"a".repeat(HANDLE_MAX_CHARS), "1234").len(), HANDLE_MAX_CHARS);
+        assert_eq!(with_suffix(&"a".repeat(HANDLE_MAX_CHARS), "0123abcd").len(), HANDLE_MAX_CHARS);
+        // No doubled underscore where the cut lands after one
+        assert_eq!(with_suffix(&format!("{}_b", "a".repeat(20)), "0123abcd"), format!("{}_0123abcd", "a".repeat(20)));
+        assert!(validate_handle("handle", &with_suffix(&"a".repeat(HANDLE_MAX_CHARS), "0123abcd")).is_ok());
+    }
+}
@@ -1,2 +1,25 @@
+pub mod account_deletion;
+pub mod annotations;
+pub mod api_keys;
+pub mod audit;
 pub mod auth;
-pub mod email;
\ No newline at end of file
+pub mod avatars;
+pub mod blob_store;
+pub mod blocks;
+pub mod comment_counts;
+pub mod comments;
+pub mod data_export;
+pub mod domains;
+pub mod email;
+pub mod follows;
+pub mod handles;
+pub mod oidc;
+pub mod pages;
+pub mod renormalize;
+pub mod reports;
+pub mod reputation;
+pub mod search;
+pub mod totp;
+pub mod url_aliases;
+pub mod verification;
+pub mod votes;
\ No newline at end of file
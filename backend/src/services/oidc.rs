@@ -0,0 +1,461 @@
+use crate::config::OidcProviderConfig;
+use anyhow::Result;
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use rand::Rng;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+const GITHUB_AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// The subset of the OpenID Provider metadata we rely on
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: Option<String>,
+    /// Algorithms ID tokens may be signed with. OIDC requires every provider
+    /// to support RS256, so that is assumed when the document omits the list.
+    #[serde(default = "default_signing_algs")]
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+fn default_signing_algs() -> Vec<String> {
+    vec!["RS256".to_string()]
+}
+
+/// A PKCE code verifier and its S256 challenge (RFC 7636)
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+/// The identity asserted by a provider after a successful code exchange
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    id: i64,
+    name: Option<String>,
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// OAuth2 authorization-code + PKCE client for one configured provider
+pub struct OidcService {
+    provider: OidcProviderConfig,
+    redirect_uri: String,
+    http: reqwest::Client,
+}
+
+impl OidcService {
+    pub fn new(provider: OidcProviderConfig, redirect_uri: String) -> Self {
+        Self {
+            provider,
+            redirect_uri,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn generate_pkce() -> Pkce {
+        let mut bytes = [0u8; 32];
+        rand::rng().fill(&mut bytes);
+        let verifier = BASE64URL_NOPAD.encode(&bytes);
+        let challenge = BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes()));
+        Pkce { verifier, challenge }
+    }
+
+    /// Fetches the provider's endpoints, via discovery for OIDC issuers
+    pub async fn metadata(&self) -> Result<ProviderMetadata> {
+        if self.provider.kind == "github" {
+            return Ok(ProviderMetadata {
+                issuer: self.provider.issuer.clone(),
+                authorization_endpoint: GITHUB_AUTHORIZE_URL.to_string(),
+                token_endpoint: GITHUB_TOKEN_URL.to_string(),
+                jwks_uri: None,
+                id_token_signing_alg_values_supported: Vec::new(),
+            });
+        }
+
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            self.provider.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self.http
+            .get(&discovery_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if metadata.issuer.trim_end_matches('/') != self.provider.issuer.trim_end_matches('/') {
+            return Err(anyhow::anyhow!("Discovery document issuer does not match the configured issuer"));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Builds the URL the user's browser is sent to in order to sign in
+    pub fn authorization_url(
+        &self,
+        metadata: &ProviderMetadata,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String> {
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.provider.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.provider.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    /// Exchanges an authorization code and returns the verified identity
+    pub async fn exchange_code(
+        &self,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<ExternalIdentity> {
+        let tokens: TokenResponse = self.http
+            .post(&metadata.token_endpoint)
+            .header("Accept", "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.provider.client_id),
+                ("client_secret", &self.provider.client_secret),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if self.provider.kind == "github" {
+            return self.github_identity(&tokens.access_token).await;
+        }
+
+        let id_token = tokens.id_token
+            .ok_or_else(|| anyhow::anyhow!("Provider did not return an ID token"))?;
+        self.verify_id_token(metadata, &id_token, nonce).await
+    }
+
+    async fn verify_id_token(&self, metadata: &ProviderMetadata, id_token: &str, nonce: &str) -> Result<ExternalIdentity> {
+        let header = decode_header(id_token)?;
+
+        // The token mustn't pick how it is verified: only algorithms the
+        // provider advertises are accepted, so an HS256 token can't be
+        // checked against a secret the provider never signs with
+        let advertised = metadata.id_token_signing_alg_values_supported
+            .iter()
+            .any(|alg| alg.parse::<Algorithm>().is_ok_and(|alg| alg == header.alg));
+        if !advertised {
+            return Err(anyhow::anyhow!("ID token is signed with {:?}, which the provider does not advertise", header.alg));
+        }
+
+        // HS256 ID tokens are signed with the client secret (OIDC Core 10.1)
+        let key = if header.alg == Algorithm::HS256 {
+            DecodingKey::from_secret(self.provider.client_secret.as_bytes())
+        } else {
+            let jwks_uri = metadata.jwks_uri.as_deref()
+                .ok_or_else(|| anyhow::anyhow!("Provider does not publish a JWKS"))?;
+            let jwks: JwkSet = self.http.get(jwks_uri).send().await?.error_for_status()?.json().await?;
+            let jwk = match &header.kid {
+                Some(kid) => jwks.find(kid),
+                None => jwks.keys.first(),
+            }
+            .ok_or_else(|| anyhow::anyhow!("No matching signing key for ID token"))?;
+            DecodingKey::from_jwk(jwk)?
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.provider.client_id]);
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| anyhow::anyhow!("Invalid ID token: {}", e))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(anyhow::anyhow!("ID token nonce mismatch"));
+        }
+
+        Ok(ExternalIdentity {
+            subject: claims.sub,
+            email: claims.email.map(|email| email.to_lowercase()),
+            email_verified: claims.email_verified,
+            name: claims.name,
+        })
+    }
+
+    async fn github_identity(&self, access_token: &str) -> Result<ExternalIdentity> {
+        let user: GithubUser = self.github_get(access_token, "/user").await?;
+        let emails: Vec<GithubEmail> = self.github_get(access_token, "/user/emails").await?;
+
+        let email = emails.into_iter().find(|email| email.primary && email.verified);
+
+        Ok(ExternalIdentity {
+            subject: user.id.to_string(),
+            email_verified: email.is_some(),
+            email: email.map(|email| email.email.to_lowercase()),
+            name: user.name.or(Some(user.login)),
+        })
+    }
+
+    async fn github_get<T: serde::de::DeserializeOwned>(&self, access_token: &str, path: &str) -> Result<T> {
+        Ok(self.http
+            .get(format!("{}{}", GITHUB_API_URL, path))
+            .bearer_auth(access_token)
+            .header("User-Agent", "votp-backend")
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const CLIENT_ID: &str = "votp-test-client";
+    const CLIENT_SECRET: &str = "votp-test-secret";
+    const REDIRECT_URI: &str = "http://localhost/callback";
+
+    /// Authorization codes handed out by the mock issuer, with their PKCE challenge and nonce
+    #[derive(Default)]
+    struct MockIssuerState {
+        issuer: Mutex<String>,
+        codes: Mutex<HashMap<String, (String, String)>>,
+    }
+
+    async fn discovery(state: web::Data<MockIssuerState>) -> HttpResponse {
+        let issuer = state.issuer.lock().unwrap().clone();
+        HttpResponse::Ok().json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+            "id_token_signing_alg_values_supported": ["HS256"],
+        }))
+    }
+
+    /// Signs the user in immediately and redirects back with a code
+    async fn authorize(state: web::Data<MockIssuerState>, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+        let code = format!("code-{}", state.codes.lock().unwrap().len());
+        state.codes.lock().unwrap().insert(
+            code.clone(),
+            (query["code_challenge"].clone(), query["nonce"].clone()),
+        );
+        HttpResponse::Found()
+            .append_header(("Location", format!("{}?code={}&state={}", query["redirect_uri"], code, query["state"])))
+            .finish()
+    }
+
+    async fn token(state: web::Data<MockIssuerState>, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+        let Some((challenge, nonce)) = state.codes.lock().unwrap().remove(&form["code"]) else {
+            return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+        };
+
+        let expected = BASE64URL_NOPAD.encode(&Sha256::digest(form["code_verifier"].as_bytes()));
+        if expected != challenge || form["client_secret"] != CLIENT_SECRET {
+            return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let id_token = encode(
+            &Header::new(Algorithm::HS256),
+            &json!({
+                "iss": state.issuer.lock().unwrap().clone(),
+                "aud": CLIENT_ID,
+                "sub": "mock-user-1",
+                "email": "Mock.User@Example.com",
+                "email_verified": true,
+                "name": "Mock User",
+                "nonce": nonce,
+                "iat": now,
+                "exp": now + 300,
+            }),
+            &EncodingKey::from_secret(CLIENT_SECRET.as_bytes()),
+        )
+        .unwrap();
+
+        HttpResponse::Ok().json(json!({
+            "access_token": "mock-access-token",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        }))
+    }
+
+    async fn start_mock_issuer() -> String {
+        let state = web::Data::new(MockIssuerState::default());
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .route("/.well-known/openid-configuration", web::get().to(discovery))
+                .route("/other/.well-known/openid-configuration", web::get().to(discovery))
+                .route("/authorize", web::get().to(authorize))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let issuer = format!("http://{}", server.addrs()[0]);
+        *state.issuer.lock().unwrap() = issuer.clone();
+        actix_web::rt::spawn(server.run());
+        issuer
+    }
+
+    fn provider(issuer: &str) -> OidcProviderConfig {
+        OidcProviderConfig {
+            name: "mock".to_string(),
+            kind: "oidc".to_string(),
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: CLIENT_SECRET.to_string(),
+            scopes: "openid email profile".to_string(),
+        }
+    }
+
+    /// Follows the authorization URL like a browser would and returns the code
+    async fn authorize_code(authorization_url: &str) -> String {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client.get(authorization_url).send().await.unwrap();
+        let location = Url::parse(response.headers()["location"].to_str().unwrap()).unwrap();
+        assert!(location.as_str().starts_with(REDIRECT_URI));
+        location.query_pairs().find(|(k, _)| k == "code").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn test_pkce_challenge_is_s256_of_verifier() {
+        let pkce = OidcService::generate_pkce();
+
+        assert_eq!(pkce.verifier.len(), 43);
+        assert_eq!(pkce.challenge, BASE64URL_NOPAD.encode(&Sha256::digest(pkce.verifier.as_bytes())));
+    }
+
+    #[actix_web::test]
+    async fn test_authorization_code_flow_against_mock_issuer() {
+        let issuer = start_mock_issuer().await;
+        let service = OidcService::new(provider(&issuer), REDIRECT_URI.to_string());
+
+        let metadata = service.metadata().await.unwrap();
+        assert_eq!(metadata.token_endpoint, format!("{}/token", issuer));
+
+        let pkce = OidcService::generate_pkce();
+        let url = service.authorization_url(&metadata, "state-1", "nonce-1", &pkce.challenge).unwrap();
+        let code = authorize_code(&url).await;
+
+        let identity = service.exchange_code(&metadata, &code, &pkce.verifier, "nonce-1").await.unwrap();
+        assert_eq!(identity.subject, "mock-user-1");
+        assert_eq!(identity.email.as_deref(), Some("mock.user@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[actix_web::test]
+    async fn test_wrong_code_verifier_is_rejected() {
+        let issuer = start_mock_issuer().await;
+        let service = OidcService::new(provider(&issuer), REDIRECT_URI.to_string());
+        let metadata = service.metadata().await.unwrap();
+
+        let pkce = OidcService::generate_pkce();
+        let url = service.authorization_url(&metadata, "state-1", "nonce-1", &pkce.challenge).unwrap();
+        let code = authorize_code(&url).await;
+
+        let other = OidcService::generate_pkce();
+        assert!(service.exchange_code(&metadata, &code, &other.verifier, "nonce-1").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_nonce_mismatch_is_rejected() {
+        let issuer = start_mock_issuer().await;
+        let service = OidcService::new(provider(&issuer), REDIRECT_URI.to_string());
+        let metadata = service.metadata().await.unwrap();
+
+        let pkce = OidcService::generate_pkce();
+        let url = service.authorization_url(&metadata, "state-1", "nonce-1", &pkce.challenge).unwrap();
+        let code = authorize_code(&url).await;
+
+        let error = service.exchange_code(&metadata, &code, &pkce.verifier, "other-nonce").await.unwrap_err();
+        assert!(error.to_string().contains("nonce"));
+    }
+
+    #[actix_web::test]
+    async fn test_unadvertised_signing_algorithm_is_rejected() {
+        let issuer = start_mock_issuer().await;
+        let service = OidcService::new(provider(&issuer), REDIRECT_URI.to_string());
+        let mut metadata = service.metadata().await.unwrap();
+        metadata.id_token_signing_alg_values_supported = vec!["RS256".to_string()];
+
+        let pkce = OidcService::generate_pkce();
+        let url = service.authorization_url(&metadata, "state-1", "nonce-1", &pkce.challenge).unwrap();
+        let code = authorize_code(&url).await;
+
+        let error = service.exchange_code(&metadata, &code, &pkce.verifier, "nonce-1").await.unwrap_err();
+        assert!(error.to_string().contains("does not advertise"));
+    }
+
+    #[actix_web::test]
+    async fn test_issuer_mismatch_in_discovery_is_rejected() {
+        let issuer = start_mock_issuer().await;
+        let service = OidcService::new(provider(&format!("{}/other", issuer)), REDIRECT_URI.to_string());
+
+        assert!(service.metadata().await.is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::models::PageMetadataInput;
+use anyhow::Result;
+use sqlx::PgPool;
+use url::Url;
+
+const MAX_TITLE_CHARS: usize = 300;
+const MAX_DESCRIPTION_CHARS: usize = 1000;
+const MAX_SITE_NAME_CHARS: usize = 100;
+const MAX_LANGUAGE_LEN: usize = 35;
+const MAX_FAVICON_URL_LEN: usize = 2048;
+
+/// Page metadata cleaned up for storage. Fields the client didn't send, or
+/// sent in a form that couldn't be cleaned up, are None.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub language: Option<String>,
+    pub favicon_url: Option<String>,
+}
+
+impl PageMetadata {
+    /// Cleans up metadata sent for the page at `page_url`: text is reduced
+    /// to one line of printable characters and shortened, the language must
+    /// look like a BCP 47 tag and the favicon must be an http(s) URL
+    pub fn sanitize(input: &PageMetadataInput, page_url: &str) -> Self {
+        Self {
+            title: clean_text(input.title.as_deref(), MAX_TITLE_CHARS),
+            description: clean_text(input.description.as_deref(), MAX_DESCRIPTION_CHARS),
+            site_name: clean_text(input.site_name.as_deref(), MAX_SITE_NAME_CHARS),
+            language: input.language.as_deref().and_then(clean_language),
+            favicon_url: input.favicon_url.as_deref().and_then(|favicon_url| clean_favicon_url(favicon_url, page_url)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Collapses whitespace, drops control and invisible formatting characters
+/// and truncates to `max_chars`
+fn clean_text(input: Option<&str>, max_chars: usize) -> Option<String> {
+    let text = input?
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'))
+        .collect::<String>();
+    let text = text.trim();
+
+    if text.chars().count() <= max_chars {
+        return (!text.is_empty()).then(|| text.to_string());
+    }
+    let truncated: String = text.chars().take(max_chars - 1).collect();
+    Some(format!("{}…", truncated.trim_end()))
+}
+
+/// A BCP 47 language tag with conventional casing (en-GB, zh-Hant-TW)
+fn clean_language(input: &str) -> Option<String> {
+    let input = input.trim().replace('_', "-");
+    if input.len() > MAX_LANGUAGE_LEN {
+        return None;
+    }
+
+    let mut subtags = input.split('-');
+    let primary = subtags.next()?;
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut tag = primary.to_ascii_lowercase();
+    for subtag in subtags {
+        if !(1..=8).contains(&subtag.len()) || !subtag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        tag.push('-');
+        match subtag.len() {
+            2 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => tag.push_str(&subtag.to_ascii_uppercase()),
+            4 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                tag.push_str(&subtag[..1].to_ascii_uppercase());
+                tag.push_str(&subtag[1..].to_ascii_lowercase());
+            }
+            _ => tag.push_str(&subtag.to_ascii_lowercase()),
+        }
+    }
+
+    Some(tag)
+}
+
+/// An absolute http(s) favicon URL, resolving relative ones against the page
+fn clean_favicon_url(input: &str, page_url: &str) -> Option<String> {
+    let input = input.trim();
+    let mut url = match Url::parse(page_url) {
+        Ok(page_url) => page_url.join(input),
+        Err(_) => Url::parse(input),
+    }
+    .ok()?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.set_username("").ok()?;
+    url.set_password(None).ok()?;
+
+    let url = url.to_string();
+    (url.len() <= MAX_FAVICON_URL_LEN).then_some(url)
+}
+
+/// Metadata for commented pages, keyed by url_hash. Any commenter's client
+/// may supply it; comments from established accounts refresh the fields they
+/// sent, others only fill in fields that are still empty.
+pub struct PageService {
+    pool: PgPool,
+}
+
+impl PageService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Stores metadata for a page, keeping stored values for fields that
+    /// are None, and for every field that is already set unless `replace`
+    pub async fn record(&self, url_hash: &str, metadata: &PageMetadata, replace: bool) -> Result<()> {
+        if metadata.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO pages (url_hash, title, description, site_name, language, favicon_url)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (url_hash) DO UPDATE
+            SET title = COALESCE(CASE WHEN $7 THEN EXCLUDED.title END, pages.title, EXCLUDED.title),
+                description = COALESCE(CASE WHEN $7 THEN EXCLUDED.description END, pages.description, EXCLUDED.description),
+                site_name = COALESCE(CASE WHEN $7 THEN EXCLUDED.site_name END, pages.site_name, EXCLUDED.site_name),
+                language = COALESCE(CASE WHEN $7 THEN EXCLUDED.language END, pages.language, EXCLUDED.language),
+                favicon_url = COALESCE(CASE WHEN $7 THEN EXCLUDED.favicon_url END, pages.favicon_url, EXCLUDED.favicon_url),
+                updated_at = NOW()
+            "#
+        )
+        .bind(url_hash)
+        .bind(&metadata.title)
+        .bind(&metadata.description)
+        .bind(&metadata.site_name)
+        .bind(&metadata.language)
+        .bind(&metadata.favicon_url)
+        .bind(replace)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::models::Page;
+    use crate::utils::normalize_url;
+
+    const PAGE_URL: &str = "https://news.example.com/2024/story?id=1";
+
+    #[test]
+    fn test_clean_text() {
+        assert_eq!(clean_text(Some("  Breaking\n\tnews \u{202E}today\u{0007} "), 100), Some("Breaking news today".to_string()));
+        assert_eq!(clean_text(Some(" \n "), 100), None);
+        assert_eq!(clean_text(None, 100), None);
+        assert_eq!(clean_text(Some("abcdef"), 6), Some("abcdef".to_string()));
+        assert_eq!(clean_text(Some("abc defg"), 6), Some("abc d…".to_string()));
+        assert_eq!(clean_text(Some("ab  cdefg"), 4), Some("ab…".to_string()));
+    }
+
+    #[test]
+    fn test_clean_language() {
+        assert_eq!(clean_language("en"), Some("en".to_string()));
+        assert_eq!(clean_language("EN_gb"), Some("en-GB".to_string()));
+        assert_eq!(clean_language("zh-hant-tw"), Some("zh-Hant-TW".to_string()));
+        assert_eq!(clean_language("es-419"), Some("es-419".to_string()));
+        assert_eq!(clean_language("english"), None);
+        assert_eq!(clean_language("en--GB"), None);
+        assert_eq!(clean_language("en-GB\"><script>"), None);
+        assert_eq!(clean_language(""), None);
+    }
+
+    #[test]
+    fn test_clean_favicon_url() {
+        assert_eq!(clean_favicon_url("/favicon.ico", PAGE_URL), Some("https://news.example.com/favicon.ico".to_string()));
+        assert_eq!(clean_favicon_url("icon.png", PAGE_URL), Some("https://news.example.com/2024/icon.png".to_string()));
+        assert_eq!(clean_favicon_url("//cdn.example.net/i.png", PAGE_URL), Some("https://cdn.example.net/i.png".to_string()));
+        assert_eq!(
+            clean_favicon_url("http://user:pw@cdn.example.net/i.png", PAGE_URL),
+            Some("http://cdn.example.net/i.png".to_string())
+        );
+        assert_eq!(clean_favicon_url("javascript:alert(1)", PAGE_URL), None);
+        assert_eq!(clean_favicon_url("data:image/png;base64,AAAA", PAGE_URL), None);
+        assert_eq!(clean_favicon_url(&format!("/{}", "a".repeat(MAX_FAVICON_URL_LEN)), PAGE_URL), None);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let input = PageMetadataInput {
+            title: Some("  A story ".to_string()),
+            language: Some("not a language".to_string()),
+            ..Default::default()
+        };
+        let metadata = PageMetadata::sanitize(&input, PAGE_URL);
+        assert_eq!(metadata.title, Some("A story".to_string()));
+        assert_eq!(metadata.language, None);
+        assert!(!metadata.is_empty());
+        assert!(PageMetadata::sanitize(&PageMetadataInput::default(), PAGE_URL).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_only_replacing_records_overwrite() {
+        let Some(pool) = testing::pool().await else { return };
+        let service = PageService::new(pool.clone());
+        let (_, url_hash) = normalize_url(&testing::unique_url()).unwrap();
+        let metadata = |title: &str, site_name: Option<&str>| PageMetadata {
+            title: Some(title.to_string()),
+            site_name: site_name.map(str::to_string),
+            ..Default::default()
+        };
+        let stored = || async {
+            sqlx::query_as::<_, Page>("SELECT * FROM pages WHERE url_hash = $1")
+                .bind(&url_hash)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+
+        service.record(&url_hash, &metadata("Original", None), false).await.unwrap();
+        service.record(&url_hash, &metadata("Defaced", Some("News")), false).await.unwrap();
+        let page = stored().await;
+        assert_eq!(page.title.as_deref(), Some("Original"));
+        assert_eq!(page.site_name.as_deref(), Some("News"));
+
+        service.record(&url_hash, &metadata("Updated", None), true).await.unwrap();
+        let page = stored().await;
+        assert_eq!(page.title.as_deref(), Some("Updated"));
+        assert_eq!(page.site_name.as_deref(), Some("News"));
+    }
+}
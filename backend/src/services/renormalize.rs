@@ -0,0 +1,194 @@
+use crate::models::UrlAlias;
+use crate::url_rules::UrlRules;
+use crate::utils::{create_url_hash, url_domain};
+use anyhow::Result;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Outcome of renormalizing one batch of comments
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenormalizeBatch {
+    /// Rows read, whatever the outcome
+    pub checked: u64,
+    /// Rows whose url_hash changed
+    pub rehashed: u64,
+    /// Rows whose original URL no longer parses; left as they are
+    pub failed: u64,
+    /// Keyset position to continue from, None once every row is done
+    pub last_id: Option<Uuid>,
+}
+
+/// Recomputes stored URL hashes with a given set of URL rules, after the
+/// rules or the normalizer changed. Comments are rewritten from the URL the
+/// client originally sent and tagged with the rules version, so an
+/// interrupted run can simply be started again.
+pub struct RenormalizeService {
+    pool: PgPool,
+}
+
+impl RenormalizeService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Number of comments per stored normalizer version; None is for comments
+    /// stored before versioning
+    pub async fn status(&self) -> Result<Vec<(Option<String>, i64)>> {
+        let counts = sqlx::query_as::<_, (Option<String>, i64)>(
+            "SELECT normalizer_version, COUNT(*) FROM comments GROUP BY normalizer_version ORDER BY COUNT(*) DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// Renormalizes up to `limit` comments not yet at the rules' version,
+    /// starting after `after_id`
+    pub async fn comments_batch(&self, rules: &UrlRules, after_id: Option<Uuid>, limit: i64) -> Result<RenormalizeBatch> {
+        let rows = sqlx::query_as::<_, (Uuid, String, String)>(
+            r#"
+            SELECT id, url, url_hash FROM comments
+            WHERE normalizer_version IS DISTINCT FROM $1 AND ($2::uuid IS NULL OR id > $2)
+            ORDER BY id
+            LIMIT $3
+            "#
+        )
+        .bind(rules.version())
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut batch = RenormalizeBatch {
+            checked: rows.len() as u64,
+            last_id: rows.last().map(|(id, _, _)| *id),
+            ..Default::default()
+        };
+        let mut ids = Vec::with_capacity(rows.len());
+        let mut normalized_urls = Vec::with_capacity(rows.len());
+        let mut url_hashes = Vec::with_capacity(rows.len());
+        let mut domains = Vec::with_capacity(rows.len());
+        let mut moved_hashes = (Vec::new(), Vec::new());
+        for (id, url, old_hash) in rows {
+            let Ok(normalized_url) = rules.canonicalize(&url).map(|url| url.to_string()) else {
+                batch.failed += 1;
+                continue;
+            };
+            let url_hash = create_url_hash(&normalized_url);
+            if url_hash != old_hash {
+                batch.rehashed += 1;
+                moved_hashes.0.push(old_hash);
+                moved_hashes.1.push(url_hash.clone());
+            }
+            ids.push(id);
+            domains.push(url_domain(&normalized_url));
+            normalized_urls.push(normalized_url);
+            url_hashes.push(url_hash);
+        }
+
+        // Only derived columns change, so comments mustn't look edited
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("SET LOCAL votp.keep_updated_at = 'on'").execute(&mut *tx).await?;
+        sqlx::query(
+            r#"
+            UPDATE comments c
+            SET normalized_url = v.normalized_url, url_hash = v.url_hash, domain = v.domain, normalizer_version = $5
+            FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[]) AS v(id, normalized_url, url_hash, domain)
+            WHERE c.id = v.id
+            "#
+        )
+        .bind(&ids)
+        .bind(&normalized_urls)
+        .bind(&url_hashes)
+        .bind(&domains)
+        .bind(rules.version())
+        .execute(&mut *tx)
+        .await?;
+
+        // Page metadata follows the comments to their new hash, unless the
+        // new hash already has its own. The old row stays for comments not
+        // renormalized yet.
+        sqlx::query(
+            r#"
+            INSERT INTO pages (url_hash, title, description, site_name, language, favicon_url, created_at, updated_at)
+            SELECT DISTINCT ON (m.new_hash) m.new_hash, p.title, p.description, p.site_name, p.language, p.favicon_url, p.created_at, p.updated_at
+            FROM UNNEST($1::text[], $2::text[]) AS m(old_hash, new_hash)
+            JOIN pages p ON p.url_hash = m.old_hash
+            ORDER BY m.new_hash, p.updated_at DESC
+            ON CONFLICT (url_hash) DO NOTHING
+            "#
+        )
+        .bind(&moved_hashes.0)
+        .bind(&moved_hashes.1)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(batch)
+    }
+
+    /// Rehashes every URL alias. Aliases store normalized URLs only, so they
+    /// are renormalized from those. Aliases that now normalize to their
+    /// canonical URL are removed, as are all but the oldest of aliases that
+    /// now share a hash. Returns how many aliases were kept and removed.
+    pub async fn aliases(&self, rules: &UrlRules) -> Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query_as::<_, UrlAlias>("SELECT * FROM url_aliases ORDER BY created_at, alias_hash FOR UPDATE")
+            .fetch_all(&mut *tx)
+            .await?;
+        let total = rows.len() as u64;
+
+        let normalize = |url: &str| {
+            let normalized_url = rules.canonicalize(url).map_or_else(|_| url.to_string(), |url| url.to_string());
+            (create_url_hash(&normalized_url), normalized_url)
+        };
+        let mut aliases: Vec<UrlAlias> = Vec::with_capacity(rows.len());
+        let mut positions = HashMap::new();
+        for mut alias in rows {
+            (alias.alias_hash, alias.alias_url) = normalize(&alias.alias_url);
+            (alias.canonical_hash, alias.canonical_url) = normalize(&alias.canonical_url);
+            if alias.alias_hash == alias.canonical_hash || positions.contains_key(&alias.alias_hash) {
+                continue;
+            }
+            positions.insert(alias.alias_hash.clone(), aliases.len());
+            aliases.push(alias);
+        }
+
+        // Keep aliases one hop: a canonical URL that became an alias itself
+        // hands its aliases on to its own canonical URL
+        for index in 0..aliases.len() {
+            if let Some(&target) = positions.get(&aliases[index].canonical_hash) {
+                let target = &aliases[target];
+                let (canonical_hash, canonical_url) = (target.canonical_hash.clone(), target.canonical_url.clone());
+                aliases[index].canonical_hash = canonical_hash;
+                aliases[index].canonical_url = canonical_url;
+            }
+        }
+        aliases.retain(|alias| alias.alias_hash != alias.canonical_hash);
+
+        let column = |field: fn(&UrlAlias) -> String| aliases.iter().map(field).collect::<Vec<_>>();
+        sqlx::query("DELETE FROM url_aliases").execute(&mut *tx).await?;
+        sqlx::query(
+            r#"
+            INSERT INTO url_aliases (alias_hash, alias_url, canonical_hash, canonical_url, source, created_by, created_at)
+            SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::uuid[], $7::timestamptz[])
+            "#
+        )
+        .bind(column(|alias| alias.alias_hash.clone()))
+        .bind(column(|alias| alias.alias_url.clone()))
+        .bind(column(|alias| alias.canonical_hash.clone()))
+        .bind(column(|alias| alias.canonical_url.clone()))
+        .bind(column(|alias| alias.source.clone()))
+        .bind(aliases.iter().map(|alias| alias.created_by).collect::<Vec<_>>())
+        .bind(aliases.iter().map(|alias| alias.created_at).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        let kept = aliases.len() as u64;
+        Ok((kept, total - kept))
+    }
+}
@@ -0,0 +1,85 @@
+use crate::models::CommentReport;
+use crate::services::reputation::{ReputationService, TrustLevel};
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Reports of abusive comments and their review by moderators. Each report
+/// is weighted by the reporter's trust level so the review queue surfaces
+/// comments flagged by established users first.
+pub struct ReportService {
+    pool: PgPool,
+}
+
+impl ReportService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Files or updates a report; returns None if the comment doesn't exist
+    pub async fn report(&self, reporter_id: Uuid, reporter_level: TrustLevel, comment_id: Uuid, reason: &str) -> Result<Option<CommentReport>> {
+        let report = sqlx::query_as::<_, CommentReport>(
+            r#"
+            INSERT INTO comment_reports (comment_id, reporter_id, author_id, reason, weight)
+            SELECT id, $2, user_id, $3, $4 FROM comments WHERE id = $1
+            ON CONFLICT (comment_id, reporter_id) DO UPDATE SET reason = EXCLUDED.reason, weight = EXCLUDED.weight
+            RETURNING *
+            "#
+        )
+        .bind(comment_id)
+        .bind(reporter_id)
+        .bind(reason)
+        .bind(reporter_level.report_weight())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(report)
+    }
+
+    /// Open reports, heaviest total weight per comment first
+    pub async fn open_reports(&self, limit: i64) -> Result<Vec<CommentReport>> {
+        let reports = sqlx::query_as::<_, CommentReport>(
+            r#"
+            SELECT r.* FROM comment_reports r
+            JOIN (
+                SELECT comment_id, SUM(weight) AS total_weight FROM comment_reports
+                WHERE status = 'open' GROUP BY comment_id
+            ) t ON t.comment_id = r.comment_id
+            WHERE r.status = 'open'
+            ORDER BY t.total_weight DESC, r.comment_id, r.created_at
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(reports)
+    }
+
+    /// Accepts or rejects an open report and updates the reputation of the
+    /// reporter and the comment's author; returns None if no open report matched
+    pub async fn resolve(&self, report_id: Uuid, moderator_id: Uuid, accept: bool) -> Result<Option<CommentReport>> {
+        let report = sqlx::query_as::<_, CommentReport>(
+            r#"
+            UPDATE comment_reports
+            SET status = $2, resolved_by = $3, resolved_at = NOW()
+            WHERE id = $1 AND status = 'open'
+            RETURNING *
+            "#
+        )
+        .bind(report_id)
+        .bind(if accept { "accepted" } else { "rejected" })
+        .bind(moderator_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(report) = &report {
+            let reputation = ReputationService::new(self.pool.clone());
+            reputation.recompute_user(report.reporter_id).await?;
+            reputation.recompute_user(report.author_id).await?;
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use async_graphql::Enum;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Points per net vote received on a comment from another user
+const VOTE_POINTS: i64 = 1;
+/// Points for each report the user filed that a moderator accepted
+const REPORT_FILED_POINTS: i64 = 5;
+/// Points lost for each accepted report against one of the user's comments
+const REPORT_AGAINST_POINTS: i64 = -15;
+/// Account age earns a point a day, up to this many
+const MAX_AGE_POINTS: i64 = 30;
+/// Past this age in days neither age points nor the age thresholds can
+/// change a user's score or level any more
+const AGE_SETTLED_DAYS: i64 = 31;
+
+/// Privilege tier derived from reputation and account age. Levels only ever
+/// come from `TrustLevel::for_score`, so the stored value is a cache.
+#[derive(Enum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrustLevel {
+    /// Brand-new or negatively scored accounts
+    New,
+    Basic,
+    Member,
+    Trusted,
+}
+
+impl TrustLevel {
+    /// Minimum score and account age in days for each level above New
+    const THRESHOLDS: [(TrustLevel, i64, i64); 3] = [
+        (TrustLevel::Trusted, 100, 30),
+        (TrustLevel::Member, 25, 7),
+        (TrustLevel::Basic, 3, 2),
+    ];
+
+    pub fn for_score(score: i64, age_days: i64) -> Self {
+        Self::THRESHOLDS
+            .iter()
+            .find(|(_, min_score, min_age)| score >= *min_score && age_days >= *min_age)
+            .map(|(level, _, _)| *level)
+            .unwrap_or(TrustLevel::New)
+    }
+
+    pub fn from_i16(value: i16) -> Self {
+        match value {
+            3 => TrustLevel::Trusted,
+            2 => TrustLevel::Member,
+            1 => TrustLevel::Basic,
+            _ => TrustLevel::New,
+        }
+    }
+
+    pub fn as_i16(self) -> i16 {
+        self as i16
+    }
+
+    /// New accounts can't put links in comments, the cheapest form of spam
+    pub fn can_post_links(self) -> bool {
+        self > TrustLevel::New
+    }
+
+    /// Page metadata from lower levels only fills fields nobody has set yet,
+    /// so a new account can't rename a page for everyone
+    pub fn can_replace_page_metadata(self) -> bool {
+        self >= TrustLevel::Member
+    }
+
+    /// Comments allowed per rolling hour
+    pub fn comments_per_hour(self) -> i64 {
+        match self {
+            TrustLevel::New => 5,
+            TrustLevel::Basic => 20,
+            TrustLevel::Member => 60,
+            TrustLevel::Trusted => 200,
+        }
+    }
+
+    /// How much a report filed by this user counts towards a comment's review priority
+    pub fn report_weight(self) -> i32 {
+        match self {
+            TrustLevel::New | TrustLevel::Basic => 1,
+            TrustLevel::Member => 2,
+            TrustLevel::Trusted => 4,
+        }
+    }
+
+    /// How long after posting a comment may still be edited; None means no limit
+    pub fn edit_window(self) -> Option<Duration> {
+        match self {
+            TrustLevel::New => Some(Duration::minutes(5)),
+            TrustLevel::Basic => Some(Duration::minutes(30)),
+            TrustLevel::Member => Some(Duration::hours(24)),
+            TrustLevel::Trusted => None,
+        }
+    }
+}
+
+/// Rough check for links in comment text: anything a reader's client would
+/// likely turn into a clickable URL
+pub fn contains_link(content: &str) -> bool {
+    let content = content.to_lowercase();
+    ["http://", "https://", "www."].iter().any(|marker| content.contains(marker))
+}
+
+/// What a user's reputation is computed from
+#[derive(Debug, Clone, FromRow)]
+pub struct ReputationInputs {
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// The stored level before this computation
+    pub trust_level: i16,
+    pub net_votes: i64,
+    pub reports_filed: i64,
+    pub reports_against: i64,
+}
+
+impl ReputationInputs {
+    pub fn age_days(&self, now: DateTime<Utc>) -> i64 {
+        (now - self.created_at).num_days().max(0)
+    }
+
+    pub fn score(&self, now: DateTime<Utc>) -> i64 {
+        self.net_votes * VOTE_POINTS
+            + self.reports_filed * REPORT_FILED_POINTS
+            + self.reports_against * REPORT_AGAINST_POINTS
+            + self.age_days(now).min(MAX_AGE_POINTS)
+    }
+}
+
+/// Keeps `users.reputation` and `users.trust_level` up to date. Votes and
+/// report decisions recompute the affected users straight away; the batch
+/// job recomputes accounts young enough to still earn age points, and those
+/// marked stale because a voter of theirs moved in or out of New.
+pub struct ReputationService {
+    pool: PgPool,
+}
+
+impl ReputationService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn trust_level(&self, user_id: Uuid) -> Result<TrustLevel> {
+        let level = sqlx::query_scalar::<_, i16>("SELECT trust_level FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(TrustLevel::from_i16(level))
+    }
+
+    /// Recomputes one user's score, e.g. after they received a vote
+    pub async fn recompute_user(&self, user_id: Uuid) -> Result<()> {
+        self.recompute(Some(user_id), false).await.map(|_| ())
+    }
+
+    /// Recomputes users whose score may have changed since the last run;
+    /// returns how many changed
+    pub async fn recompute_due(&self) -> Result<u64> {
+        self.recompute(None, false).await
+    }
+
+    /// Recomputes every user; returns how many changed
+    pub async fn recompute_all(&self) -> Result<u64> {
+        self.recompute(None, true).await
+    }
+
+    async fn recompute(&self, user_id: Option<Uuid>, all: bool) -> Result<u64> {
+        let inputs = sqlx::query_as::<_, ReputationInputs>(
+            r#"
+            SELECT
+                u.id AS user_id,
+                u.created_at,
+                u.trust_level,
+                -- Votes from New accounts carry no weight, so a batch of
+                -- throwaway accounts can't vote each other up
+                COALESCE((
+                    SELECT SUM(v.value)::BIGINT FROM comment_votes v
+                    JOIN comments c ON c.id = v.comment_id
+                    JOIN users voter ON voter.id = v.user_id
+                    WHERE c.user_id = u.id AND v.user_id <> u.id AND voter.trust_level > 0
+                ), 0) AS net_votes,
+                (SELECT COUNT(*) FROM comment_reports r WHERE r.reporter_id = u.id AND r.status = 'accepted') AS reports_filed,
+                (SELECT COUNT(*) FROM comment_reports r WHERE r.author_id = u.id AND r.status = 'accepted') AS reports_against
+            FROM users u
+            WHERE u.id = $1
+               OR ($1::uuid IS NULL AND ($2 OR u.reputation_stale OR u.created_at > NOW() - make_interval(days => $3)))
+            "#
+        )
+        .bind(user_id)
+        .bind(all)
+        .bind(AGE_SETTLED_DAYS as i32)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = Utc::now();
+        let mut ids = Vec::with_capacity(inputs.len());
+        let mut scores = Vec::with_capacity(inputs.len());
+        let mut levels = Vec::with_capacity(inputs.len());
+        let mut reweighted_voters = Vec::new();
+        for input in &inputs {
+            let score = input.score(now);
+            let level = TrustLevel::for_score(score, input.age_days(now));
+            if (level == TrustLevel::New) != (TrustLevel::from_i16(input.trust_level) == TrustLevel::New) {
+                reweighted_voters.push(input.user_id);
+            }
+            ids.push(input.user_id);
+            scores.push(score.clamp(i32::MIN as i64, i32::MAX as i64) as i32);
+            levels.push(level.as_i16());
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query(
+            r#"
+            UPDATE users u
+            SET reputation = v.score, trust_level = v.level, reputation_stale = FALSE
+            FROM UNNEST($1::uuid[], $2::int[], $3::smallint[]) AS v(id, score, level)
+            WHERE u.id = v.id AND (u.reputation, u.trust_level, u.reputation_stale) IS DISTINCT FROM (v.score, v.level, FALSE)
+            "#
+        )
+        .bind(&ids)
+        .bind(&scores)
+        .bind(&levels)
+        .execute(&mut *tx)
+        .await?;
+
+        // The votes these users cast have started or stopped counting, so
+        // the next batch run picks up everyone they voted for
+        if !reweighted_voters.is_empty() {
+            sqlx::query(
+                r#"
+                UPDATE users SET reputation_stale = TRUE
+                WHERE id IN (
+                    SELECT c.user_id FROM comment_votes v
+                    JOIN comments c ON c.id = v.comment_id
+                    WHERE v.user_id = ANY($1) AND c.user_id <> v.user_id
+                )
+                "#
+            )
+            .bind(&reweighted_voters)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(updated.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::services::votes::VoteService;
+
+    fn inputs(age_days: i64, net_votes: i64, reports_filed: i64, reports_against: i64) -> (ReputationInputs, DateTime<Utc>) {
+        let now = Utc::now();
+        let inputs = ReputationInputs {
+            user_id: Uuid::new_v4(),
+            created_at: now - Duration::days(age_days),
+            trust_level: 0,
+            net_votes,
+            reports_filed,
+            reports_against,
+        };
+        (inputs, now)
+    }
+
+    #[test]
+    fn test_score_combines_votes_reports_and_age() {
+        let (fresh, now) = inputs(0, 0, 0, 0);
+        assert_eq!(fresh.score(now), 0);
+
+        let (veteran, now) = inputs(400, 10, 2, 1);
+        assert_eq!(veteran.score(now), 10 + 10 - 15 + MAX_AGE_POINTS);
+    }
+
+    #[test]
+    fn test_trust_level_needs_score_and_age() {
+        assert_eq!(TrustLevel::for_score(0, 0), TrustLevel::New);
+        assert_eq!(TrustLevel::for_score(3, 0), TrustLevel::New);
+        assert_eq!(TrustLevel::for_score(500, 1), TrustLevel::New);
+        assert_eq!(TrustLevel::for_score(3, 2), TrustLevel::Basic);
+        assert_eq!(TrustLevel::for_score(500, 2), TrustLevel::Basic);
+        assert_eq!(TrustLevel::for_score(25, 7), TrustLevel::Member);
+        assert_eq!(TrustLevel::for_score(100, 29), TrustLevel::Member);
+        assert_eq!(TrustLevel::for_score(100, 30), TrustLevel::Trusted);
+        assert_eq!(TrustLevel::for_score(-10, 365), TrustLevel::New);
+    }
+
+    #[test]
+    fn test_age_settles_after_every_age_rule() {
+        for days in TrustLevel::THRESHOLDS.iter().map(|(_, _, min_age)| *min_age).chain([MAX_AGE_POINTS]) {
+            assert!(AGE_SETTLED_DAYS > days);
+        }
+    }
+
+    #[test]
+    fn test_trust_level_round_trips_through_storage() {
+        for level in [TrustLevel::New, TrustLevel::Basic, TrustLevel::Member, TrustLevel::Trusted] {
+            assert_eq!(TrustLevel::from_i16(level.as_i16()), level);
+        }
+    }
+
+    #[test]
+    fn test_contains_link() {
+        assert!(contains_link("see HTTPS://example.com"));
+        assert!(contains_link("go to www.example.com"));
+        assert!(!contains_link("no links here, just example.com talk"));
+    }
+
+    #[test]
+    fn test_privileges_grow_with_level() {
+        assert!(!TrustLevel::New.can_post_links());
+        assert!(TrustLevel::Basic.can_post_links());
+        assert!(!TrustLevel::Basic.can_replace_page_metadata());
+        assert!(TrustLevel::Member.can_replace_page_metadata());
+        assert!(TrustLevel::New.comments_per_hour() < TrustLevel::Trusted.comments_per_hour());
+        assert!(TrustLevel::New.report_weight() < TrustLevel::Trusted.report_weight());
+        assert!(TrustLevel::New.edit_window() < TrustLevel::Member.edit_window());
+        assert_eq!(TrustLevel::Trusted.edit_window(), None);
+    }
+
+    #[tokio::test]
+    async fn test_votes_from_new_accounts_count_once_the_voter_levels_up() {
+        let Some(pool) = testing::pool().await else { return };
+        let (author, voter) = (testing::create_user(&pool).await, testing::create_user(&pool).await);
+        let comment_id = testing::create_comment(&pool, author, &testing::unique_url()).await;
+        let reputation = |user_id: Uuid| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_as::<_, (i32, bool)>("SELECT reputation, reputation_stale FROM users WHERE id = $1")
+                    .bind(user_id)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        VoteService::new(pool.clone()).vote(voter, comment_id, 1).await.unwrap();
+        assert_eq!(reputation(author).await, (0, false));
+
+        // Old enough to leave New on age points alone
+        sqlx::query("UPDATE users SET created_at = NOW() - INTERVAL '40 days' WHERE id = $1")
+            .bind(voter)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let service = ReputationService::new(pool.clone());
+        service.recompute_user(voter).await.unwrap();
+        assert_eq!(service.trust_level(voter).await.unwrap(), TrustLevel::Member);
+        assert_eq!(reputation(author).await, (0, true));
+
+        service.recompute_due().await.unwrap();
+        assert_eq!(reputation(author).await, (1, false));
+    }
+}
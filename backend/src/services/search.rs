@@ -0,0 +1,204 @@
+use crate::graphql::pagination::RankCursor;
+use crate::models::Comment;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Comment languages with a Postgres text search configuration, as ISO 639-1
+/// code and configuration name. Comments in any other language, or with no
+/// language, are indexed with the `simple` configuration only.
+pub const SEARCH_LANGUAGES: &[(&str, &str)] = &[
+    ("da", "danish"),
+    ("de", "german"),
+    ("en", "english"),
+    ("es", "spanish"),
+    ("fi", "finnish"),
+    ("fr", "french"),
+    ("hu", "hungarian"),
+    ("it", "italian"),
+    ("nl", "dutch"),
+    ("no", "norwegian"),
+    ("pt", "portuguese"),
+    ("ro", "romanian"),
+    ("ru", "russian"),
+    ("sv", "swedish"),
+    ("tr", "turkish"),
+];
+
+// ts_headline wraps matches in these private-use characters, which are
+// replaced with <mark> tags once the rest of the snippet has been escaped
+const MATCH_START: char = '\u{E000}';
+const MATCH_END: char = '\u{E001}';
+
+/// Normalizes a comment language to a supported code, or None if unsupported
+pub fn search_language(language: &str) -> Option<&'static str> {
+    let language = language.trim().to_lowercase();
+    let code = language.split(['-', '_']).next().unwrap_or_default();
+    SEARCH_LANGUAGES
+        .iter()
+        .find(|(supported, _)| *supported == code)
+        .map(|(supported, _)| *supported)
+}
+
+/// SQL for the IMMUTABLE `search_config(language)` function used by the
+/// generated `comments.search_vector` column. Changing the language list
+/// only affects rows written afterwards.
+pub fn search_config_function_sql() -> String {
+    let cases: String = SEARCH_LANGUAGES
+        .iter()
+        .map(|(code, config)| format!("WHEN '{}' THEN '{}'::regconfig ", code, config))
+        .collect();
+
+    format!(
+        "CREATE OR REPLACE FUNCTION search_config(language TEXT) RETURNS regconfig AS $$ \
+         SELECT CASE language {}ELSE 'simple'::regconfig END \
+         $$ LANGUAGE sql IMMUTABLE",
+        cases
+    )
+}
+
+/// Escapes a ts_headline snippet for HTML and turns match markers into `<mark>` tags
+pub fn snippet_html(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilters {
+    /// Normalized host; subdomains match too
+    pub domain: Option<String>,
+    pub url_hash: Option<String>,
+    pub author_id: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Supported language code; stems the query and restricts results to that language
+    pub language: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct SearchHit {
+    #[sqlx(flatten)]
+    pub comment: Comment,
+    pub rank: f32,
+    /// ts_headline output with raw match markers; see `snippet_html`
+    pub snippet: String,
+}
+
+impl SearchHit {
+    pub fn cursor(&self) -> RankCursor {
+        RankCursor { rank: self.rank, at: self.comment.created_at, id: self.comment.id }
+    }
+}
+
+/// Full-text search over comments using the GIN-indexed `search_vector` column
+pub struct SearchService {
+    pool: PgPool,
+}
+
+impl SearchService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Matches `query` in web search syntax ("quoted phrases", or, -excluded),
+    /// most relevant first; fetches `limit` rows
+    pub async fn search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        viewer_id: Option<Uuid>,
+        after: Option<RankCursor>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>> {
+        let headline_options = format!(
+            "StartSel={}, StopSel={}, MaxFragments=2, MinWords=5, MaxWords=20, FragmentDelimiter=\" … \"",
+            MATCH_START, MATCH_END
+        );
+
+        let hits = sqlx::query_as::<_, SearchHit>(
+            r#"
+            WITH q AS (SELECT websearch_to_tsquery(search_config($2), $1) AS query)
+            SELECT m.*, ts_headline(search_config($2), m.content, q.query, $13) AS snippet
+            FROM (
+                SELECT * FROM (
+                    SELECT c.*, ts_rank(c.search_vector, q.query) AS rank
+                    FROM comments c, q
+                    WHERE c.search_vector @@ q.query
+                      AND NOT is_blocked_between($3, c.user_id)
+                      AND ($2::text IS NULL OR c.language = $2)
+                      AND ($4::text IS NULL OR c.url_hash = $4)
+                      AND ($5::uuid IS NULL OR c.user_id = $5)
+                      AND ($6::timestamptz IS NULL OR c.created_at >= $6)
+                      AND ($7::timestamptz IS NULL OR c.created_at < $7)
+                      AND ($8::text IS NULL OR url_host(c.normalized_url) = $8
+                           OR right(url_host(c.normalized_url), length($8) + 1) = '.' || $8)
+                ) ranked
+                WHERE $9::real IS NULL OR (ranked.rank, ranked.created_at, ranked.id) < ($9, $10, $11)
+                ORDER BY ranked.rank DESC, ranked.created_at DESC, ranked.id DESC
+                LIMIT $12
+            ) m, q
+            ORDER BY m.rank DESC, m.created_at DESC, m.id DESC
+            "#
+        )
+        .bind(query)
+        .bind(filters.language)
+        .bind(viewer_id)
+        .bind(&filters.url_hash)
+        .bind(filters.author_id)
+        .bind(filters.since)
+        .bind(filters.until)
+        .bind(&filters.domain)
+        .bind(after.map(|cursor| cursor.rank))
+        .bind(after.map(|cursor| cursor.at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .bind(headline_options)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_language() {
+        assert_eq!(search_language("en"), Some("en"));
+        assert_eq!(search_language("en-GB"), Some("en"));
+        assert_eq!(search_language(" DE "), Some("de"));
+        assert_eq!(search_language("pt_BR"), Some("pt"));
+        assert_eq!(search_language("ja"), None);
+        assert_eq!(search_language(""), None);
+    }
+
+    #[test]
+    fn test_search_config_function_covers_every_language() {
+        let sql = search_config_function_sql();
+        for (code, config) in SEARCH_LANGUAGES {
+            assert!(sql.contains(&format!("WHEN '{}' THEN '{}'::regconfig", code, config)));
+        }
+        assert!(sql.contains("IMMUTABLE"));
+    }
+
+    #[test]
+    fn test_snippet_html_escapes_content_but_keeps_marks() {
+        let raw = format!("a <b>&</b> {}match{} \"q\"", MATCH_START, MATCH_END);
+        assert_eq!(snippet_html(&raw), "a &lt;b&gt;&amp;&lt;/b&gt; <mark>match</mark> &quot;q&quot;");
+    }
+}
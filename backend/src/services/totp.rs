@@ -0,0 +1,177 @@
+use anyhow::Result;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+
+/// Length of each time step in seconds (RFC 6238 default)
+const TIME_STEP: i64 = 30;
+/// Number of digits in a generated code
+const DIGITS: u32 = 6;
+/// Accepted clock drift, in time steps, on either side of the current step
+const ALLOWED_DRIFT: i64 = 1;
+/// Number of recovery codes issued on enrollment
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Alphabet for recovery codes, without easily confused characters
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+pub struct TotpService {
+    issuer: String,
+}
+
+impl TotpService {
+    pub fn new(issuer: String) -> Self {
+        Self { issuer }
+    }
+
+    /// Generates a new random 160-bit shared secret, base32 encoded
+    pub fn generate_secret(&self) -> String {
+        let mut secret = [0u8; 20];
+        rand::rng().fill(&mut secret);
+        BASE32_NOPAD.encode(&secret)
+    }
+
+    /// Builds the otpauth:// URI understood by authenticator apps
+    pub fn provisioning_uri(&self, secret: &str, account: &str) -> String {
+        let label = format!("{}:{}", self.issuer, account);
+        format!(
+            "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            url::form_urlencoded::byte_serialize(label.as_bytes()).collect::<String>(),
+            secret,
+            url::form_urlencoded::byte_serialize(self.issuer.as_bytes()).collect::<String>(),
+            DIGITS,
+            TIME_STEP,
+        )
+    }
+
+    /// Generates the code for a given time step
+    pub fn generate_code(&self, secret: &str, step: i64) -> Result<String> {
+        let key = BASE32_NOPAD
+            .decode(secret.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {}", e))?;
+
+        let mut mac = Hmac::<Sha1>::new_from_slice(&key)
+            .map_err(|e| anyhow::anyhow!("Invalid TOTP key: {}", e))?;
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // Dynamic truncation (RFC 4226 section 5.3)
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+
+        Ok(format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize))
+    }
+
+    /// Verifies a code at the given unix time, returning the matched time step.
+    ///
+    /// Steps at or before `last_used_step` are rejected so a code cannot be replayed.
+    pub fn verify_code(
+        &self,
+        secret: &str,
+        code: &str,
+        unix_time: i64,
+        last_used_step: Option<i64>,
+    ) -> Result<Option<i64>> {
+        let code = code.trim();
+        if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(None);
+        }
+
+        let current_step = unix_time / TIME_STEP;
+        for step in (current_step - ALLOWED_DRIFT)..=(current_step + ALLOWED_DRIFT) {
+            if last_used_step.is_some_and(|last| step <= last) {
+                continue;
+            }
+            if self.generate_code(secret, step)? == code {
+                return Ok(Some(step));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Generates a fresh set of single-use recovery codes in `xxxxx-xxxxx` form
+    pub fn generate_recovery_codes(&self) -> Vec<String> {
+        let mut rng = rand::rng();
+        (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let chars: String = (0..10)
+                    .map(|_| {
+                        RECOVERY_CODE_ALPHABET[rng.random_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                    })
+                    .collect();
+                format!("{}-{}", &chars[..5], &chars[5..])
+            })
+            .collect()
+    }
+
+    /// Canonical form of a recovery code as typed by the user, used before hashing
+    pub fn normalize_recovery_code(code: &str) -> String {
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B test secret ("12345678901234567890")
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let totp = TotpService::new("VOTP".to_string());
+        let vectors = vec![
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ];
+
+        for (time, expected) in vectors {
+            assert_eq!(totp.generate_code(RFC_SECRET, time / TIME_STEP).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_verify_code_with_drift_and_replay() {
+        let totp = TotpService::new("VOTP".to_string());
+        let secret = totp.generate_secret();
+        let now = 1_700_000_000;
+        let previous = totp.generate_code(&secret, now / TIME_STEP - 1).unwrap();
+
+        let step = totp.verify_code(&secret, &previous, now, None).unwrap();
+        assert_eq!(step, Some(now / TIME_STEP - 1));
+        assert_eq!(totp.verify_code(&secret, &previous, now, step).unwrap(), None);
+        assert_eq!(totp.verify_code(&secret, "12345", now, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let totp = TotpService::new("VOTP".to_string());
+        let codes = totp.generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(
+            TotpService::normalize_recovery_code(&codes[0].to_uppercase()),
+            codes[0].replace('-', "")
+        );
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let totp = TotpService::new("VOTP".to_string());
+        let uri = totp.provisioning_uri("ABC", "user@example.com");
+
+        assert!(uri.starts_with("otpauth://totp/VOTP%3Auser%40example.com?secret=ABC&issuer=VOTP"));
+    }
+}
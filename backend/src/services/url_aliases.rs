@@ -0,0 +1,360 @@
+use crate::models::{PendingUrlAlias, UrlAlias};
+use crate::utils::url_domain;
+use anyhow::{bail, Result};
+use sqlx::PgPool;
+use url::Url;
+use uuid::Uuid;
+
+/// Google's AMP cache, which serves `example.com/a` as
+/// `example-com.cdn.ampproject.org/c/s/example.com/a`
+const AMP_CACHE_SUFFIX: &str = ".cdn.ampproject.org";
+
+/// Distinct accounts that must report the same canonical URL for a page
+/// before its thread is merged; until then moderators see it as pending
+pub const REPORTERS_TO_ALIAS: i64 = 3;
+
+/// Whether a canonical URL reported for a page is believable enough to merge
+/// the page's thread into it. Both URLs must be normalized. The canonical
+/// page has to be on the same site, or be the origin of an AMP cache page,
+/// and can't be the site's front page unless the page itself is one, since
+/// soft-404 and paywall pages often point there.
+pub fn trusted_canonical(alias_url: &str, canonical_url: &str) -> bool {
+    let (Ok(alias), Ok(canonical)) = (Url::parse(alias_url), Url::parse(canonical_url)) else {
+        return false;
+    };
+    let web = |url: &Url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some();
+    if !web(&alias) || !web(&canonical) {
+        return false;
+    }
+
+    if canonical.path() == "/" && canonical.query().is_none() && alias.path() != "/" {
+        return false;
+    }
+
+    let alias_host = alias.host_str().unwrap_or_default();
+    let canonical_host = canonical.host_str().unwrap_or_default();
+    if alias_host.ends_with(AMP_CACHE_SUFFIX) {
+        return alias.path_segments().is_some_and(|mut segments| segments.any(|segment| segment == canonical_host));
+    }
+
+    url_domain(alias_url) == url_domain(canonical_url)
+}
+
+/// Maps URL hashes onto the thread they belong to. An alias points straight
+/// at its canonical hash; chains are never stored, so resolving is one lookup.
+pub struct UrlAliasService {
+    pool: PgPool,
+}
+
+impl UrlAliasService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// The alias entry for a URL hash, if it has been merged into another thread
+    pub async fn alias(&self, url_hash: &str) -> Result<Option<UrlAlias>> {
+        let alias = sqlx::query_as::<_, UrlAlias>("SELECT * FROM url_aliases WHERE alias_hash = $1")
+            .bind(url_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(alias)
+    }
+
+    /// Hash of the thread a URL hash belongs to
+    pub async fn resolve(&self, url_hash: &str) -> Result<String> {
+        Ok(self.alias(url_hash).await?.map_or_else(|| url_hash.to_string(), |alias| alias.canonical_hash))
+    }
+
+    /// Every URL hash whose comments belong in the same thread as `url_hash`,
+    /// canonical hash first
+    pub async fn thread_hashes(&self, url_hash: &str) -> Result<Vec<String>> {
+        let canonical_hash = self.resolve(url_hash).await?;
+        let mut hashes = vec![canonical_hash.clone()];
+        hashes.extend(self.aliases_of(&canonical_hash).await?.into_iter().map(|alias| alias.alias_hash));
+
+        Ok(hashes)
+    }
+
+    /// URLs merged into the thread with this canonical hash
+    pub async fn aliases_of(&self, canonical_hash: &str) -> Result<Vec<UrlAlias>> {
+        let aliases = sqlx::query_as::<_, UrlAlias>(
+            "SELECT * FROM url_aliases WHERE canonical_hash = $1 ORDER BY created_at, alias_hash"
+        )
+        .bind(canonical_hash)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(aliases)
+    }
+
+    /// Records a canonical URL reported by a client for the page at
+    /// `alias_url`. Each account has one report per page, replaced by its
+    /// latest. Once `REPORTERS_TO_ALIAS` accounts agree the alias takes
+    /// effect, unless the page already has one or is itself a canonical URL;
+    /// those take a moderator. Returns the alias if this report created it.
+    pub async fn report(
+        &self,
+        user_id: Uuid,
+        (alias_url, alias_hash): (&str, &str),
+        (canonical_url, canonical_hash): (&str, &str),
+    ) -> Result<Option<UrlAlias>> {
+        if alias_hash == canonical_hash || !trusted_canonical(alias_url, canonical_url) {
+            return Ok(None);
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO url_alias_reports (alias_hash, user_id, alias_url, canonical_hash, canonical_url)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (alias_hash, user_id) DO UPDATE
+            SET alias_url = EXCLUDED.alias_url,
+                canonical_hash = EXCLUDED.canonical_hash,
+                canonical_url = EXCLUDED.canonical_url,
+                created_at = NOW()
+            "#
+        )
+        .bind(alias_hash)
+        .bind(user_id)
+        .bind(alias_url)
+        .bind(canonical_hash)
+        .bind(canonical_url)
+        .execute(&mut *tx)
+        .await?;
+
+        let reporters = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM url_alias_reports WHERE alias_hash = $1 AND canonical_hash = $2"
+        )
+        .bind(alias_hash)
+        .bind(canonical_hash)
+        .fetch_one(&mut *tx)
+        .await?;
+        if reporters < REPORTERS_TO_ALIAS {
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        // Alias writers take turns, so two merges can't each pass the checks
+        // below and together leave a chain
+        sqlx::query("LOCK TABLE url_aliases IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+
+        // Point at the end of the chain, unless that leads back to this page
+        let target = sqlx::query_as::<_, UrlAlias>("SELECT * FROM url_aliases WHERE alias_hash = $1")
+            .bind(canonical_hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let (canonical_url, canonical_hash) = match target {
+            Some(target) if target.canonical_hash == alias_hash => {
+                tx.commit().await?;
+                return Ok(None);
+            }
+            Some(target) => (target.canonical_url, target.canonical_hash),
+            None => (canonical_url.to_string(), canonical_hash.to_string()),
+        };
+        let has_aliases = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM url_aliases WHERE canonical_hash = $1)")
+            .bind(alias_hash)
+            .fetch_one(&mut *tx)
+            .await?;
+        if has_aliases {
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        let alias = sqlx::query_as::<_, UrlAlias>(
+            r#"
+            INSERT INTO url_aliases (alias_hash, alias_url, canonical_hash, canonical_url, source, created_by)
+            VALUES ($1, $2, $3, $4, 'reported', $5)
+            ON CONFLICT (alias_hash) DO NOTHING
+            RETURNING *
+            "#
+        )
+        .bind(alias_hash)
+        .bind(alias_url)
+        .bind(&canonical_hash)
+        .bind(&canonical_url)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if alias.is_some() {
+            sqlx::query("DELETE FROM url_alias_reports WHERE alias_hash = $1")
+                .bind(alias_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(alias)
+    }
+
+    /// Reported canonical URLs still waiting for enough reporters, most
+    /// reported first, for moderators to merge by hand
+    pub async fn pending(&self, limit: i64) -> Result<Vec<PendingUrlAlias>> {
+        let pending = sqlx::query_as::<_, PendingUrlAlias>(
+            r#"
+            SELECT alias_hash, MIN(alias_url) AS alias_url, canonical_hash, MIN(canonical_url) AS canonical_url,
+                   COUNT(*) AS reporters, MIN(created_at) AS first_reported_at
+            FROM url_alias_reports
+            GROUP BY alias_hash, canonical_hash
+            ORDER BY reporters DESC, first_reported_at ASC
+            LIMIT $1
+            "#
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(pending)
+    }
+
+    /// Merges the thread for `alias` into the thread `canonical` belongs to,
+    /// replacing any alias `alias` already had. URLs previously merged into
+    /// `alias` move along with it.
+    pub async fn merge(
+        &self,
+        moderator_id: Uuid,
+        (alias_url, alias_hash): (&str, &str),
+        (canonical_url, canonical_hash): (&str, &str),
+    ) -> Result<UrlAlias> {
+        if alias_hash == canonical_hash {
+            bail!("a URL can't be merged into itself");
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("LOCK TABLE url_aliases IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+
+        let target = sqlx::query_as::<_, UrlAlias>("SELECT * FROM url_aliases WHERE alias_hash = $1")
+            .bind(canonical_hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let (canonical_url, canonical_hash) = match target {
+            // Merging a thread into one of its own aliases makes that alias the canonical URL
+            Some(target) if target.canonical_hash == alias_hash => {
+                sqlx::query("DELETE FROM url_aliases WHERE alias_hash = $1")
+                    .bind(canonical_hash)
+                    .execute(&mut *tx)
+                    .await?;
+                (canonical_url.to_string(), canonical_hash.to_string())
+            }
+            Some(target) => (target.canonical_url, target.canonical_hash),
+            None => (canonical_url.to_string(), canonical_hash.to_string()),
+        };
+
+        sqlx::query("UPDATE url_aliases SET canonical_hash = $2, canonical_url = $3 WHERE canonical_hash = $1")
+            .bind(alias_hash)
+            .bind(&canonical_hash)
+            .bind(&canonical_url)
+            .execute(&mut *tx)
+            .await?;
+
+        let alias = sqlx::query_as::<_, UrlAlias>(
+            r#"
+            INSERT INTO url_aliases (alias_hash, alias_url, canonical_hash, canonical_url, source, created_by)
+            VALUES ($1, $2, $3, $4, 'moderator', $5)
+            ON CONFLICT (alias_hash) DO UPDATE
+            SET canonical_hash = EXCLUDED.canonical_hash,
+                canonical_url = EXCLUDED.canonical_url,
+                source = EXCLUDED.source,
+                created_by = EXCLUDED.created_by,
+                created_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(alias_hash)
+        .bind(alias_url)
+        .bind(&canonical_hash)
+        .bind(&canonical_url)
+        .bind(moderator_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("DELETE FROM url_alias_reports WHERE alias_hash = $1")
+            .bind(alias_hash)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(alias)
+    }
+
+    /// Gives a merged URL its own thread again; returns false if it wasn't merged
+    pub async fn split(&self, url_hash: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM url_aliases WHERE alias_hash = $1")
+            .bind(url_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::utils::normalize_url;
+
+    #[test]
+    fn test_trusted_canonical() {
+        // Same site, including other subdomains
+        assert!(trusted_canonical("https://example.com/a?page=1", "https://example.com/a"));
+        assert!(trusted_canonical("https://amp.example.com/a", "https://example.com/a"));
+        assert!(trusted_canonical("http://example.com/a", "https://news.example.com/story/a"));
+        // AMP cache pages and their origin
+        assert!(trusted_canonical("https://example-com.cdn.ampproject.org/c/s/example.com/a", "https://example.com/a"));
+        assert!(!trusted_canonical("https://example-com.cdn.ampproject.org/c/s/example.com/a", "https://evil.com/a"));
+        // Other sites, shared hosting suffixes and non-web URLs
+        assert!(!trusted_canonical("https://example.com/a", "https://example.org/a"));
+        assert!(!trusted_canonical("https://alice.github.io/a", "https://bob.github.io/a"));
+        assert!(!trusted_canonical("https://example.com/a", "ftp://example.com/a"));
+        assert!(!trusted_canonical("https://example.com/a", "not a url"));
+        // The front page only when the page is one too
+        assert!(!trusted_canonical("https://example.com/missing", "https://example.com/"));
+        assert!(trusted_canonical("https://example.com/?lang=en", "https://example.com/"));
+    }
+
+    async fn report(service: &UrlAliasService, user_id: Uuid, alias: &(String, String), canonical: &(String, String)) -> Result<Option<UrlAlias>> {
+        service.report(user_id, (&alias.0, &alias.1), (&canonical.0, &canonical.1)).await
+    }
+
+    #[tokio::test]
+    async fn test_reports_merge_once_enough_accounts_agree() {
+        let Some(pool) = testing::pool().await else { return };
+        let service = UrlAliasService::new(pool.clone());
+        let page = testing::unique_url();
+        let amp = normalize_url(&format!("{}/amp", page)).unwrap();
+        let canonical = normalize_url(&page).unwrap();
+        let other = normalize_url(&format!("{}/other", page)).unwrap();
+        let mut reporters = Vec::new();
+        for _ in 0..REPORTERS_TO_ALIAS {
+            reporters.push(testing::create_user(&pool).await);
+        }
+
+        // Reporting again, or reporting somewhere else, doesn't add up
+        for _ in 0..REPORTERS_TO_ALIAS {
+            assert!(report(&service, reporters[0], &amp, &canonical).await.unwrap().is_none());
+        }
+        assert!(report(&service, reporters[1], &amp, &other).await.unwrap().is_none());
+        assert!(service.alias(&amp.1).await.unwrap().is_none());
+        let pending = service.pending(200).await.unwrap();
+        let pending = pending.iter().filter(|p| p.alias_hash == amp.1).map(|p| (p.canonical_hash.as_str(), p.reporters)).collect::<Vec<_>>();
+        assert_eq!(pending.len(), 2);
+        assert!(pending.contains(&(canonical.1.as_str(), 1)) && pending.contains(&(other.1.as_str(), 1)));
+
+        let (last, others) = reporters[1..].split_last().unwrap();
+        for user_id in others {
+            assert!(report(&service, *user_id, &amp, &canonical).await.unwrap().is_none());
+        }
+        assert!(report(&service, *last, &amp, &canonical).await.unwrap().is_some());
+        assert_eq!(service.resolve(&amp.1).await.unwrap(), canonical.1);
+        assert!(service.pending(200).await.unwrap().iter().all(|p| p.alias_hash != amp.1));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::utils::{generate_secure_token, generate_verification_code, hash_token};
+use anyhow::Result;
+use sqlx::PgPool;
+
+/// Maximum number of wrong guesses before a code is discarded
+const MAX_ATTEMPTS: i32 = 5;
+
+/// What an emailed code may be used for. A code issued for one purpose
+/// is never accepted for another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationPurpose {
+    SignUp,
+    Login,
+    EmailChange,
+}
+
+impl VerificationPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationPurpose::SignUp => "signup",
+            VerificationPurpose::Login => "login",
+            VerificationPurpose::EmailChange => "email_change",
+        }
+    }
+}
+
+/// A freshly issued code, plus the token embedded in its magic link
+pub struct IssuedCode {
+    pub code: String,
+    pub link_token: String,
+}
+
+/// Stores emailed verification codes with expiry and attempt limits.
+///
+/// There is one outstanding code per email address; issuing a new one
+/// replaces the previous code whatever its purpose.
+pub struct VerificationService {
+    pool: PgPool,
+    expiry_minutes: i64,
+}
+
+impl VerificationService {
+    pub fn new(pool: PgPool, expiry_minutes: i64) -> Self {
+        Self { pool, expiry_minutes }
+    }
+
+    pub async fn issue(&self, email: &str, purpose: VerificationPurpose) -> Result<IssuedCode> {
+        let code = generate_verification_code();
+        let link_token = generate_secure_token();
+
+        sqlx::query(
+            r#"
+            INSERT INTO verification_codes (email, code, purpose, link_token_hash, attempts, expires_at, created_at)
+            VALUES ($1, $2, $3, $4, 0, NOW() + make_interval(mins => $5), NOW())
+            ON CONFLICT (email) DO UPDATE
+            SET code = EXCLUDED.code,
+                purpose = EXCLUDED.purpose,
+                link_token_hash = EXCLUDED.link_token_hash,
+                attempts = 0,
+                expires_at = EXCLUDED.expires_at,
+                created_at = NOW()
+            "#,
+        )
+        .bind(email)
+        .bind(&code)
+        .bind(purpose.as_str())
+        .bind(hash_token(&link_token))
+        .bind(self.expiry_minutes as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(IssuedCode { code, link_token })
+    }
+
+    /// Checks and consumes a 6-digit code. Wrong guesses count towards the attempt limit.
+    pub async fn verify_code(&self, email: &str, purpose: VerificationPurpose, code: &str) -> Result<bool> {
+        self.consume(email, purpose, "code = $3", code.trim()).await
+    }
+
+    /// Checks and consumes the token from a magic link
+    pub async fn verify_link_token(&self, email: &str, purpose: VerificationPurpose, token: &str) -> Result<bool> {
+        self.consume(email, purpose, "link_token_hash = $3", &hash_token(token)).await
+    }
+
+    async fn consume(&self, email: &str, purpose: VerificationPurpose, predicate: &str, value: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let consumed = sqlx::query(&format!(
+            r#"
+            DELETE FROM verification_codes
+            WHERE email = $1 AND purpose = $2 AND {} AND expires_at > NOW() AND attempts < $4
+            "#,
+            predicate
+        ))
+        .bind(email)
+        .bind(purpose.as_str())
+        .bind(value)
+        .bind(MAX_ATTEMPTS)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            == 1;
+
+        if !consumed {
+            // Burn an attempt, and drop the code once the limit has been reached
+            sqlx::query("UPDATE verification_codes SET attempts = attempts + 1 WHERE email = $1 AND purpose = $2")
+                .bind(email)
+                .bind(purpose.as_str())
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query("DELETE FROM verification_codes WHERE email = $1 AND (attempts >= $2 OR expires_at <= NOW())")
+                .bind(email)
+                .bind(MAX_ATTEMPTS)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(consumed)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::services::reputation::ReputationService;
+use anyhow::Result;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Up and down votes on comments; the author's reputation follows each change
+pub struct VoteService {
+    pool: PgPool,
+}
+
+impl VoteService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Sets the user's vote on a comment (1, -1, or 0 to clear it) and returns
+    /// the comment's new score, or None if the comment doesn't exist
+    pub async fn vote(&self, user_id: Uuid, comment_id: Uuid, value: i16) -> Result<Option<i64>> {
+        let Some(author_id) = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM comments WHERE id = $1")
+            .bind(comment_id)
+            .fetch_optional(&self.pool)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        if value == 0 {
+            sqlx::query("DELETE FROM comment_votes WHERE comment_id = $1 AND user_id = $2")
+                .bind(comment_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        } else {
+            sqlx::query(
+                r#"
+                INSERT INTO comment_votes (comment_id, user_id, value) VALUES ($1, $2, $3)
+                ON CONFLICT (comment_id, user_id) DO UPDATE SET value = EXCLUDED.value, created_at = NOW()
+                "#
+            )
+            .bind(comment_id)
+            .bind(user_id)
+            .bind(value)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        ReputationService::new(self.pool.clone()).recompute_user(author_id).await?;
+
+        Ok(Some(self.score(comment_id).await?))
+    }
+
+    /// Net votes on a comment
+    pub async fn score(&self, comment_id: Uuid) -> Result<i64> {
+        let score = sqlx::query_scalar::<_, i64>(
+            "SELECT COALESCE(SUM(value), 0)::BIGINT FROM comment_votes WHERE comment_id = $1"
+        )
+        .bind(comment_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(score)
+    }
+
+    /// The user's current vote on a comment, 0 if none
+    pub async fn vote_of(&self, user_id: Uuid, comment_id: Uuid) -> Result<i16> {
+        let value = sqlx::query_scalar::<_, i16>(
+            "SELECT value FROM comment_votes WHERE comment_id = $1 AND user_id = $2"
+        )
+        .bind(comment_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(value.unwrap_or(0))
+    }
+}
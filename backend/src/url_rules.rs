@@ -0,0 +1,770 @@
+use crate::utils::{create_url_hash, registrable_domain};
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+use url::Url;
+
+/// Rules compiled into the server, used unless URL_RULES_FILE points elsewhere
+pub const BUNDLED_RULES: &str = include_str!("../url_rules.toml");
+
+/// Bump whenever a code change alters the canonical form of any URL, so
+/// `renormalize` picks up every stored row
+pub const NORMALIZER_REVISION: u32 = 1;
+
+/// ISO 639-1 language codes, the only labels treated as language subdomains
+const ISO_639_1: &[&str] = &[
+    "aa", "ab", "ae", "af", "ak", "am", "an", "ar", "as", "av", "ay", "az", "ba", "be", "bg", "bh", "bi", "bm",
+    "bn", "bo", "br", "bs", "ca", "ce", "ch", "co", "cr", "cs", "cu", "cv", "cy", "da", "de", "dv", "dz", "ee",
+    "el", "en", "eo", "es", "et", "eu", "fa", "ff", "fi", "fj", "fo", "fr", "fy", "ga", "gd", "gl", "gn", "gu",
+    "gv", "ha", "he", "hi", "ho", "hr", "ht", "hu", "hy", "hz", "ia", "id", "ie", "ig", "ii", "ik", "io", "is",
+    "it", "iu", "ja", "jv", "ka", "kg", "ki", "kj", "kk", "kl", "km", "kn", "ko", "kr", "ks", "ku", "kv", "kw",
+    "ky", "la", "lb", "lg", "li", "ln", "lo", "lt", "lu", "lv", "mg", "mh", "mi", "mk", "ml", "mn", "mr", "ms",
+    "mt", "my", "na", "nb", "nd", "ne", "ng", "nl", "nn", "no", "nr", "nv", "ny", "oc", "oj", "om", "or", "os",
+    "pa", "pi", "pl", "ps", "pt", "qu", "rm", "rn", "ro", "ru", "rw", "sa", "sc", "sd", "se", "sg", "si", "sk",
+    "sl", "sm", "sn", "so", "sq", "sr", "ss", "st", "su", "sv", "sw", "ta", "te", "tg", "th", "ti", "tk", "tl",
+    "tn", "to", "tr", "ts", "tt", "tw", "ty", "ug", "uk", "ur", "uz", "ve", "vi", "vo", "wa", "wo", "xh", "yi",
+    "yo", "za", "zh", "zu",
+];
+
+/// Rewrites that produce another rewritable URL are followed at most this many times
+const MAX_REWRITES: usize = 4;
+
+/// Name the `[defaults]` table goes by internally; rules can't use it
+const DEFAULTS: &str = "defaults";
+
+/// How much of a URL's fragment identifies the page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FragmentMode {
+    Drop,
+    Keep,
+    /// Keep hash routes (`#/path`, `#!path`) used by single-page apps, drop anchors
+    Routes,
+}
+
+/// One `[defaults]` or `[[rule]]` table as written in the rules file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: Option<String>,
+    #[serde(default)]
+    domains: Vec<String>,
+    #[serde(default)]
+    drop_params: Vec<String>,
+    keep_params: Option<Vec<String>>,
+    #[serde(default)]
+    rewrite: Vec<RewriteSpec>,
+    lowercase_path: Option<bool>,
+    fragment: Option<FragmentMode>,
+    fold_www: Option<bool>,
+    fold_mobile: Option<bool>,
+    strip_language_subdomain: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RewriteSpec {
+    path: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    defaults: RuleSpec,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleSpec>,
+}
+
+/// Query parameter name, or prefix when written as `prefix_*`; compared case-insensitively
+#[derive(Debug, Clone)]
+struct ParamPattern(String, bool);
+
+impl ParamPattern {
+    fn new(pattern: &str) -> Self {
+        let pattern = pattern.to_lowercase();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => ParamPattern(prefix.to_string(), true),
+            None => ParamPattern(pattern, false),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        if self.1 { name.starts_with(&self.0) } else { name == self.0 }
+    }
+}
+
+#[derive(Debug)]
+struct Rewrite {
+    path: Regex,
+    to: String,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    domains: Vec<String>,
+    drop_params: Vec<ParamPattern>,
+    keep_params: Option<Vec<ParamPattern>>,
+    rewrites: Vec<Rewrite>,
+    lowercase_path: Option<bool>,
+    fragment: Option<FragmentMode>,
+    fold_www: Option<bool>,
+    fold_mobile: Option<bool>,
+    strip_language_subdomain: Option<bool>,
+}
+
+impl Rule {
+    fn compile(spec: RuleSpec, name: String) -> Result<Self> {
+        let rewrites = spec.rewrite
+            .into_iter()
+            .map(|rewrite| {
+                if !rewrite.to.starts_with('/') && Url::parse(&rewrite.to.replace('$', "")).is_err() {
+                    bail!("rule {}: rewrite target {:?} must be a path or an absolute URL", name, rewrite.to);
+                }
+                Ok(Rewrite {
+                    path: Regex::new(&rewrite.path)
+                        .with_context(|| format!("rule {}: invalid rewrite pattern {:?}", name, rewrite.path))?,
+                    to: rewrite.to,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Hosts are matched in their ASCII (punycode) form, as `Url` stores them
+        let domains = spec.domains
+            .iter()
+            .map(|domain| {
+                let domain = domain.trim().trim_end_matches('.');
+                Url::parse(&format!("http://{}/", domain))
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                    .ok_or_else(|| anyhow!("rule {}: invalid domain {:?}", name, domain))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Rule {
+            domains,
+            drop_params: spec.drop_params.iter().map(|param| ParamPattern::new(param)).collect(),
+            keep_params: spec.keep_params.map(|params| params.iter().map(|param| ParamPattern::new(param)).collect()),
+            rewrites,
+            lowercase_path: spec.lowercase_path,
+            fragment: spec.fragment,
+            fold_www: spec.fold_www,
+            fold_mobile: spec.fold_mobile,
+            strip_language_subdomain: spec.strip_language_subdomain,
+            name,
+        })
+    }
+
+    fn label(&self) -> String {
+        if self.name == DEFAULTS { "[defaults]".to_string() } else { format!("rule {}", self.name) }
+    }
+
+    /// A rule for `example.com` covers that host and all of its subdomains
+    fn matches(&self, host: &str) -> bool {
+        self.domains.iter().any(|domain| {
+            host == domain || host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.'))
+        })
+    }
+}
+
+/// Effective settings for one host after layering every matching rule over the defaults
+struct Settings<'a> {
+    rules: Vec<&'a Rule>,
+    lowercase_path: bool,
+    fragment: FragmentMode,
+    fold_www: bool,
+    fold_mobile: bool,
+    strip_language_subdomain: bool,
+}
+
+impl Settings<'_> {
+    /// Trace label of the rule a setting came from
+    fn setting_from(&self, pick: impl Fn(&Rule) -> bool) -> String {
+        self.rules.iter().rev().find(|rule| pick(rule)).map_or_else(|| "[defaults]".to_string(), |rule| rule.label())
+    }
+}
+
+/// A parsed, validated rules file
+#[derive(Debug)]
+pub struct UrlRules {
+    defaults: Rule,
+    rules: Vec<Rule>,
+    version: String,
+    source: String,
+}
+
+impl UrlRules {
+    pub fn parse(source: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(source).context("invalid URL rules file")?;
+
+        if file.defaults.name.is_some() || !file.defaults.domains.is_empty() || !file.defaults.rewrite.is_empty() {
+            bail!("[defaults] can't have a name, domains or rewrites");
+        }
+        let defaults = Rule::compile(file.defaults, DEFAULTS.to_string())?;
+
+        let mut rules: Vec<Rule> = Vec::with_capacity(file.rules.len());
+        for (index, spec) in file.rules.into_iter().enumerate() {
+            let name = spec.name.clone().ok_or_else(|| anyhow!("rule #{} has no name", index + 1))?;
+            if name == DEFAULTS || rules.iter().any(|rule| rule.name == name) {
+                bail!("rule {} is defined twice", name);
+            }
+            if spec.domains.is_empty() {
+                bail!("rule {} has no domains", name);
+            }
+            rules.push(Rule::compile(spec, name)?);
+        }
+
+        let version = format!("{}-{}", NORMALIZER_REVISION, &create_url_hash(source)[..12]);
+        Ok(UrlRules { defaults, rules, version, source: source.to_string() })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path).with_context(|| format!("can't read {}", path.display()))?;
+        Self::parse(&source).with_context(|| format!("in {}", path.display()))
+    }
+
+    /// Identifies the normalizer code revision and rules file that produce a
+    /// canonical URL; stored with each comment as `normalizer_version`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The rules file these rules were parsed from, for clients that hash
+    /// URLs themselves
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_RULES).expect("bundled url_rules.toml is valid")
+    }
+
+    fn settings(&self, host: &str) -> Settings<'_> {
+        let rules = self.rules.iter().filter(|rule| rule.matches(host)).collect::<Vec<_>>();
+        let layered = |pick: fn(&Rule) -> Option<bool>, fallback: bool| {
+            rules.iter().rev().find_map(|rule| pick(rule)).or(pick(&self.defaults)).unwrap_or(fallback)
+        };
+
+        Settings {
+            lowercase_path: layered(|rule| rule.lowercase_path, false),
+            fold_www: layered(|rule| rule.fold_www, true),
+            fold_mobile: layered(|rule| rule.fold_mobile, false),
+            strip_language_subdomain: layered(|rule| rule.strip_language_subdomain, false),
+            fragment: rules.iter().rev().find_map(|rule| rule.fragment)
+                .or(self.defaults.fragment)
+                .unwrap_or(FragmentMode::Drop),
+            rules,
+        }
+    }
+
+    /// Canonical form of a URL under these rules
+    pub fn canonicalize(&self, input: &str) -> Result<Url> {
+        self.canonicalize_traced(input, &mut Vec::new())
+    }
+
+    /// Like `canonicalize`, also describing each rule that changed the URL
+    pub fn explain(&self, input: &str) -> Result<(Url, Vec<String>)> {
+        let mut trace = Vec::new();
+        let url = self.canonicalize_traced(input, &mut trace)?;
+        Ok((url, trace))
+    }
+
+    fn canonicalize_traced(&self, input: &str, trace: &mut Vec<String>) -> Result<Url> {
+        let mut url = Url::parse(input.trim())?;
+
+        // Rewrite patterns see the path in its normalized form
+        let settings = self.settings(url.host_str().unwrap_or_default());
+        let path = self.clean_path(url.path(), &settings, trace);
+        url.set_path(&path);
+
+        for _ in 0..MAX_REWRITES {
+            let host = url.host_str().unwrap_or_default().to_lowercase();
+            let settings = self.settings(&host);
+            match self.rewrite(&url, &settings)? {
+                Some((rewritten, rule)) => {
+                    trace.push(format!("{}: rewrote {} to {}", rule, url, rewritten));
+                    url = rewritten;
+                }
+                None => break,
+            }
+        }
+
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let settings = self.settings(&host);
+        for rule in &settings.rules {
+            trace.push(format!("{}: matched host {}", rule.label(), host));
+        }
+
+        if url.host_str().is_some() {
+            let folded = self.fold_host(&host, &settings, trace);
+            url.set_host(Some(&folded))?;
+        }
+
+        let mut path = self.clean_path(url.path(), &settings, trace);
+        if settings.lowercase_path && path.chars().any(|c| c.is_uppercase()) {
+            trace.push(format!("{}: lowercased path", settings.setting_from(|rule| rule.lowercase_path.is_some())));
+            path = path.to_lowercase();
+        }
+        url.set_path(&normalize_percent_encoding(&path));
+
+        // Query: drop tracking and session parameters, then sort by name for a
+        // stable order. Parameters are split on literal `&` only and kept in
+        // their encoded form, so `a=b%26c` and `a=b&c` stay different URLs.
+        let mut params = Vec::new();
+        for param in url.query().unwrap_or_default().split('&').filter(|param| !param.is_empty()) {
+            let name = param_name(param);
+            match self.dropped_by(&name, &settings) {
+                Some(rule) => trace.push(format!("{}: dropped query parameter {}", rule, name)),
+                None => params.push(normalize_percent_encoding(param)),
+            }
+        }
+        params.sort_by(|a, b| a.split('=').next().cmp(&b.split('=').next()));
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            url.set_query(Some(&params.join("&")));
+        }
+
+        if let Some(fragment) = url.fragment().map(normalize_percent_encoding) {
+            let keep = match settings.fragment {
+                FragmentMode::Keep => !fragment.is_empty(),
+                FragmentMode::Routes => fragment.starts_with('/') || fragment.starts_with('!'),
+                FragmentMode::Drop => false,
+            };
+            if keep {
+                trace.push(format!("{}: kept fragment #{}", settings.setting_from(|rule| rule.fragment.is_some()), fragment));
+                url.set_fragment(Some(&fragment));
+            } else {
+                url.set_fragment(None);
+            }
+        }
+
+        Ok(url)
+    }
+
+    /// Drops session parameters from path segments (`;jsessionid=...`) and
+    /// trailing slashes, and normalizes percent-encoding
+    fn clean_path(&self, path: &str, settings: &Settings, trace: &mut Vec<String>) -> String {
+        let mut path = path
+            .split('/')
+            .map(|segment| {
+                let mut parts = segment.split(';');
+                let mut kept = parts.next().unwrap_or_default().to_string();
+                for param in parts {
+                    let name = param_name(param);
+                    match self.dropped_by(&name, settings) {
+                        Some(rule) => trace.push(format!("{}: dropped path parameter {}", rule, name)),
+                        None => {
+                            kept.push(';');
+                            kept.push_str(param);
+                        }
+                    }
+                }
+                kept
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        while path.len() > 1 && path.ends_with('/') {
+            path.pop();
+        }
+        normalize_percent_encoding(&path)
+    }
+
+    /// Applies the first rewrite of a matching rule whose pattern matches the path
+    fn rewrite(&self, url: &Url, settings: &Settings) -> Result<Option<(Url, String)>> {
+        for rule in &settings.rules {
+            for rewrite in &rule.rewrites {
+                let Some(captures) = rewrite.path.captures(url.path()) else {
+                    continue;
+                };
+                let mut target = String::new();
+                captures.expand(&rewrite.to, &mut target);
+
+                let rewritten = if target.starts_with('/') {
+                    let mut rewritten = url.clone();
+                    rewritten.set_path(&target);
+                    rewritten
+                } else {
+                    let mut rewritten = Url::parse(&target)
+                        .with_context(|| format!("rule {}: rewrite produced an invalid URL {:?}", rule.name, target))?;
+                    let query = rewritten.query().unwrap_or_default().to_string();
+                    let existing = query.split('&').map(param_name).collect::<Vec<_>>();
+                    let carried = url.query().unwrap_or_default()
+                        .split('&')
+                        .filter(|param| !param.is_empty() && !existing.contains(&param_name(param)))
+                        .collect::<Vec<_>>();
+                    if !carried.is_empty() {
+                        let query = std::iter::once(query.as_str()).filter(|query| !query.is_empty()).chain(carried);
+                        rewritten.set_query(Some(&query.collect::<Vec<_>>().join("&")));
+                    }
+                    if rewritten.fragment().is_none() {
+                        rewritten.set_fragment(url.fragment());
+                    }
+                    rewritten
+                };
+
+                if rewritten == *url {
+                    continue;
+                }
+                return Ok(Some((rewritten, rule.label())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Which rule drops a parameter, if any. A `keep_params` list drops
+    /// everything it doesn't name; otherwise the `drop_params` lists add up.
+    fn dropped_by(&self, name: &str, settings: &Settings) -> Option<String> {
+        if let Some(rule) = settings.rules.iter().rev().find(|rule| rule.keep_params.is_some()) {
+            let keep = rule.keep_params.as_ref()?;
+            return (!keep.iter().any(|pattern| pattern.matches(name))).then(|| rule.label());
+        }
+
+        std::iter::once(&self.defaults)
+            .chain(settings.rules.iter().copied())
+            .find(|rule| rule.drop_params.iter().any(|pattern| pattern.matches(name)))
+            .map(|rule| rule.label())
+    }
+
+    /// Lowercases a host and folds away www, mobile and language labels as
+    /// configured, never touching the registrable domain itself
+    pub fn normalize_host(&self, host: &str) -> String {
+        let host = host.trim().trim_end_matches('.').to_lowercase();
+        let settings = self.settings(&host);
+        self.fold_host(&host, &settings, &mut Vec::new())
+    }
+
+    fn fold_host(&self, host: &str, settings: &Settings, trace: &mut Vec<String>) -> String {
+        let host = host.trim_end_matches('.');
+        let domain = registrable_domain(host);
+        let Some(subdomain) = host.strip_suffix(domain.as_str()).and_then(|rest| rest.strip_suffix('.')) else {
+            return host.to_string();
+        };
+
+        let mut labels = subdomain.split('.').collect::<Vec<_>>();
+        if settings.fold_www && labels.first() == Some(&"www") {
+            labels.remove(0);
+            trace.push(format!("{}: removed www label", settings.setting_from(|rule| rule.fold_www.is_some())));
+        }
+        // A mobile label leads the subdomain, or follows a language label as
+        // in en.m.wikipedia.org; an m further in is part of a site's name
+        let mobile_at = usize::from(labels.first().is_some_and(|label| ISO_639_1.contains(label)));
+        if settings.fold_mobile && labels.get(mobile_at).is_some_and(|label| matches!(*label, "m" | "mobile")) {
+            labels.remove(mobile_at);
+            trace.push(format!("{}: removed mobile label", settings.setting_from(|rule| rule.fold_mobile.is_some())));
+        }
+        if settings.strip_language_subdomain && labels.first().is_some_and(|label| ISO_639_1.contains(label)) {
+            trace.push(format!(
+                "{}: removed language label {}",
+                settings.setting_from(|rule| rule.strip_language_subdomain.is_some()),
+                labels[0]
+            ));
+            labels.remove(0);
+        }
+
+        labels.push(&domain);
+        labels.join(".")
+    }
+}
+
+/// RFC 3986 unreserved characters, which never need percent-encoding
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// RFC 3986 reserved characters, which may mean something different when encoded
+fn is_reserved(byte: u8) -> bool {
+    matches!(
+        byte,
+        b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+    )
+}
+
+/// Percent-encoding normalization of a path, query or fragment (RFC 3986
+/// section 6.2.2): escaped unreserved characters are decoded, remaining
+/// escapes use uppercase hex, and anything neither reserved nor unreserved
+/// is encoded. Reserved characters are left exactly as written.
+pub fn normalize_percent_encoding(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut normalized = String::with_capacity(component.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        let hex = |digit: u8| (digit as char).to_digit(16).unwrap_or_default() as u8;
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                Some(hex(*high) << 4 | hex(*low))
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(decoded) if is_unreserved(decoded) => {
+                normalized.push(decoded as char);
+                i += 3;
+                continue;
+            }
+            Some(decoded) => {
+                normalized.push_str(&format!("%{:02X}", decoded));
+                i += 3;
+                continue;
+            }
+            None if is_unreserved(byte) || is_reserved(byte) => normalized.push(byte as char),
+            None => normalized.push_str(&format!("%{:02X}", byte)),
+        }
+        i += 1;
+    }
+    normalized
+}
+
+/// Decoded name of a `name=value` query or path parameter, for matching against rules
+fn param_name(param: &str) -> String {
+    url::form_urlencoded::parse(param.as_bytes())
+        .next()
+        .map(|(name, _)| name.into_owned())
+        .unwrap_or_default()
+}
+
+fn current_rules() -> &'static RwLock<Arc<UrlRules>> {
+    static RULES: OnceLock<RwLock<Arc<UrlRules>>> = OnceLock::new();
+    RULES.get_or_init(|| RwLock::new(Arc::new(UrlRules::bundled())))
+}
+
+/// The rules in effect; the bundled ones until `install` is called
+pub fn current() -> Arc<UrlRules> {
+    current_rules().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+pub fn install(rules: UrlRules) {
+    *current_rules().write().unwrap_or_else(|e| e.into_inner()) = Arc::new(rules);
+}
+
+/// Reloads the rules file whenever its modification time changes. A file
+/// that fails to parse is reported and the previous rules stay in effect.
+pub fn watch(path: PathBuf, interval: Duration) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok();
+
+    actix_web::rt::spawn(async move {
+        let mut last_modified: Option<SystemTime> = modified(&path);
+        let mut interval = actix_web::rt::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            match UrlRules::load(&path) {
+                Ok(rules) => {
+                    install(rules);
+                    info!("Reloaded URL rules from {}", path.display());
+                }
+                Err(e) => warn!("Keeping previous URL rules: {:#}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn canonical(rules: &UrlRules, url: &str) -> String {
+        rules.canonicalize(url).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_bundled_rules() {
+        let rules = UrlRules::bundled();
+        let cases = [
+            ("https://youtu.be/dQw4w9WgXcQ?t=42", "https://youtube.com/watch?v=dQw4w9WgXcQ"),
+            ("https://m.youtube.com/watch?v=dQw4w9WgXcQ&feature=share&list=PL1", "https://youtube.com/watch?list=PL1&v=dQw4w9WgXcQ"),
+            ("https://www.youtube.com/shorts/abc123", "https://youtube.com/watch?v=abc123"),
+            ("https://mobile.twitter.com/rustlang/status/1?s=20", "https://x.com/rustlang/status/1"),
+            ("https://en.m.wikipedia.org/wiki/Rust", "https://en.wikipedia.org/wiki/Rust"),
+            ("https://www.amazon.co.uk/Some-Book/dp/B000000001/ref=sr_1_1?qid=1&keywords=x", "https://amazon.co.uk/dp/B000000001"),
+            ("https://es.khanacademy.org/math", "https://khanacademy.org/math"),
+            // ref, source and campaign mean something on most sites
+            ("https://example.com/a?ref=main&source=docs&campaign=2024", "https://example.com/a?campaign=2024&ref=main&source=docs"),
+            // Session ids are dropped from the query and from path parameters
+            ("https://example.com/shop;jsessionid=ABC123/item?PHPSESSID=1&id=4", "https://example.com/shop/item?id=4"),
+            ("https://example.com/a?utm_source=x&utm_medium=y&fbclid=z", "https://example.com/a"),
+            // Hash routes identify pages in single-page apps; plain anchors don't
+            ("https://app.example.com/#/inbox/42", "https://app.example.com/#/inbox/42"),
+            ("https://app.example.com/#!/settings", "https://app.example.com/#!/settings"),
+            ("https://example.com/post#comments", "https://example.com/post"),
+            // m. is only folded on sites that opt in, and never in the registrable domain
+            ("https://m.example.com/a", "https://m.example.com/a"),
+            ("https://m.me/someone", "https://m.me/someone"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(canonical(&rules, input), expected, "Failed for input: {}", input);
+            assert_eq!(canonical(&rules, expected), expected, "Not idempotent for: {}", expected);
+        }
+    }
+
+    #[test]
+    fn test_rfc3986_normalization() {
+        let rules = UrlRules::bundled();
+        let cases = [
+            // Scheme and host case, default ports
+            ("HTTP://Example.COM:80/a", "http://example.com/a"),
+            ("https://example.com:443/", "https://example.com/"),
+            ("https://example.com:8443/a", "https://example.com:8443/a"),
+            // Dot segments
+            ("https://example.com/a/./b/../c", "https://example.com/a/c"),
+            ("https://example.com/a/%2E%2e/b", "https://example.com/b"),
+            // Escaped unreserved characters are decoded, other escapes uppercased
+            ("https://example.com/%7Euser/%61bc", "https://example.com/~user/abc"),
+            ("https://example.com/a%2fb", "https://example.com/a%2Fb"),
+            ("https://example.com/a?q=%e2%82%ac&%6B=v", "https://example.com/a?k=v&q=%E2%82%AC"),
+            ("https://example.com/#/%7ea%2f", "https://example.com/#/~a%2F"),
+            // Characters that can't appear literally are encoded, reserved ones kept
+            ("https://example.com/a?q=€", "https://example.com/a?q=%E2%82%AC"),
+            ("https://example.com/a b?x=1|2&y=50%", "https://example.com/a%20b?x=1%7C2&y=50%25"),
+            ("https://example.com/a?q=a+b&r=(x):y", "https://example.com/a?q=a+b&r=(x):y"),
+            // Unicode and punycode hosts
+            ("https://bücher.de/a", "https://xn--bcher-kva.de/a"),
+            ("https://XN--BCHER-KVA.de/a", "https://xn--bcher-kva.de/a"),
+            ("https://www.bücher.de/a", "https://xn--bcher-kva.de/a"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(canonical(&rules, input), expected, "Failed for input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_escaped_delimiters_stay_distinct() {
+        let rules = UrlRules::bundled();
+        assert_eq!(canonical(&rules, "https://example.com/?a=b%26c"), "https://example.com/?a=b%26c");
+        assert_ne!(canonical(&rules, "https://example.com/?a=b%26c"), canonical(&rules, "https://example.com/?a=b&c"));
+        assert_ne!(canonical(&rules, "https://example.com/?a=b%3Dc"), canonical(&rules, "https://example.com/?a=b=c"));
+        assert_ne!(canonical(&rules, "https://example.com/a%2Fb"), canonical(&rules, "https://example.com/a/b"));
+    }
+
+    #[test]
+    fn test_unicode_rule_domains_match_punycode_hosts() {
+        let rules = UrlRules::parse("[[rule]]\nname = \"books\"\ndomains = [\"Bücher.de\"]\nkeep_params = [\"id\"]").unwrap();
+        assert_eq!(canonical(&rules, "https://bücher.de/a?id=1&x=2"), "https://xn--bcher-kva.de/a?id=1");
+    }
+
+    #[test]
+    fn test_rules_layer_in_file_order() {
+        let rules = UrlRules::parse(
+            r#"
+            [defaults]
+            drop_params = ["utm_*"]
+
+            [[rule]]
+            name = "docs"
+            domains = ["example.com"]
+            lowercase_path = true
+            fragment = "keep"
+            drop_params = ["lang"]
+
+            [[rule]]
+            name = "docs-api"
+            domains = ["api.example.com"]
+            lowercase_path = false
+            keep_params = ["id"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(canonical(&rules, "https://Example.com/Guide?lang=en&utm_x=1#Intro"), "https://example.com/guide#Intro");
+        assert_eq!(canonical(&rules, "https://api.example.com/Ref?id=1&page=2#x"), "https://api.example.com/Ref?id=1#x");
+        assert_eq!(canonical(&rules, "https://other.org/Guide?lang=en#x"), "https://other.org/Guide?lang=en");
+    }
+
+    #[test]
+    fn test_explain_names_the_rules_that_fired() {
+        let (url, trace) = UrlRules::bundled().explain("https://youtu.be/dQw4w9WgXcQ?utm_source=x&t=1").unwrap();
+        assert_eq!(url.as_str(), "https://youtube.com/watch?v=dQw4w9WgXcQ");
+        assert!(trace.iter().any(|line| line.starts_with("rule youtube-short-links: rewrote")));
+        assert!(trace.iter().any(|line| line == "rule youtube: dropped query parameter utm_source"));
+        assert!(trace.iter().any(|line| line == "rule youtube: dropped query parameter t"));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        assert!(UrlRules::parse("[[rule]]\ndomains = [\"a.com\"]").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"\ndomains = [\"a.com\"]\nfragmnet = \"keep\"").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"\ndomains = [\"a.com\"]\nfragment = \"sometimes\"").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"\ndomains = [\"a.com\"]\nrewrite = [{ path = \"(\", to = \"/x\" }]").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"\ndomains = [\"a.com\"]\nrewrite = [{ path = \"x\", to = \"nope\" }]").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"a\"\ndomains = [\"a.com\"]\n[[rule]]\nname = \"a\"\ndomains = [\"b.com\"]").is_err());
+        assert!(UrlRules::parse("[[rule]]\nname = \"defaults\"\ndomains = [\"a.com\"]").is_err());
+        assert!(UrlRules::parse("[defaults]\ndomains = [\"a.com\"]").is_err());
+    }
+
+    fn url_strategy() -> impl Strategy<Value = String> {
+        let scheme = prop::sample::select(vec!["http", "https", "HTTPS"]);
+        let host = prop::sample::select(vec![
+            "example.com", "WWW.Example.com", "m.youtube.com", "youtu.be", "twitter.com", "bücher.de",
+            "xn--bcher-kva.de", "en.m.wikipedia.org", "es.khanacademy.org", "www.co.uk", "192.168.0.1", "[::1]",
+        ]);
+        let port = prop::option::of(prop::sample::select(vec![80u16, 443, 8080]));
+        let segment = "([a-zA-Z0-9._~!$&'()*+,;=:@ é|{}-]|%[0-9a-fA-F]{2}|%|\\.\\.?){0,8}";
+        let path = prop::collection::vec(segment, 0..5);
+        let param = "([a-zA-Z0-9._~!$'()*+,;:@/? é|-]|%[0-9a-fA-F]{2}|%){0,6}(=([a-zA-Z0-9._~!$'()*+,;=:@/? é|-]|%[0-9a-fA-F]{2}|%){0,6})?";
+        let query = prop::option::of(prop::collection::vec(param, 0..5));
+        let fragment = prop::option::of("[/!]?([a-zA-Z0-9._~!$&'()*+,;=:@/? -]|%[0-9a-fA-F]{2}){0,8}");
+
+        (scheme, host, port, path, query, fragment).prop_map(|(scheme, host, port, path, query, fragment)| {
+            let mut url = format!("{}://{}", scheme, host);
+            if let Some(port) = port {
+                url.push_str(&format!(":{}", port));
+            }
+            for segment in path {
+                url.push('/');
+                url.push_str(&segment);
+            }
+            if let Some(query) = query {
+                url.push('?');
+                url.push_str(&query.join("&"));
+            }
+            if let Some(fragment) = fragment {
+                url.push('#');
+                url.push_str(&fragment);
+            }
+            url
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_canonicalize_is_idempotent(url in url_strategy()) {
+            let rules = UrlRules::bundled();
+            if let Ok(once) = rules.canonicalize(&url) {
+                let twice = rules.canonicalize(once.as_str()).unwrap();
+                prop_assert_eq!(once.as_str(), twice.as_str());
+            }
+        }
+
+        #[test]
+        fn prop_escaped_unreserved_characters_are_equivalent(
+            prefix in "[a-z0-9]{0,6}",
+            c in "[a-zA-Z0-9._~-]",
+            suffix in "[a-z0-9]{1,6}",
+            lowercase_hex in any::<bool>(),
+        ) {
+            let rules = UrlRules::bundled();
+            let byte = c.as_bytes()[0];
+            let escaped = if lowercase_hex { format!("%{:02x}", byte) } else { format!("%{:02X}", byte) };
+            let plain = format!("https://example.com/{}{}{}?{}{}={}", prefix, c, suffix, prefix, c, suffix);
+            let encoded = format!("https://example.com/{}{}{}?{}{}={}", prefix, escaped, suffix, prefix, escaped, suffix);
+            prop_assert_eq!(canonical(&rules, &plain), canonical(&rules, &encoded));
+        }
+    }
+}
@@ -1,91 +1,49 @@
-use regex::Regex;
+use crate::url_rules;
 use sha2::{Digest, Sha256};
 use url::Url;
 
-/// Normalizes a URL to create a canonical form for comment grouping
+/// Normalizes a URL to create a canonical form for comment grouping, using
+/// the URL rules currently in effect (see `url_rules`)
 pub fn normalize_url(input_url: &str) -> Result<(String, String), Box<dyn std::error::Error>> {
-    // Parse the URL
-    let mut url = Url::parse(input_url)?;
-    
-    // Convert to lowercase
-    let _ = url.set_scheme(&url.scheme().to_lowercase());
-    
-    // Handle host normalization
-    if let Some(host) = url.host_str() {
-        let normalized_host = normalize_host(host);
-        url.set_host(Some(&normalized_host))?;
-    }
-    
-    // Normalize path - remove trailing slash unless it's the root
-    let path = url.path().to_string();
-    let normalized_path = if path.len() > 1 && path.ends_with('/') {
-        &path[..path.len()-1]
-    } else {
-        &path
-    };
-    url.set_path(normalized_path);
-    
-    // Remove common tracking parameters
-    let mut query_pairs: Vec<(String, String)> = url.query_pairs()
-        .filter(|(key, _)| !is_tracking_parameter(key))
-        .map(|(k, v)| (k.to_string(), v.to_string()))
-        .collect();
-    
-    // Sort query parameters for consistency
-    query_pairs.sort_by(|a, b| a.0.cmp(&b.0));
-    
-    // Rebuild query string
-    if query_pairs.is_empty() {
-        url.set_query(None);
-    } else {
-        let query_string = query_pairs
-            .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<_>>()
-            .join("&");
-        url.set_query(Some(&query_string));
-    }
-    
-    // Remove fragment
-    url.set_fragment(None);
-    
-    let normalized_url = url.to_string();
-    let url_hash = create_url_hash(&normalized_url);
+    let (normalized_url, url_hash, _) = normalize_url_versioned(input_url)?;
     
     Ok((normalized_url, url_hash))
 }
 
-fn normalize_host(host: &str) -> String {
-    let host = host.to_lowercase();
-    
-    // Remove www. prefix
-    let host = if host.starts_with("www.") {
-        &host[4..]
-    } else {
-        &host
-    };
+/// Like `normalize_url`, also returning the version of the rules that
+/// produced the result, for storing alongside the hash
+pub fn normalize_url_versioned(input_url: &str) -> Result<(String, String, String), Box<dyn std::error::Error>> {
+    let rules = url_rules::current();
+    let normalized_url = rules.canonicalize(input_url)?.to_string();
+    let url_hash = create_url_hash(&normalized_url);
     
-    // Handle language subdomains (en., es., de., fr., etc.)
-    let lang_pattern = Regex::new(r"^[a-z]{2}\.").unwrap();
-    if lang_pattern.is_match(host) {
-        // Remove language subdomain
-        &host[3..]
-    } else {
-        host
-    }.to_string()
+    Ok((normalized_url, url_hash, rules.version().to_string()))
 }
 
-fn is_tracking_parameter(key: &str) -> bool {
-    const TRACKING_PARAMS: &[&str] = &[
-        "utm_source", "utm_medium", "utm_campaign", "utm_term", "utm_content",
-        "fbclid", "gclid", "msclkid", "ref", "referrer", "_ga", "_gl",
-        "mc_cid", "mc_eid", "campaign", "source", "medium",
-    ];
-    
-    TRACKING_PARAMS.contains(&key)
+/// Lowercases a host and folds away the `www.`, mobile and language labels
+/// the URL rules ask for. Only labels above the registrable domain are ever
+/// removed, so `www.co.uk` and `go.dev` are kept.
+pub fn normalize_host(host: &str) -> String {
+    url_rules::current().normalize_host(host)
+}
+
+/// Registrable domain (public suffix plus one label) of a host per the
+/// bundled Public Suffix List, so `news.bbc.co.uk` becomes `bbc.co.uk`.
+/// IP addresses and hosts that are themselves a public suffix come back as they are.
+pub fn registrable_domain(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+    if host.starts_with('[') || host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+    psl::domain_str(&host).map(str::to_string).unwrap_or(host)
 }
 
-fn create_url_hash(url: &str) -> String {
+/// Registrable domain of a URL's host, if it has one
+pub fn url_domain(url: &str) -> Option<String> {
+    Url::parse(url).ok()?.host_str().map(registrable_domain)
+}
+
+pub fn create_url_hash(url: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(url.as_bytes());
     hex::encode(hasher.finalize())
@@ -97,6 +55,19 @@ pub fn generate_verification_code() -> String {
     format!("{:06}", rng.random_range(100000..=999999))
 }
 
+/// Generates a random URL-safe token for single-use links and challenges
+pub fn generate_secure_token() -> String {
+    use rand::Rng;
+    let mut bytes = [0u8; 32];
+    rand::rng().fill(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a secret token for storage so the database never holds it in plain text
+pub fn hash_token(token: &str) -> String {
+    create_url_hash(token)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,9 +77,10 @@ mod tests {
         let test_cases = vec![
             ("http://example.com/article1", "http://example.com/article1"),
             ("http://example.com/article1/", "http://example.com/article1"),
-            ("http://en.example.com/article1/", "http://example.com/article1"),
+            ("http://en.example.com/article1/", "http://en.example.com/article1"),
             ("http://www.example.com/article1", "http://example.com/article1"),
-            ("https://en.example.com/article1?utm_source=google", "https://example.com/article1"),
+            ("https://es.khanacademy.org/math?utm_source=google", "https://khanacademy.org/math"),
+            ("https://go.dev/doc/", "https://go.dev/doc"),
         ];
         
         for (input, expected) in test_cases {
@@ -121,7 +93,7 @@ mod tests {
     fn test_same_urls_produce_same_hash() {
         let url1 = "http://example.com/article1";
         let url2 = "http://example.com/article1/";
-        let url3 = "http://en.example.com/article1/";
+        let url3 = "http://WWW.example.com/article1/";
         
         let (_, hash1) = normalize_url(url1).unwrap();
         let (_, hash2) = normalize_url(url2).unwrap();
@@ -130,4 +102,131 @@ mod tests {
         assert_eq!(hash1, hash2);
         assert_eq!(hash2, hash3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_url_hashes_are_stable() {
+        // Stored comments are looked up by these hashes, so a change here
+        // splits existing threads and needs existing rows renormalized
+        let corpus = [
+            ("https://example.com/article1", "https://example.com/article1",
+             "4425b8e5140baa803c4c16e21bb5387afc64f3ee484f9e0d3d27772769ac2f86"),
+            ("https://www.example.com/a/?utm_source=x&b=2&a=1#top", "https://example.com/a?a=1&b=2",
+             "051029b6a13fc6686e4523427e03b3a177e6970f9bfe03b026a9a023819b902a"),
+            ("https://youtu.be/dQw4w9WgXcQ?t=30", "https://youtube.com/watch?v=dQw4w9WgXcQ",
+             "6bc35c67f0de1515cd8f2f30a583a919186e85b956fe025cd7b4a933fa5b78e6"),
+            ("https://bücher.de/%7Euser/a%2fb?q=€", "https://xn--bcher-kva.de/~user/a%2Fb?q=%E2%82%AC",
+             "4e3e90dddf0b6ecccf27128663b0de5c813a213d24cd15caacf3f837f7c3f89e"),
+            ("https://example.com/?a=b%26c", "https://example.com/?a=b%26c",
+             "9a58c37eca8604ef9e5d44e9454dd7fd0eaf7ca1b97f21b7600bfbda8884308e"),
+            ("http://Example.com:80/a/./b/../c/", "http://example.com/a/c",
+             "00b947bf9c3af4ea902fba2f4f5f952edaa988a97ccefe9b79506ad0f0e14955"),
+            ("https://app.example.com/#/inbox/42", "https://app.example.com/#/inbox/42",
+             "062bbd230e4e13cb00721d6dd5bf22b2d424dc2762fc14b68403775d22c0570e"),
+        ];
+
+        for (input, expected_url, expected_hash) in corpus {
+            let (normalized, hash) = normalize_url(input).unwrap();
+            assert_eq!(normalized, expected_url, "Failed for input: {}", input);
+            assert_eq!(hash, expected_hash, "Hash changed for input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_database_scripts_match_backend_normalizer() {
+        // The init scripts seed comments with precomputed hashes and must not
+        // carry their own copy of the normalization rules
+        let simple = include_str!("../../database/init-simple.sql");
+        let sharding = include_str!("../../database/init-sharding.sql");
+        let (normalized, hash) = normalize_url("https://example.com/article1").unwrap();
+
+        assert!(simple.contains(&format!("'{}',\n    '{}',", normalized, hash)));
+        for script in [simple, sharding] {
+            assert!(!script.contains("FUNCTION normalize_and_hash_url"));
+        }
+    }
+
+    #[test]
+    fn test_normalize_host_corpus() {
+        // Hosts that an earlier version merged with unrelated sites, plus the
+        // cases each rule is meant to handle
+        let corpus = [
+            // Two-letter labels that are part of the registrable domain
+            ("go.dev", "go.dev"),
+            ("ai.google", "ai.google"),
+            ("is.gd", "is.gd"),
+            ("en.github.io", "en.github.io"),
+            // Two-letter labels above the registrable domain on sites without language subdomains
+            ("my.site.com", "my.site.com"),
+            ("en.example.com", "en.example.com"),
+            ("de.wikipedia.org", "de.wikipedia.org"),
+            // Language subdomains on opted-in sites
+            ("es.khanacademy.org", "khanacademy.org"),
+            ("FR.KhanAcademy.org", "khanacademy.org"),
+            ("www.de.khanacademy.org", "khanacademy.org"),
+            ("uk.linkedin.com", "linkedin.com"),
+            // Only ISO 639-1 codes count as languages
+            ("xx.khanacademy.org", "xx.khanacademy.org"),
+            ("eng.khanacademy.org", "eng.khanacademy.org"),
+            ("support.khanacademy.org", "support.khanacademy.org"),
+            // Only the leftmost label is considered
+            ("blog.en.khanacademy.org", "blog.en.khanacademy.org"),
+            // www is only stripped above the registrable domain
+            ("www.example.com", "example.com"),
+            ("www.bbc.co.uk", "bbc.co.uk"),
+            ("www.co.uk", "www.co.uk"),
+            ("www.github.io", "www.github.io"),
+            ("www.www.example.com", "www.example.com"),
+            // Mobile labels lead the subdomain or follow a language label
+            ("m.youtube.com", "youtube.com"),
+            ("mobile.x.com", "x.com"),
+            ("en.m.wikipedia.org", "en.wikipedia.org"),
+            ("foo.m.wikipedia.org", "foo.m.wikipedia.org"),
+            ("foo.m.example.com", "foo.m.example.com"),
+            // Multi-label public suffixes
+            ("news.bbc.co.uk", "news.bbc.co.uk"),
+            ("user.github.io", "user.github.io"),
+            ("co.uk", "co.uk"),
+            // Trailing dots, IP addresses, single-label and IDN hosts
+            ("example.com.", "example.com"),
+            ("192.168.0.1", "192.168.0.1"),
+            ("[::1]", "[::1]"),
+            ("localhost", "localhost"),
+            ("www.xn--80ak6aa92e.com", "xn--80ak6aa92e.com"),
+        ];
+
+        for (host, expected) in corpus {
+            assert_eq!(normalize_host(host), expected, "Failed for host: {}", host);
+        }
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("news.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(registrable_domain("Blog.Example.COM."), "example.com");
+        assert_eq!(registrable_domain("example.com"), "example.com");
+        assert_eq!(registrable_domain("user.github.io"), "user.github.io");
+        assert_eq!(registrable_domain("co.uk"), "co.uk");
+        assert_eq!(registrable_domain("127.0.0.1"), "127.0.0.1");
+        assert_eq!(registrable_domain("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_url_domain() {
+        assert_eq!(url_domain("https://docs.rs/sqlx/latest"), Some("docs.rs".to_string()));
+        assert_eq!(url_domain("https://a.b.example.org:8443/x"), Some("example.org".to_string()));
+        assert_eq!(url_domain("mailto:someone@example.com"), None);
+    }
+
+    /// The corpus extension/url-hash.js is checked against, so the hashes
+    /// it expects are the server's
+    #[test]
+    fn test_extension_hash_corpus() {
+        let corpus: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../extension/tests/url-hash-corpus.json")).unwrap();
+        assert!(!corpus.is_empty());
+        for case in corpus {
+            let url = case["url"].as_str().unwrap();
+            assert_eq!(normalize_url(url).unwrap().1, case["url_hash"].as_str().unwrap(), "{}", url);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use async_graphql::ErrorExtensions;
+use phonenumber::Mode;
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+
+pub const NAME_MAX_CHARS: usize = 100;
+pub const BIO_MAX_CHARS: usize = 1000;
+pub const PASSWORD_MIN_CHARS: usize = 8;
+pub const PASSWORD_MAX_CHARS: usize = 128;
+pub const HANDLE_MIN_CHARS: usize = 3;
+pub const HANDLE_MAX_CHARS: usize = 30;
+
+/// Handles that would let someone pose as staff or collide with site routes
+const RESERVED_HANDLES: &[&str] = &[
+    "abuse", "account", "admin", "administrator", "anonymous", "api", "deleted", "everyone", "help",
+    "here", "mod", "moderator", "null", "official", "postmaster", "root", "security", "settings",
+    "staff", "support", "system", "undefined", "user", "votp", "webmaster",
+];
+
+/// A validation failure on a single input field
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Collects field errors so every problem with an input is reported at once
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the error, if any, and passes the value through
+    pub fn check<T>(&mut self, result: Result<T, FieldError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.errors.push(error);
+                None
+            }
+        }
+    }
+
+    /// Records an error found outside the field validators, e.g. a uniqueness check
+    pub fn add(&mut self, error: FieldError) {
+        self.errors.push(error);
+    }
+
+    /// Fails with a GraphQL error carrying every field error in its extensions:
+    /// `{ "code": "VALIDATION_FAILED", "fields": [{ "field", "code", "message" }] }`
+    pub fn finish(self) -> async_graphql::Result<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+
+        let message = self.errors
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+        let fields = async_graphql::Value::from_json(serde_json::to_value(&self.errors).unwrap_or_default())
+            .unwrap_or_default();
+
+        Err(async_graphql::Error::new(message).extend_with(|_, e| {
+            e.set("code", "VALIDATION_FAILED");
+            e.set("fields", fields.clone());
+        }))
+    }
+}
+
+/// Characters that are invisible or reorder text, used to disguise names
+fn is_invisible_or_bidi(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}' | '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+/// Trims, NFC-normalizes and collapses internal whitespace in a display name.
+/// Names mixing scripts (e.g. Latin with look-alike Cyrillic letters) are rejected.
+pub fn validate_name(field: &str, name: &str) -> Result<String, FieldError> {
+    let name: String = name.nfc().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if name.is_empty() {
+        return Err(FieldError::new(field, "REQUIRED", "Name cannot be empty"));
+    }
+
+    if name.chars().count() > NAME_MAX_CHARS {
+        return Err(FieldError::new(
+            field,
+            "TOO_LONG",
+            format!("Name must be at most {} characters", NAME_MAX_CHARS),
+        ));
+    }
+
+    if name.chars().any(|c| c.is_control() || is_invisible_or_bidi(c)) {
+        return Err(FieldError::new(field, "INVALID_CHARACTERS", "Name contains invisible or control characters"));
+    }
+
+    if !name.as_str().is_single_script() {
+        return Err(FieldError::new(
+            field,
+            "MIXED_SCRIPT",
+            "Name mixes characters from different alphabets",
+        ));
+    }
+
+    Ok(name)
+}
+
+/// Parses a phone number in international format and returns it as E.164.
+/// An empty value clears the number.
+pub fn validate_phone_number(field: &str, phone_number: &str) -> Result<Option<String>, FieldError> {
+    let phone_number = phone_number.trim();
+    if phone_number.is_empty() {
+        return Ok(None);
+    }
+
+    if !phone_number.starts_with('+') {
+        return Err(FieldError::new(
+            field,
+            "INVALID_FORMAT",
+            "Phone number must be in international format, starting with + and the country code",
+        ));
+    }
+
+    let parsed = phonenumber::parse(None, phone_number)
+        .map_err(|_| FieldError::new(field, "INVALID_FORMAT", "Phone number could not be parsed"))?;
+
+    if !parsed.is_valid() {
+        return Err(FieldError::new(field, "INVALID", "Phone number is not valid"));
+    }
+
+    Ok(Some(parsed.format().mode(Mode::E164).to_string()))
+}
+
+/// Trims and length-checks a bio. An empty value clears the bio.
+pub fn validate_bio(field: &str, bio: &str) -> Result<Option<String>, FieldError> {
+    let bio: String = bio.trim().nfc().collect();
+    if bio.is_empty() {
+        return Ok(None);
+    }
+
+    if bio.chars().count() > BIO_MAX_CHARS {
+        return Err(FieldError::new(
+            field,
+            "TOO_LONG",
+            format!("Bio must be at most {} characters", BIO_MAX_CHARS),
+        ));
+    }
+
+    if bio.chars().any(|c| (c.is_control() && c != '\n' && c != '\t') || is_invisible_or_bidi(c)) {
+        return Err(FieldError::new(field, "INVALID_CHARACTERS", "Bio contains invisible or control characters"));
+    }
+
+    Ok(Some(bio))
+}
+
+/// Comparison key for handles: case, underscores and look-alike characters
+/// (`0`/`o`, `1`/`l`, `rn`/`m`) are folded so confusable handles collide.
+/// `i` is folded too, since handles keep their case and `I` passes for `l`.
+pub fn handle_skeleton(handle: &str) -> String {
+    skeleton(&handle.to_lowercase())
+        .collect::<String>()
+        .to_lowercase()
+        .replace('_', "")
+        .replace('i', "l")
+}
+
+/// Checks a handle's format and reserved words; an optional leading `@` is dropped.
+/// Case is kept for display, uniqueness is checked on the skeleton.
+pub fn validate_handle(field: &str, handle: &str) -> Result<String, FieldError> {
+    let handle = handle.trim().trim_start_matches('@');
+    let length = handle.chars().count();
+
+    if !(HANDLE_MIN_CHARS..=HANDLE_MAX_CHARS).contains(&length) {
+        return Err(FieldError::new(
+            field,
+            "INVALID_LENGTH",
+            format!("Handle must be between {} and {} characters", HANDLE_MIN_CHARS, HANDLE_MAX_CHARS),
+        ));
+    }
+
+    if !handle.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(FieldError::new(
+            field,
+            "INVALID_CHARACTERS",
+            "Handle may only contain letters, digits and underscores",
+        ));
+    }
+
+    if !handle.chars().any(|c| c.is_ascii_alphabetic()) {
+        return Err(FieldError::new(field, "INVALID_FORMAT", "Handle must contain at least one letter"));
+    }
+
+    let key = handle_skeleton(handle);
+    if RESERVED_HANDLES.iter().any(|reserved| handle_skeleton(reserved) == key) {
+        return Err(FieldError::new(field, "RESERVED", "This handle is reserved"));
+    }
+
+    Ok(handle.to_string())
+}
+
+pub fn validate_email(field: &str, email: &str) -> Result<String, FieldError> {
+    let email = email.trim().to_lowercase();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(FieldError::new(field, "INVALID_FORMAT", "Invalid email address"));
+    }
+    Ok(email)
+}
+
+pub fn validate_password(field: &str, password: &str) -> Result<(), FieldError> {
+    let length = password.chars().count();
+    if length < PASSWORD_MIN_CHARS {
+        return Err(FieldError::new(
+            field,
+            "TOO_SHORT",
+            format!("Password must be at least {} characters", PASSWORD_MIN_CHARS),
+        ));
+    }
+    if length > PASSWORD_MAX_CHARS {
+        return Err(FieldError::new(
+            field,
+            "TOO_LONG",
+            format!("Password must be at most {} characters", PASSWORD_MAX_CHARS),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name("name", "  Jane   Doe ").unwrap(), "Jane Doe");
+        assert_eq!(validate_name("name", "José Álvarez").unwrap(), "José Álvarez");
+        assert_eq!(validate_name("name", "山田 はなこ").unwrap(), "山田 はなこ");
+
+        assert_eq!(validate_name("name", "   ").unwrap_err().code, "REQUIRED");
+        assert_eq!(validate_name("name", &"a".repeat(101)).unwrap_err().code, "TOO_LONG");
+        assert_eq!(validate_name("name", "Jane\u{202E}eoD").unwrap_err().code, "INVALID_CHARACTERS");
+        // Latin "P" and "al" with Cyrillic "у" and "а"
+        assert_eq!(validate_name("name", "Pаypal").unwrap_err().code, "MIXED_SCRIPT");
+    }
+
+    #[test]
+    fn test_validate_phone_number() {
+        assert_eq!(
+            validate_phone_number("phoneNumber", "+1 (650) 253-0000").unwrap(),
+            Some("+16502530000".to_string())
+        );
+        assert_eq!(
+            validate_phone_number("phoneNumber", "+44 20 7031 3000").unwrap(),
+            Some("+442070313000".to_string())
+        );
+        assert_eq!(validate_phone_number("phoneNumber", "").unwrap(), None);
+
+        assert_eq!(validate_phone_number("phoneNumber", "650 253 0000").unwrap_err().code, "INVALID_FORMAT");
+        assert!(validate_phone_number("phoneNumber", "+1 555").is_err());
+        assert!(validate_phone_number("phoneNumber", &format!("+1{}", "5".repeat(30))).is_err());
+    }
+
+    #[test]
+    fn test_validate_bio() {
+        assert_eq!(validate_bio("bio", "  Hello\nworld  ").unwrap(), Some("Hello\nworld".to_string()));
+        assert_eq!(validate_bio("bio", " ").unwrap(), None);
+        assert_eq!(validate_bio("bio", &"a".repeat(1001)).unwrap_err().code, "TOO_LONG");
+    }
+
+    #[test]
+    fn test_validate_handle() {
+        assert_eq!(validate_handle("handle", "@Jane_Doe").unwrap(), "Jane_Doe");
+        assert_eq!(validate_handle("handle", "user42a").unwrap(), "user42a");
+
+        assert_eq!(validate_handle("handle", "jd").unwrap_err().code, "INVALID_LENGTH");
+        assert_eq!(validate_handle("handle", "jane.doe").unwrap_err().code, "INVALID_CHARACTERS");
+        assert_eq!(validate_handle("handle", "jané").unwrap_err().code, "INVALID_CHARACTERS");
+        assert_eq!(validate_handle("handle", "12345").unwrap_err().code, "INVALID_FORMAT");
+        assert_eq!(validate_handle("handle", "Admin").unwrap_err().code, "RESERVED");
+        assert_eq!(validate_handle("handle", "adm1n").unwrap_err().code, "RESERVED");
+        assert_eq!(validate_handle("handle", "sup_port").unwrap_err().code, "RESERVED");
+    }
+
+    #[test]
+    fn test_handle_skeleton_folds_confusables() {
+        assert_eq!(handle_skeleton("JaneDoe"), handle_skeleton("jane_doe"));
+        assert_eq!(handle_skeleton("b0b"), handle_skeleton("bob"));
+        assert_eq!(handle_skeleton("paul"), handle_skeleton("pau1"));
+        assert_eq!(handle_skeleton("PauI"), handle_skeleton("paul"));
+        assert_eq!(handle_skeleton("modern"), handle_skeleton("modem"));
+        assert_ne!(handle_skeleton("alice"), handle_skeleton("alicia"));
+    }
+
+    #[test]
+    fn test_validator_collects_all_errors() {
+        let mut validator = Validator::new();
+        validator.check(validate_name("name", ""));
+        validator.check(validate_bio("bio", &"a".repeat(1001)));
+        assert_eq!(validator.check(validate_email("email", "A@Example.com")), Some("a@example.com".to_string()));
+
+        let error = validator.finish().unwrap_err();
+        let extensions = serde_json::to_value(error.extensions.unwrap()).unwrap();
+        assert_eq!(extensions["code"], "VALIDATION_FAILED");
+        assert_eq!(extensions["fields"][0]["field"], "name");
+        assert_eq!(extensions["fields"][1]["code"], "TOO_LONG");
+    }
+}
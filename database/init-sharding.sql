@@ -85,33 +85,9 @@ BEGIN
 END;
 $$ language 'plpgsql';
 
--- URL normalization and hashing function
-CREATE OR REPLACE FUNCTION normalize_and_hash_url(input_url TEXT)
-RETURNS TABLE(normalized_url TEXT, url_hash TEXT) AS $$
-DECLARE
-    clean_url TEXT;
-    hash_result TEXT;
-BEGIN
-    -- Basic URL normalization (simplified version)
-    -- Remove trailing slash unless it's root
-    clean_url := regexp_replace(input_url, '/$', '');
-    IF clean_url = '' THEN
-        clean_url := '/';
-    END IF;
-    
-    -- Convert to lowercase
-    clean_url := lower(clean_url);
-    
-    -- Remove common tracking parameters (simplified)
-    clean_url := regexp_replace(clean_url, '[?&](utm_[^&]*|fbclid[^&]*|gclid[^&]*|ref[^&]*)', '', 'g');
-    clean_url := regexp_replace(clean_url, '[?&]$', '');
-    
-    -- Generate SHA256 hash
-    hash_result := encode(digest(clean_url, 'sha256'), 'hex');
-    
-    RETURN QUERY SELECT clean_url, hash_result;
-END;
-$$ language 'plpgsql';
+-- URLs are normalized and hashed by the backend only (backend/url_rules.toml).
+-- After changing the rules, recompute stored hashes with:
+--     votp-backend renormalize
 
 -- Create a function to determine which shard a comment should go to
 CREATE OR REPLACE FUNCTION get_comment_shard(url_hash TEXT)
@@ -72,33 +72,9 @@ CREATE TRIGGER update_comments_updated_at
     BEFORE UPDATE ON comments
     FOR EACH ROW EXECUTE FUNCTION update_comments_updated_at_column();
 
--- URL normalization and hashing function
-CREATE OR REPLACE FUNCTION normalize_and_hash_url(input_url TEXT)
-RETURNS TABLE(normalized_url TEXT, url_hash TEXT) AS $$
-DECLARE
-    clean_url TEXT;
-    hash_result TEXT;
-BEGIN
-    -- Basic URL normalization
-    -- Remove trailing slash unless it's root
-    clean_url := regexp_replace(input_url, '/$', '');
-    IF clean_url = '' THEN
-        clean_url := '/';
-    END IF;
-    
-    -- Convert to lowercase
-    clean_url := lower(clean_url);
-    
-    -- Remove common tracking parameters
-    clean_url := regexp_replace(clean_url, '[?&](utm_[^&]*|fbclid[^&]*|gclid[^&]*|ref[^&]*)', '', 'g');
-    clean_url := regexp_replace(clean_url, '[?&]$', '');
-    
-    -- Generate SHA256 hash
-    hash_result := encode(digest(clean_url, 'sha256'), 'hex');
-    
-    RETURN QUERY SELECT clean_url, hash_result;
-END;
-$$ language 'plpgsql';
+-- URLs are normalized and hashed by the backend only (backend/url_rules.toml).
+-- After changing the rules, recompute stored hashes with:
+--     votp-backend renormalize
 
 -- Insert sample data for testing
 INSERT INTO users (name, email, password_hash, email_verified) VALUES 
@@ -106,21 +82,15 @@ INSERT INTO users (name, email, password_hash, email_verified) VALUES
     ('Demo User', 'demo@example.com', '$argon2id$v=19$m=4096,t=3,p=1$fakehashjustfortesting', true)
 ON CONFLICT (email) DO NOTHING;
 
--- Insert sample comments for testing
-WITH sample_urls AS (
-    SELECT normalize_and_hash_url('https://example.com/article1') as url_data
-    UNION ALL
-    SELECT normalize_and_hash_url('https://news.example.com/story') as url_data
-)
+-- Insert sample comments for testing. normalized_url and url_hash are what
+-- the backend produces for the URL; a backend test keeps them in sync.
 INSERT INTO comments (content, url, normalized_url, url_hash, user_id)
 SELECT 
     'This is a sample comment for testing!',
     'https://example.com/article1',
-    (url_data).normalized_url,
-    (url_data).url_hash,
+    'https://example.com/article1',
+    '4425b8e5140baa803c4c16e21bb5387afc64f3ee484f9e0d3d27772769ac2f86',
     (SELECT id FROM users WHERE email = 'test@example.com' LIMIT 1)
-FROM sample_urls
-WHERE (url_data).normalized_url LIKE '%article1%'
 ON CONFLICT DO NOTHING;
 
 -- Grant permissions
@@ -1,7 +1,14 @@
 // Background Service Worker for VOTP Chrome Extension
 
+importScripts('url-hash.js');
+
 // API Configuration
 const API_BASE_URL = 'http://localhost:8000/api';
+const URL_RULES_URL = new URL('/url-rules.toml', API_BASE_URL).href;
+// Must match HASH_PREFIX_LEN in backend/src/services/comment_counts.rs
+const HASH_PREFIX_LEN = 4;
+// The server lets clients cache the rules for five minutes
+const URL_RULES_MAX_AGE_MS = 5 * 60 * 1000;
 const STORAGE_KEYS = {
   AUTH_TOKEN: 'votp_auth_token',
   USER_DATA: 'votp_user_data',
@@ -91,6 +98,11 @@ chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
       handleGetCurrentUrl(sender, sendResponse);
       return true;
       
+    case 'PAGE_LOADED':
+      handlePageLoaded(sender.tab, message.url);
+      sendResponse({ success: true });
+      return false;
+      
     default:
       console.warn('Unknown message type:', message.type);
       sendResponse({ error: 'Unknown message type' });
@@ -206,6 +218,63 @@ function handleGetCurrentUrl(sender, sendResponse) {
   }
 }
 
+// URL rules from the server, parsed, and when they were fetched
+let urlRules = null;
+let urlRulesFetchedAt = 0;
+
+async function loadUrlRules() {
+  if (urlRules && Date.now() - urlRulesFetchedAt < URL_RULES_MAX_AGE_MS) {
+    return urlRules;
+  }
+  
+  const response = await fetch(URL_RULES_URL);
+  if (!response.ok) {
+    throw new Error(`HTTP ${response.status}`);
+  }
+  urlRules = VotpUrlHash.parseRules(await response.text());
+  urlRulesFetchedAt = Date.now();
+  return urlRules;
+}
+
+// Show the page's comment count on the toolbar badge. Only a short prefix of
+// the page's url_hash is sent, without credentials, so the server can't tell
+// which page is open; the full URL is sent only once the sidebar is opened.
+async function handlePageLoaded(tab, url) {
+  if (!tab || !/^https?:\/\//.test(url || '')) return;
+  
+  try {
+    const urlHash = await VotpUrlHash.urlHash(await loadUrlRules(), url);
+    const response = await fetch(API_BASE_URL, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        query: `
+          query CommentCounts($prefixes: [String!]!) {
+            commentCountsByHashPrefix(prefixes: $prefixes) {
+              urlHash
+              commentCount
+            }
+          }
+        `,
+        variables: { prefixes: [urlHash.slice(0, HASH_PREFIX_LEN)] }
+      })
+    });
+    
+    const data = await response.json();
+    const counts = data.data?.commentCountsByHashPrefix || [];
+    const count = counts.find(entry => entry.urlHash === urlHash)?.commentCount || 0;
+    
+    chrome.action.setBadgeText({
+      tabId: tab.id,
+      text: count === 0 ? '' : count > 999 ? '999+' : String(count)
+    }).catch(() => {
+      // The tab may have been closed meanwhile
+    });
+  } catch (error) {
+    console.warn('Could not look up comment count:', error.message);
+  }
+}
+
 // Tab update handler - reset sidebar state when navigating
 chrome.tabs.onUpdated.addListener(async (tabId, changeInfo, tab) => {
   if (changeInfo.status === 'complete' && tab.url) {
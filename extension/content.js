@@ -48,6 +48,21 @@
     }
   }
 
+  // Canonical URL the page declares for itself, if any, resolved against the page URL
+  function getCanonicalUrl() {
+    const link = document.querySelector('link[rel~="canonical"][href]');
+    const ogUrl = document.querySelector('meta[property="og:url"][content]');
+    const declared = link ? link.getAttribute('href') : ogUrl ? ogUrl.getAttribute('content') : null;
+    if (!declared) return null;
+    
+    try {
+      const canonical = new URL(declared, window.location.href);
+      return /^https?:$/.test(canonical.protocol) ? canonical.href : null;
+    } catch (error) {
+      return null;
+    }
+  }
+
   // Initialize the extension
   function initialize() {
     if (isInitialized) return;
@@ -72,6 +87,15 @@
       }
     }
     
+    // Let the background worker look up this page's comment count by hash prefix
+    try {
+      chrome.runtime.sendMessage({ type: 'PAGE_LOADED', url: window.location.href }, () => {
+        void chrome.runtime.lastError;
+      });
+    } catch (error) {
+      console.warn('VOTP: Could not request comment count:', error.message);
+    }
+    
     // Listen for window resize
     window.addEventListener('resize', handleWindowResize);
     
@@ -217,6 +241,7 @@
           sidebarIframe.contentWindow.postMessage({
             type: 'CURRENT_URL',
             url: window.location.href,
+            canonicalUrl: getCanonicalUrl(),
             title: document.title
           }, '*');
           break;
@@ -337,6 +362,7 @@
           sidebarIframe.contentWindow.postMessage({
             type: 'SIDEBAR_VISIBLE',
             url: window.location.href,
+            canonicalUrl: getCanonicalUrl(),
             title: document.title
           }, '*');
         } catch (error) {
@@ -10,6 +10,11 @@
         user: null,
         token: null,
         currentUrl: null,
+        canonicalUrl: null,
+        canonicalReportedFor: null,
+        // Comments are only fetched once the sidebar has been opened, so
+        // the page URL isn't sent on every page load
+        isVisible: false,
         pageTitle: null,
         comments: [],
         isLoading: false
@@ -177,6 +182,9 @@
         elements.commentsLoading = document.getElementById('comments-loading');
         elements.commentsEmpty = document.getElementById('comments-empty');
         elements.commentsError = document.getElementById('comments-error');
+        elements.siteDiscussion = document.getElementById('site-discussion');
+        elements.siteDomain = document.getElementById('site-domain');
+        elements.sitePages = document.getElementById('site-pages');
         
         // Toast container
         elements.toastContainer = document.getElementById('toast-container');
@@ -292,7 +300,9 @@
     
     // Handle sidebar visible event
     function handleSidebarVisible(data) {
+        appState.isVisible = true;
         appState.currentUrl = data.url;
+        appState.canonicalUrl = data.canonicalUrl || null;
         appState.pageTitle = data.title;
         updatePageInfo();
         
@@ -304,6 +314,7 @@
     // Handle current URL response
     function handleCurrentUrl(data) {
         appState.currentUrl = data.url;
+        appState.canonicalUrl = data.canonicalUrl || null;
         appState.pageTitle = data.title;
         updatePageInfo();
     }
@@ -376,7 +387,9 @@
         elements.authSection?.classList.add('hidden');
         elements.userSection?.classList.remove('hidden');
         updateUserDisplay();
-        loadComments();
+        if (appState.isVisible) {
+            loadComments();
+        }
     }
     
     // Show email form
@@ -481,6 +494,8 @@
                 query: `
                     mutation Login($email: String!, $password: String!) {
                         login(email: $email, password: $password) {
+                            mfaRequired
+                            mfaChallenge
                             token
                             user {
                                 id
@@ -501,11 +516,16 @@
                 throw new Error(response.error);
             }
             
-            const loginData = response.data?.data?.login;
+            let loginData = response.data?.data?.login;
             if (!loginData) {
                 throw new Error('Invalid response from server');
             }
             
+            // Accounts with two-factor authentication need a second step
+            if (loginData.mfaRequired) {
+                loginData = await completeMfaLogin(loginData.mfaChallenge);
+            }
+            
             // Store authentication data
             appState.isAuthenticated = true;
             appState.user = loginData.user;
@@ -534,6 +554,45 @@
         }
     }
     
+    // Exchange an MFA challenge and authenticator/recovery code for a session
+    async function completeMfaLogin(challenge) {
+        const code = window.prompt('Enter the 6-digit code from your authenticator app, or a recovery code:');
+        if (!code) {
+            throw new Error('Two-factor authentication code required');
+        }
+        
+        const response = await makeApiRequest({
+            query: `
+                mutation CompleteLogin($challenge: String!, $code: String!) {
+                    completeLogin(challenge: $challenge, code: $code) {
+                        token
+                        user {
+                            id
+                            name
+                            email
+                            phoneNumber
+                            bio
+                            createdAt
+                            updatedAt
+                        }
+                    }
+                }
+            `,
+            variables: { challenge, code: code.trim() }
+        });
+        
+        if (response.error) {
+            throw new Error(response.error);
+        }
+        
+        const loginData = response.data?.data?.completeLogin;
+        if (!loginData) {
+            throw new Error(response.data?.errors?.[0]?.message || 'Invalid authentication code');
+        }
+        
+        return loginData;
+    }
+    
     // Handle send verification code
     async function handleSendVerificationCode() {
         const email = elements.signupEmail?.textContent;
@@ -726,6 +785,11 @@
                             parentId
                             createdAt
                             updatedAt
+                            author {
+                                name
+                                handle
+                                avatarUrl(size: 32)
+                            }
                         }
                     }
                 `,
@@ -812,11 +876,13 @@
     
     // Load comments for current page
     async function loadComments() {
-        if (!appState.currentUrl || !appState.isAuthenticated) return;
+        if (!appState.currentUrl || !appState.isAuthenticated || !appState.isVisible) return;
         
         showCommentsLoading(true);
         
         try {
+            await reportCanonicalUrl();
+            
             const response = await makeApiRequest({
                 query: `
                     query CommentsForUrl($url: String!) {
@@ -829,6 +895,12 @@
                             parentId
                             createdAt
                             updatedAt
+                            collapsed
+                            author {
+                                name
+                                handle
+                                avatarUrl(size: 32)
+                            }
                         }
                     }
                 `,
@@ -849,6 +921,7 @@
             appState.comments = comments;
             
             displayComments(comments);
+            loadSiteDiscussion();
             
         } catch (error) {
             console.error('Error loading comments:', error);
@@ -863,6 +936,72 @@
         }
     }
     
+    // Tell the server which URL the page declares as canonical, once per page,
+    // so this page's comments join that URL's thread
+    async function reportCanonicalUrl() {
+        const { currentUrl, canonicalUrl } = appState;
+        if (!canonicalUrl || canonicalUrl === currentUrl || appState.canonicalReportedFor === currentUrl) return;
+        appState.canonicalReportedFor = currentUrl;
+        
+        try {
+            await makeApiRequest({
+                query: `
+                    mutation ReportCanonical($url: String!, $canonicalUrl: String!) {
+                        reportCanonical(url: $url, canonicalUrl: $canonicalUrl) {
+                            canonicalUrl
+                        }
+                    }
+                `,
+                variables: { url: currentUrl, canonicalUrl },
+                requireAuth: true
+            });
+        } catch (error) {
+            console.error('Error reporting canonical URL:', error);
+        }
+    }
+    
+    // Load other commented pages on the same site
+    async function loadSiteDiscussion() {
+        if (!elements.siteDiscussion) return;
+        
+        try {
+            const response = await makeApiRequest({
+                query: `
+                    query SiteDiscussion($url: String!) {
+                        domainActivity(domain: $url) {
+                            domain
+                        }
+                        pagesForDomain(domain: $url, sort: RECENT, excludeUrl: $url, limit: 5) {
+                            normalizedUrl
+                            commentCount
+                        }
+                    }
+                `,
+                variables: { url: appState.currentUrl },
+                requireAuth: true
+            });
+            
+            const data = response.data?.data;
+            const pages = (data?.pagesForDomain || []).filter(page => /^https?:\/\//.test(page.normalizedUrl));
+            if (response.error || pages.length === 0) {
+                elements.siteDiscussion.classList.add('hidden');
+                return;
+            }
+            
+            elements.siteDomain.textContent = data.domainActivity.domain;
+            elements.sitePages.innerHTML = pages.map(page => `
+                <li class="site-page">
+                    <a href="${escapeHtml(page.normalizedUrl).replace(/"/g, '&quot;')}" target="_blank" rel="noopener noreferrer">${escapeHtml(page.normalizedUrl)}</a>
+                    <span class="site-page-count">${page.commentCount} ${page.commentCount === 1 ? 'comment' : 'comments'}</span>
+                </li>
+            `).join('');
+            elements.siteDiscussion.classList.remove('hidden');
+        } catch (error) {
+            console.error('Error loading site discussion:', error);
+            elements.siteDiscussion.classList.add('hidden');
+        }
+    }
+    
     // Refresh comments
     function refreshComments() {
         loadComments();
@@ -902,17 +1041,18 @@
         const date = new Date(comment.createdAt);
         const timeAgo = getTimeAgo(date);
         
-        // For now, we'll use the user ID as the author name since we don't have user lookup
-        // In a real implementation, you'd want to fetch user details
-        const authorName = `User ${comment.userId.substring(0, 8)}`;
+        const authorName = comment.author?.name || `User ${comment.userId.substring(0, 8)}`;
         const authorInitial = authorName.charAt(0);
+        const avatar = comment.author?.avatarUrl
+            ? `<img class="comment-avatar" src="${escapeHtml(comment.author.avatarUrl)}" alt="">`
+            : `<div class="comment-avatar">${escapeHtml(authorInitial)}</div>`;
         
         return `
             <div class="comment-item" data-comment-id="${comment.id}">
                 <div class="comment-header">
-                    <div class="comment-avatar">${authorInitial}</div>
+                    ${avatar}
                     <div class="comment-meta">
-                        <div class="comment-author">${authorName}</div>
+                        <div class="comment-author" title="${comment.author ? '@' + escapeHtml(comment.author.handle) : ''}">${escapeHtml(authorName)}</div>
                         <div class="comment-time">${timeAgo}</div>
                     </div>
                     <div class="comment-actions">
@@ -931,7 +1071,12 @@
                         ` : ''}
                     </div>
                 </div>
-                <div class="comment-content">${escapeHtml(comment.content)}</div>
+                ${comment.collapsed ? `
+                    <details class="comment-collapsed">
+                        <summary>Comment from a muted user</summary>
+                        <div class="comment-content">${escapeHtml(comment.content)}</div>
+                    </details>
+                ` : `<div class="comment-content">${escapeHtml(comment.content)}</div>`}
             </div>
         `;
     }
@@ -0,0 +1,33 @@
+// Checks url-hash.js against the server: every URL in url-hash-corpus.json
+// must hash as backend/src/utils.rs computes it under the bundled rules.
+// The Rust test suite checks the same file, so the expected hashes can't
+// drift from the server. Run with `node --test extension/tests`.
+
+const assert = require('node:assert');
+const fs = require('node:fs');
+const path = require('node:path');
+const test = require('node:test');
+
+const root = path.join(__dirname, '..', '..');
+const VotpUrlHash = new Function(`${fs.readFileSync(path.join(root, 'extension', 'url-hash.js'), 'utf8')}\nreturn VotpUrlHash;`)();
+const rules = VotpUrlHash.parseRules(fs.readFileSync(path.join(root, 'backend', 'url_rules.toml'), 'utf8'));
+const corpus = JSON.parse(fs.readFileSync(path.join(__dirname, 'url-hash-corpus.json'), 'utf8'));
+
+test('url hashes match the server', async () => {
+  for (const { url, url_hash: expected, needs_public_suffix_list: needsPsl } of corpus) {
+    if (needsPsl) {
+      continue;
+    }
+    assert.strictEqual(await VotpUrlHash.urlHash(rules, url), expected, url);
+  }
+});
+
+// Hosts one label above a multi-label public suffix are where the missing
+// Public Suffix List shows; keep them listed so a fix is noticed
+test('known differences still differ', async () => {
+  for (const { url, url_hash: expected, needs_public_suffix_list: needsPsl } of corpus) {
+    if (needsPsl) {
+      assert.notStrictEqual(await VotpUrlHash.urlHash(rules, url), expected, `${url} now matches; drop needs_public_suffix_list`);
+    }
+  }
+});
@@ -0,0 +1,429 @@
+// URL canonicalization and hashing for VOTP, following the rules the server
+// publishes at /url-rules.toml. This mirrors backend/src/url_rules.rs so the
+// extension can compute a page's url_hash and look up its comment count by
+// hash prefix without sending the URL.
+//
+// The one known difference: there is no Public Suffix List here, so the last
+// two labels of a host are taken as its registrable domain. Hosts directly
+// under a multi-label suffix (www.co.uk, www.github.io) can hash differently
+// from the server, which only means no count is found for them.
+//
+// tests/url-hash.test.js checks this against the server's hashes for a shared
+// corpus of URLs (tests/url-hash-corpus.json); add cases there when the rules
+// or the normalizer change.
+
+const VotpUrlHash = (() => {
+  // ISO 639-1 language codes, the only labels treated as language subdomains
+  const ISO_639_1 = new Set([
+    'aa', 'ab', 'ae', 'af', 'ak', 'am', 'an', 'ar', 'as', 'av', 'ay', 'az', 'ba', 'be', 'bg', 'bh', 'bi', 'bm',
+    'bn', 'bo', 'br', 'bs', 'ca', 'ce', 'ch', 'co', 'cr', 'cs', 'cu', 'cv', 'cy', 'da', 'de', 'dv', 'dz', 'ee',
+    'el', 'en', 'eo', 'es', 'et', 'eu', 'fa', 'ff', 'fi', 'fj', 'fo', 'fr', 'fy', 'ga', 'gd', 'gl', 'gn', 'gu',
+    'gv', 'ha', 'he', 'hi', 'ho', 'hr', 'ht', 'hu', 'hy', 'hz', 'ia', 'id', 'ie', 'ig', 'ii', 'ik', 'io', 'is',
+    'it', 'iu', 'ja', 'jv', 'ka', 'kg', 'ki', 'kj', 'kk', 'kl', 'km', 'kn', 'ko', 'kr', 'ks', 'ku', 'kv', 'kw',
+    'ky', 'la', 'lb', 'lg', 'li', 'ln', 'lo', 'lt', 'lu', 'lv', 'mg', 'mh', 'mi', 'mk', 'ml', 'mn', 'mr', 'ms',
+    'mt', 'my', 'na', 'nb', 'nd', 'ne', 'ng', 'nl', 'nn', 'no', 'nr', 'nv', 'ny', 'oc', 'oj', 'om', 'or', 'os',
+    'pa', 'pi', 'pl', 'ps', 'pt', 'qu', 'rm', 'rn', 'ro', 'ru', 'rw', 'sa', 'sc', 'sd', 'se', 'sg', 'si', 'sk',
+    'sl', 'sm', 'sn', 'so', 'sq', 'sr', 'ss', 'st', 'su', 'sv', 'sw', 'ta', 'te', 'tg', 'th', 'ti', 'tk', 'tl',
+    'tn', 'to', 'tr', 'ts', 'tt', 'tw', 'ty', 'ug', 'uk', 'ur', 'uz', 've', 'vi', 'vo', 'wa', 'wo', 'xh', 'yi',
+    'yo', 'za', 'zh', 'zu'
+  ]);
+
+  // Rewrites that produce another rewritable URL are followed at most this many times
+  const MAX_REWRITES = 4;
+
+  const RULE_KEYS = new Set([
+    'name', 'domains', 'drop_params', 'keep_params', 'rewrite', 'lowercase_path', 'fragment',
+    'fold_www', 'fold_mobile', 'strip_language_subdomain'
+  ]);
+
+  // Parses the subset of TOML the rules file uses: [defaults] and [[rule]]
+  // tables holding strings, booleans, arrays and inline tables
+  function parseToml(source) {
+    let pos = 0;
+    const fail = (message) => {
+      throw new Error(`url-rules.toml: ${message} at offset ${pos}`);
+    };
+    const skip = (newlines) => {
+      while (pos < source.length) {
+        const c = source[pos];
+        if (c === '#') {
+          while (pos < source.length && source[pos] !== '\n') pos++;
+        } else if (c === ' ' || c === '\t' || c === '\r' || (newlines && c === '\n')) {
+          pos++;
+        } else {
+          break;
+        }
+      }
+    };
+    const key = () => {
+      const match = /^[A-Za-z0-9_-]+/.exec(source.slice(pos));
+      if (!match) fail('expected a key');
+      pos += match[0].length;
+      return match[0];
+    };
+    const string = () => {
+      const quote = source[pos++];
+      let value = '';
+      while (pos < source.length && source[pos] !== quote) {
+        if (source[pos] === '\n') fail('unterminated string');
+        if (quote === '"' && source[pos] === '\\') {
+          const escape = source[pos + 1];
+          const simple = { '"': '"', '\\': '\\', 'n': '\n', 't': '\t', 'r': '\r', 'b': '\b', 'f': '\f' };
+          if (escape in simple) {
+            value += simple[escape];
+            pos += 2;
+          } else if (escape === 'u' || escape === 'U') {
+            const length = escape === 'u' ? 4 : 8;
+            const hex = source.slice(pos + 2, pos + 2 + length);
+            if (!new RegExp(`^[0-9A-Fa-f]{${length}}$`).test(hex)) fail('invalid unicode escape');
+            value += String.fromCodePoint(parseInt(hex, 16));
+            pos += 2 + length;
+          } else {
+            fail('invalid escape');
+          }
+        } else {
+          value += source[pos++];
+        }
+      }
+      if (pos >= source.length) fail('unterminated string');
+      pos++;
+      return value;
+    };
+    const value = () => {
+      const c = source[pos];
+      if (c === '"' || c === "'") return string();
+      if (source.startsWith('true', pos)) {
+        pos += 4;
+        return true;
+      }
+      if (source.startsWith('false', pos)) {
+        pos += 5;
+        return false;
+      }
+      if (c === '[') {
+        pos++;
+        const items = [];
+        skip(true);
+        while (source[pos] !== ']') {
+          items.push(value());
+          skip(true);
+          if (source[pos] === ',') {
+            pos++;
+            skip(true);
+          } else if (source[pos] !== ']') {
+            fail('expected , or ]');
+          }
+        }
+        pos++;
+        return items;
+      }
+      if (c === '{') {
+        pos++;
+        const table = {};
+        skip(false);
+        while (source[pos] !== '}') {
+          const name = key();
+          skip(false);
+          if (source[pos++] !== '=') fail('expected =');
+          skip(false);
+          if (name in table) fail(`duplicate key ${name}`);
+          table[name] = value();
+          skip(false);
+          if (source[pos] === ',') {
+            pos++;
+            skip(false);
+          } else if (source[pos] !== '}') {
+            fail('expected , or }');
+          }
+        }
+        pos++;
+        return table;
+      }
+      fail('unsupported value');
+    };
+
+    const document = { defaults: {}, rule: [] };
+    let table = null;
+    for (;;) {
+      skip(true);
+      if (pos >= source.length) break;
+      if (source.startsWith('[[', pos)) {
+        pos += 2;
+        if (key() !== 'rule' || !source.startsWith(']]', pos)) fail('unknown table');
+        pos += 2;
+        table = {};
+        document.rule.push(table);
+      } else if (source[pos] === '[') {
+        pos++;
+        if (key() !== 'defaults' || source[pos] !== ']') fail('unknown table');
+        pos++;
+        table = document.defaults;
+      } else {
+        const name = key();
+        skip(false);
+        if (source[pos++] !== '=') fail('expected =');
+        skip(false);
+        if (!table) fail('key outside a table');
+        if (name in table) fail(`duplicate key ${name}`);
+        table[name] = value();
+      }
+      skip(false);
+      if (pos < source.length && source[pos] !== '\n') fail('expected end of line');
+    }
+    return document;
+  }
+
+  // Query parameter name, or prefix when written as `prefix_*`; compared case-insensitively
+  function paramPattern(pattern) {
+    pattern = pattern.toLowerCase();
+    return pattern.endsWith('*')
+      ? (name) => name.toLowerCase().startsWith(pattern.slice(0, -1))
+      : (name) => name.toLowerCase() === pattern;
+  }
+
+  function compileRule(spec, name) {
+    for (const key of Object.keys(spec)) {
+      if (!RULE_KEYS.has(key)) throw new Error(`rule ${name}: unknown key ${key}`);
+    }
+    if (spec.fragment !== undefined && !['drop', 'keep', 'routes'].includes(spec.fragment)) {
+      throw new Error(`rule ${name}: unknown fragment mode ${spec.fragment}`);
+    }
+    return {
+      name,
+      // Hosts are matched in their ASCII (punycode) form, as URL stores them
+      domains: (spec.domains || []).map((domain) => {
+        domain = domain.trim().replace(/\.+$/, '');
+        return new URL(`http://${domain}/`).hostname;
+      }),
+      dropParams: (spec.drop_params || []).map(paramPattern),
+      keepParams: spec.keep_params ? spec.keep_params.map(paramPattern) : null,
+      rewrites: (spec.rewrite || []).map((rewrite) => ({ path: new RegExp(rewrite.path, 'u'), to: rewrite.to })),
+      lowercasePath: spec.lowercase_path,
+      fragment: spec.fragment,
+      foldWww: spec.fold_www,
+      foldMobile: spec.fold_mobile,
+      stripLanguageSubdomain: spec.strip_language_subdomain
+    };
+  }
+
+  // Parses and compiles a rules file as served at /url-rules.toml
+  function parseRules(source) {
+    const file = parseToml(source);
+    return {
+      defaults: compileRule(file.defaults, 'defaults'),
+      rules: file.rule.map((spec, index) => compileRule(spec, spec.name || `#${index + 1}`))
+    };
+  }
+
+  // A rule for `example.com` covers that host and all of its subdomains
+  function ruleMatches(rule, host) {
+    return rule.domains.some((domain) => host === domain || host.endsWith(`.${domain}`));
+  }
+
+  // Effective settings for one host after layering every matching rule over the defaults
+  function settingsFor(rules, host) {
+    const matching = rules.rules.filter((rule) => ruleMatches(rule, host));
+    const layered = (pick, fallback) => {
+      for (const rule of [...matching].reverse()) {
+        if (rule[pick] !== undefined) return rule[pick];
+      }
+      return rules.defaults[pick] !== undefined ? rules.defaults[pick] : fallback;
+    };
+    return {
+      rules: matching,
+      lowercasePath: layered('lowercasePath', false),
+      foldWww: layered('foldWww', true),
+      foldMobile: layered('foldMobile', false),
+      stripLanguageSubdomain: layered('stripLanguageSubdomain', false),
+      fragment: layered('fragment', 'drop')
+    };
+  }
+
+  // A keep_params list drops everything it doesn't name; otherwise the drop_params lists add up
+  function isDropped(rules, settings, name) {
+    const keeping = [...settings.rules].reverse().find((rule) => rule.keepParams);
+    if (keeping) return !keeping.keepParams.some((matches) => matches(name));
+    return [rules.defaults, ...settings.rules].some((rule) => rule.dropParams.some((matches) => matches(name)));
+  }
+
+  function percentDecode(text) {
+    const bytes = [];
+    for (let i = 0; i < text.length; i++) {
+      const escaped = /^%[0-9A-Fa-f]{2}/.test(text.slice(i, i + 3));
+      if (escaped) {
+        bytes.push(parseInt(text.slice(i + 1, i + 3), 16));
+        i += 2;
+      } else {
+        bytes.push(...new TextEncoder().encode(text[i]));
+      }
+    }
+    return new TextDecoder().decode(new Uint8Array(bytes));
+  }
+
+  // Decoded name of a `name=value` query or path parameter, for matching against rules
+  function paramName(param) {
+    return percentDecode(param.split('=')[0].replace(/\+/g, ' '));
+  }
+
+  const isUnreserved = (byte) => /[A-Za-z0-9\-._~]/.test(String.fromCharCode(byte));
+  const isReserved = (byte) => /[:/?#[\]@!$&'()*+,;=]/.test(String.fromCharCode(byte));
+  const hexByte = (byte) => `%${byte.toString(16).toUpperCase().padStart(2, '0')}`;
+
+  // Percent-encoding normalization of a path, query or fragment (RFC 3986
+  // section 6.2.2), as normalize_percent_encoding does on the server
+  function normalizePercentEncoding(component) {
+    const bytes = new TextEncoder().encode(component);
+    let normalized = '';
+    for (let i = 0; i < bytes.length; i++) {
+      const byte = bytes[i];
+      const hex = String.fromCharCode(...bytes.slice(i + 1, i + 3));
+      if (byte === 0x25 && /^[0-9A-Fa-f]{2}$/.test(hex)) {
+        const decoded = parseInt(hex, 16);
+        normalized += isUnreserved(decoded) ? String.fromCharCode(decoded) : hexByte(decoded);
+        i += 2;
+      } else if (byte < 0x80 && (isUnreserved(byte) || isReserved(byte))) {
+        normalized += String.fromCharCode(byte);
+      } else {
+        normalized += hexByte(byte);
+      }
+    }
+    return normalized;
+  }
+
+  // Drops session parameters from path segments and trailing slashes, and
+  // normalizes percent-encoding
+  function cleanPath(rules, path, settings) {
+    path = path
+      .split('/')
+      .map((segment) => {
+        const [kept, ...params] = segment.split(';');
+        return [kept, ...params.filter((param) => !isDropped(rules, settings, paramName(param)))].join(';');
+      })
+      .join('/');
+    while (path.length > 1 && path.endsWith('/')) {
+      path = path.slice(0, -1);
+    }
+    return normalizePercentEncoding(path);
+  }
+
+  // Expands $1, $name and ${name} in a rewrite target, as the regex crate does
+  function expand(template, match) {
+    return template.replace(/\$(\$|\{([A-Za-z0-9_]+)\}|([A-Za-z0-9_]+))/g, (_, all, braced, bare) => {
+      if (all === '$') return '$';
+      const name = braced || bare;
+      const group = /^\d+$/.test(name) ? match[Number(name)] : (match.groups || {})[name];
+      return group || '';
+    });
+  }
+
+  // Applies the first rewrite of a matching rule whose pattern matches the path
+  function rewrite(url, settings) {
+    for (const rule of settings.rules) {
+      for (const { path, to } of rule.rewrites) {
+        const match = path.exec(url.pathname);
+        if (!match) continue;
+        const target = expand(to, match);
+
+        let rewritten;
+        if (target.startsWith('/')) {
+          rewritten = new URL(url.href);
+          rewritten.pathname = target;
+        } else {
+          rewritten = new URL(target);
+          const query = rewritten.search.slice(1);
+          const existing = query.split('&').map(paramName);
+          const carried = url.search.slice(1)
+            .split('&')
+            .filter((param) => param && !existing.includes(paramName(param)));
+          if (carried.length > 0) {
+            rewritten.search = `?${[query, ...carried].filter(Boolean).join('&')}`;
+          }
+          if (!rewritten.hash) {
+            rewritten.hash = url.hash;
+          }
+        }
+
+        if (rewritten.href !== url.href) return rewritten;
+      }
+    }
+    return null;
+  }
+
+  // Registrable domain without the Public Suffix List: the last two labels
+  function registrableDomain(host) {
+    if (host.startsWith('[') || /^\d+(\.\d+){3}$/.test(host)) return host;
+    return host.split('.').slice(-2).join('.');
+  }
+
+  // Folds away www, mobile and language labels above the registrable domain
+  function foldHost(host, settings) {
+    host = host.replace(/\.+$/, '');
+    const domain = registrableDomain(host);
+    if (!host.endsWith(`.${domain}`)) return host;
+
+    const labels = host.slice(0, -domain.length - 1).split('.');
+    if (settings.foldWww && labels[0] === 'www') {
+      labels.shift();
+    }
+    // A mobile label leads the subdomain, or follows a language label
+    const mobileAt = ISO_639_1.has(labels[0]) ? 1 : 0;
+    if (settings.foldMobile && (labels[mobileAt] === 'm' || labels[mobileAt] === 'mobile')) {
+      labels.splice(mobileAt, 1);
+    }
+    if (settings.stripLanguageSubdomain && ISO_639_1.has(labels[0])) {
+      labels.shift();
+    }
+
+    return [...labels, domain].join('.');
+  }
+
+  // Canonical form of a URL under the given rules, as a string
+  function canonicalize(rules, input) {
+    let url = new URL(input.trim());
+
+    // Rewrite patterns see the path in its normalized form
+    url.pathname = cleanPath(rules, url.pathname, settingsFor(rules, url.hostname));
+
+    for (let i = 0; i < MAX_REWRITES; i++) {
+      const rewritten = rewrite(url, settingsFor(rules, url.hostname.toLowerCase()));
+      if (!rewritten) break;
+      url = rewritten;
+    }
+
+    const settings = settingsFor(rules, url.hostname.toLowerCase());
+    if (url.hostname) {
+      url.hostname = foldHost(url.hostname.toLowerCase(), settings);
+    }
+
+    let path = cleanPath(rules, url.pathname, settings);
+    if (settings.lowercasePath) {
+      path = path.toLowerCase();
+    }
+    url.pathname = normalizePercentEncoding(path);
+
+    // Drop tracking and session parameters, then sort by name
+    const params = url.search.slice(1)
+      .split('&')
+      .filter((param) => param && !isDropped(rules, settings, paramName(param)))
+      .map(normalizePercentEncoding);
+    const sortKey = (param) => param.split('=')[0];
+    params.sort((a, b) => (sortKey(a) < sortKey(b) ? -1 : sortKey(a) > sortKey(b) ? 1 : 0));
+    url.search = params.length > 0 ? `?${params.join('&')}` : '';
+
+    const fragment = normalizePercentEncoding(url.hash.slice(1));
+    const keep = settings.fragment === 'keep'
+      ? fragment !== ''
+      : settings.fragment === 'routes' && (fragment.startsWith('/') || fragment.startsWith('!'));
+    url.hash = keep ? `#${fragment}` : '';
+
+    return url.href;
+  }
+
+  // url_hash of a URL: the hex SHA-256 of its canonical form
+  async function urlHash(rules, input) {
+    const digest = await crypto.subtle.digest('SHA-256', new TextEncoder().encode(canonicalize(rules, input)));
+    return Array.from(new Uint8Array(digest), (byte) => byte.toString(16).padStart(2, '0')).join('');
+  }
+
+  return { parseRules, canonicalize, urlHash };
+})();